@@ -0,0 +1,53 @@
+//! Counts the frames a frame sink is handed over one emulated second,
+//! starting from the first. Timed from power-on instead, the second would
+//! hold only 59: the first frame is not done until 144 lines in.
+//!
+//! cargo run --example frame_sink -- [game.gb]
+//!
+//! Without a game, a ROM that loops in place is run.
+
+use std::cell::Cell;
+use std::rc::Rc;
+
+use game_boy_dmg_emulator::cpu::CpuError;
+use game_boy_dmg_emulator::emulator::Emulator;
+use game_boy_dmg_emulator::ppu::DOTS_PER_FRAME;
+
+const CYCLES_PER_SECOND: u32 = 4_194_304;
+
+/// Runs for up to `cycles` T-cycles, stopping early once `done` holds.
+fn run(emulator: &mut Emulator, cycles: u32, done: impl Fn() -> bool) -> Result<(), CpuError> {
+    let mut elapsed = 0;
+    while elapsed < cycles && !done() {
+        elapsed += emulator.step()? as u32;
+    }
+    Ok(())
+}
+
+fn main() {
+    let mut emulator = Emulator::new();
+    let loaded = match std::env::args().nth(1) {
+        Some(rom) => emulator.load_rom(&rom),
+        None => {
+            let mut rom = vec![0; 0x8000];
+            rom[0x0100..0x0102].copy_from_slice(&[0x18, 0xFE]); // JR -2
+            emulator.load_rom_bytes(&rom)
+        }
+    };
+    if let Err(error) = loaded {
+        eprintln!("{}", error);
+        return;
+    }
+
+    let frames = Rc::new(Cell::new(0));
+    let sink_frames = Rc::clone(&frames);
+    emulator.set_frame_sink(Box::new(move |_| sink_frames.set(sink_frames.get() + 1)));
+
+    let first_frame = run(&mut emulator, CYCLES_PER_SECOND, || frames.get() > 0);
+    if let Err(error) = first_frame.and_then(|_| run(&mut emulator, CYCLES_PER_SECOND, || false)) {
+        eprintln!("{}", error);
+        return;
+    }
+    let rate = CYCLES_PER_SECOND as f64 / DOTS_PER_FRAME as f64;
+    println!("{} frames in one second, at {:.1} ({:.0}) frames per second", frames.get(), rate, rate);
+}
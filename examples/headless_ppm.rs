@@ -0,0 +1,43 @@
+//! Runs a game for a number of frames without a window, then writes the
+//! last frame out as a PPM image.
+//!
+//! cargo run --example headless_ppm -- game.gb [frames] [out.ppm]
+
+use game_boy_dmg_emulator::emulator::Emulator;
+use game_boy_dmg_emulator::ppu::DOTS_PER_FRAME;
+
+fn main() {
+    let mut args = std::env::args().skip(1);
+    let Some(rom) = args.next() else {
+        eprintln!("usage: headless_ppm <rom> [frames] [out.ppm]");
+        return;
+    };
+    let frames: u64 = args.next().map_or(60, |frames| frames.parse().expect("frames must be a number"));
+    let out = args.next().unwrap_or_else(|| "frame.ppm".to_string());
+
+    let mut emulator = Emulator::new();
+    if let Err(error) = emulator.load_rom(&rom) {
+        eprintln!("{}", error);
+        return;
+    }
+    // A frame's worth of cycles at a time, so a game that keeps the LCD
+    // off still gets somewhere.
+    for _ in 0..frames {
+        let mut cycles = 0u32;
+        while cycles < DOTS_PER_FRAME {
+            match emulator.step() {
+                Ok(step) => cycles += step as u32,
+                Err(error) => {
+                    eprintln!("{}", error);
+                    return;
+                }
+            }
+        }
+    }
+
+    if let Err(error) = std::fs::write(&out, emulator.frame_ppm()) {
+        eprintln!("could not write {}: {}", out, error);
+        return;
+    }
+    println!("frame {} written to {}", emulator.frame_count(), out);
+}
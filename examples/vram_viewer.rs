@@ -0,0 +1,89 @@
+//! Runs a game for a number of frames, then writes the tiles in VRAM and
+//! the background tile map out as PPM images, in grays from white to
+//! black. The part of the map on screen is outlined in red.
+//!
+//! cargo run --example vram_viewer -- game.gb [frames] [tiles.ppm] [map.ppm]
+
+use game_boy_dmg_emulator::emulator::{Emulator, Palette};
+use game_boy_dmg_emulator::ppu::{
+    TileMap, DOTS_PER_FRAME, SCREEN_HEIGHT, SCREEN_WIDTH, TILE_MAP_SIZE, TILE_SHEET_HEIGHT, TILE_SHEET_WIDTH,
+};
+
+const OUTLINE: [u8; 3] = [0xFF, 0x00, 0x00];
+
+/// A binary PPM of an image of color numbers or shades.
+fn ppm(image: &[u8], width: usize, height: usize) -> Vec<u8> {
+    let mut ppm = format!("P6\n{} {}\n255\n", width, height).into_bytes();
+    for &shade in image {
+        ppm.extend_from_slice(&Palette::GRAYSCALE.rgba(shade)[..3]);
+    }
+    ppm
+}
+
+/// Outlines in `ppm` of the tile map picture the screen-sized view starting
+/// at `(left, top)`, wrapping around the edges.
+fn outline_viewport(ppm: &mut [u8], (left, top): (u8, u8)) {
+    let header = ppm.len() - TILE_MAP_SIZE * TILE_MAP_SIZE * 3;
+    let mut mark = |x: usize, y: usize| {
+        let pixel = header + (y % TILE_MAP_SIZE * TILE_MAP_SIZE + x % TILE_MAP_SIZE) * 3;
+        ppm[pixel..pixel + 3].copy_from_slice(&OUTLINE);
+    };
+    let (left, top) = (left as usize, top as usize);
+    for x in left..left + SCREEN_WIDTH {
+        mark(x, top);
+        mark(x, top + SCREEN_HEIGHT - 1);
+    }
+    for y in top..top + SCREEN_HEIGHT {
+        mark(left, y);
+        mark(left + SCREEN_WIDTH - 1, y);
+    }
+}
+
+fn main() {
+    let mut args = std::env::args().skip(1);
+    let Some(rom) = args.next() else {
+        eprintln!("usage: vram_viewer <rom> [frames] [tiles.ppm] [map.ppm]");
+        return;
+    };
+    let frames: u64 = args.next().map_or(60, |frames| frames.parse().expect("frames must be a number"));
+    let out = args.next().unwrap_or_else(|| "tiles.ppm".to_string());
+    let map_out = args.next().unwrap_or_else(|| "map.ppm".to_string());
+
+    let mut emulator = Emulator::new();
+    if let Err(error) = emulator.load_rom(&rom) {
+        eprintln!("{}", error);
+        return;
+    }
+    for _ in 0..frames {
+        let mut cycles = 0;
+        while cycles < DOTS_PER_FRAME {
+            match emulator.step() {
+                Ok(step) => cycles += step as u32,
+                Err(error) => {
+                    eprintln!("{}", error);
+                    return;
+                }
+            }
+        }
+    }
+
+    let mut tiles = vec![0; TILE_SHEET_WIDTH * TILE_SHEET_HEIGHT];
+    emulator.debug_render_tiles(&mut tiles);
+    if let Err(error) = std::fs::write(&out, ppm(&tiles, TILE_SHEET_WIDTH, TILE_SHEET_HEIGHT)) {
+        eprintln!("could not write {}: {}", out, error);
+        return;
+    }
+    println!("tiles written to {}", out);
+
+    // The map LCDC bit 3 picks for the background.
+    let map = if emulator.read_mem(0xFF40) & 0x08 != 0 { TileMap::Map9C00 } else { TileMap::Map9800 };
+    let mut picture = vec![0; TILE_MAP_SIZE * TILE_MAP_SIZE];
+    let viewport = emulator.debug_render_tilemap(map, &mut picture);
+    let mut image = ppm(&picture, TILE_MAP_SIZE, TILE_MAP_SIZE);
+    outline_viewport(&mut image, viewport);
+    if let Err(error) = std::fs::write(&map_out, image) {
+        eprintln!("could not write {}: {}", map_out, error);
+        return;
+    }
+    println!("{:?} written to {}", map, map_out);
+}
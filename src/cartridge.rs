@@ -0,0 +1,269 @@
+mod header;
+mod mbc1;
+mod mbc2;
+mod mbc3;
+mod mbc5;
+
+pub use header::{global_checksum, header_checksum, CartridgeHeader, Licensee, NINTENDO_LOGO};
+pub use mbc1::Mbc1;
+pub use mbc2::Mbc2;
+pub use mbc3::Mbc3;
+pub use mbc5::Mbc5;
+
+use std::fmt::{Display, Formatter};
+use std::io;
+use std::path::PathBuf;
+
+/// Why a ROM was refused.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RomError {
+    /// The ROM file could not be read.
+    Io { path: PathBuf, kind: io::ErrorKind },
+    /// ROMs come in powers of two from 32 KiB up.
+    BadSize { size: usize },
+    /// The header checksum is wrong, which locks up real hardware.
+    BadHeaderChecksum { expected: u8, computed: u8 },
+    /// The logo bitmap is not Nintendo's, which also locks up real hardware.
+    BadLogo,
+}
+
+impl Display for RomError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RomError::Io { path, kind } => write!(f, "could not read {}: {}", path.display(), kind),
+            RomError::BadSize { size } => write!(f, "ROM is {} bytes, expected a power of two of at least 32768", size),
+            RomError::BadHeaderChecksum { expected, computed } => write!(f, "header checksum is {:02X}, header says {:02X}", computed, expected),
+            RomError::BadLogo => write!(f, "header does not hold the Nintendo logo"),
+        }
+    }
+}
+
+impl std::error::Error for RomError {}
+
+/// The switchable unit of ROM, mapped at 0x4000-0x7FFF.
+const ROM_BANK_SIZE: usize = 0x4000;
+/// The switchable unit of RAM, mapped at 0xA000-0xBFFF.
+const RAM_BANK_SIZE: usize = 0x2000;
+
+/// A game cartridge: its ROM, any external RAM, and the bank controller that
+/// maps them into 0x0000-0x7FFF and 0xA000-0xBFFF.
+pub trait Cartridge {
+    /// Reads 0x0000-0x7FFF.
+    fn read_rom(&self, address: u16) -> u8;
+
+    /// Writes to 0x0000-0x7FFF, which program the bank controller and never
+    /// change the ROM itself.
+    fn write_rom(&mut self, address: u16, value: u8);
+
+    /// Reads 0xA000-0xBFFF.
+    fn read_ram(&self, address: u16) -> u8;
+
+    fn write_ram(&mut self, address: u16, value: u8);
+
+    /// The whole ROM image, as loaded.
+    fn rom(&self) -> &[u8];
+
+    /// Advances anything on the cartridge that keeps time by the T-cycles the
+    /// CPU just spent.
+    fn tick(&mut self, _cycles: u8) {}
+
+    /// Whether the rumble motor is on. Only rumble cartridges have one.
+    fn rumble(&self) -> bool {
+        false
+    }
+
+    /// The external RAM, as a save file stores it.
+    fn ram(&self) -> &[u8] {
+        &[]
+    }
+
+    fn ram_mut(&mut self) -> &mut [u8] {
+        &mut []
+    }
+}
+
+/// The smallest ROM, two banks mapped without a bank controller.
+const MIN_ROM_SIZE: usize = 2 * ROM_BANK_SIZE;
+
+/// Checks that `rom` has a size a cartridge could have.
+pub fn check_rom_size(rom: &[u8]) -> Result<(), RomError> {
+    if rom.len() < MIN_ROM_SIZE || !rom.len().is_power_of_two() {
+        return Err(RomError::BadSize { size: rom.len() });
+    }
+    Ok(())
+}
+
+/// Whether the cartridge type keeps its RAM powered by a battery, so it
+/// should be saved between sessions. Types whose bank controller is not
+/// implemented yet are left out. The MBC3 clock of types 0x0F and 0x10 is
+/// battery-backed too, but is not saved yet: only the RAM is.
+pub fn has_battery(header: &CartridgeHeader) -> bool {
+    matches!(header.cartridge_type, 0x03 | 0x06 | 0x09 | 0x0D | 0x0F | 0x10 | 0x13 | 0x1B | 0x1E)
+}
+
+/// Picks the implementation matching the header's cartridge type. Types
+/// without a bank controller implementation yet run as ROM only.
+pub fn from_rom(rom: Vec<u8>) -> Box<dyn Cartridge> {
+    let header = CartridgeHeader::parse(&rom);
+    let ram_size = header.ram_size_bytes();
+    match header.cartridge_type {
+        0x01 => Box::new(Mbc1::new(rom, 0)),
+        0x02 | 0x03 => Box::new(Mbc1::new(rom, ram_size)),
+        0x05 | 0x06 => Box::new(Mbc2::new(rom)),
+        0x08 | 0x09 => Box::new(RomOnly::with_ram(rom)),
+        0x0F => Box::new(Mbc3::new(rom, 0, true)),
+        0x10 => Box::new(Mbc3::new(rom, ram_size, true)),
+        0x11 => Box::new(Mbc3::new(rom, 0, false)),
+        0x12 | 0x13 => Box::new(Mbc3::new(rom, ram_size, false)),
+        0x19..=0x1B => Box::new(Mbc5::new(rom, ram_size)),
+        0x1C..=0x1E => Box::new(Mbc5::with_rumble(rom, ram_size)),
+        _ => Box::new(RomOnly::new(rom)),
+    }
+}
+
+/// A ROM of `banks` banks, each starting with its own little-endian bank
+/// number.
+#[cfg(test)]
+fn numbered_rom(banks: usize) -> Vec<u8> {
+    let mut rom = vec![0; banks * ROM_BANK_SIZE];
+    for bank in 0..banks {
+        rom[bank * ROM_BANK_SIZE] = bank as u8;
+        rom[bank * ROM_BANK_SIZE + 1] = (bank >> 8) as u8;
+    }
+    rom
+}
+
+/// A cartridge without a bank controller: up to 32 KiB of ROM mapped as is,
+/// and for types 0x08 and 0x09 8 KiB of RAM.
+pub struct RomOnly {
+    rom: Vec<u8>,
+    ram: Option<Box<[u8; 0x2000]>>,
+}
+
+impl RomOnly {
+    pub fn new(rom: Vec<u8>) -> RomOnly {
+        RomOnly {
+            rom,
+            ram: None,
+        }
+    }
+
+    pub fn with_ram(rom: Vec<u8>) -> RomOnly {
+        RomOnly {
+            rom,
+            ram: Some(Box::new([0; 0x2000])),
+        }
+    }
+}
+
+impl Cartridge for RomOnly {
+    /// Addresses past the end of a short ROM read as open bus.
+    fn read_rom(&self, address: u16) -> u8 {
+        self.rom.get(address as usize).copied().unwrap_or(0xFF)
+    }
+
+    fn write_rom(&mut self, _address: u16, _value: u8) {}
+
+    /// Without RAM the bus floats and reads 0xFF.
+    fn read_ram(&self, address: u16) -> u8 {
+        self.ram.as_ref().map_or(0xFF, |ram| ram[(address - 0xA000) as usize])
+    }
+
+    fn write_ram(&mut self, address: u16, value: u8) {
+        if let Some(ram) = &mut self.ram {
+            ram[(address - 0xA000) as usize] = value;
+        }
+    }
+
+    fn rom(&self) -> &[u8] {
+        &self.rom
+    }
+
+    fn ram(&self) -> &[u8] {
+        self.ram.as_deref().map_or(&[], |ram| &ram[..])
+    }
+
+    fn ram_mut(&mut self) -> &mut [u8] {
+        match &mut self.ram {
+            Some(ram) => &mut ram[..],
+            None => &mut [],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::header::{CARTRIDGE_TYPE, RAM_SIZE};
+
+    #[test]
+    fn test_rom_only_maps_rom_and_ram() {
+        let mut rom = vec![0; 0x8000];
+        rom[0x0000] = 0x31;
+        rom[0x7FFF] = 0x76;
+        rom[CARTRIDGE_TYPE] = 0x08;
+        let mut cartridge = from_rom(rom);
+
+        assert_eq!(cartridge.read_rom(0x0000), 0x31);
+        assert_eq!(cartridge.read_rom(0x7FFF), 0x76);
+
+        cartridge.write_ram(0xA000, 0x12);
+        cartridge.write_ram(0xBFFF, 0x34);
+        assert_eq!(cartridge.read_ram(0xA000), 0x12);
+        assert_eq!(cartridge.read_ram(0xBFFF), 0x34);
+    }
+
+    #[test]
+    fn test_short_rom_reads_open_bus() {
+        let cartridge = RomOnly::new(vec![0x00; 0x4000]);
+        assert_eq!(cartridge.read_rom(0x3FFF), 0x00);
+        assert_eq!(cartridge.read_rom(0x4000), 0xFF);
+    }
+
+    #[test]
+    fn test_rom_only_ignores_rom_writes() {
+        let mut rom = vec![0; 0x8000];
+        rom[0x2000] = 0x42;
+        let mut cartridge = from_rom(rom);
+
+        cartridge.write_rom(0x2000, 0x01);
+        assert_eq!(cartridge.read_rom(0x2000), 0x42);
+        assert_eq!(cartridge.rom()[0x2000], 0x42);
+    }
+
+    #[test]
+    fn test_rom_only_without_ram_reads_ff() {
+        let mut cartridge = from_rom(vec![0; 0x8000]);
+        cartridge.write_ram(0xA000, 0x12);
+        assert_eq!(cartridge.read_ram(0xA000), 0xFF);
+        assert_eq!(cartridge.read_ram(0xBFFF), 0xFF);
+    }
+
+    #[test]
+    fn test_from_rom_picks_mbc1() {
+        let mut rom = vec![0; 0x10000];
+        rom[CARTRIDGE_TYPE] = 0x03;
+        rom[RAM_SIZE] = 0x02;
+        rom[2 * ROM_BANK_SIZE] = 0x99;
+        let mut cartridge = from_rom(rom);
+
+        cartridge.write_rom(0x2000, 0x02);
+        assert_eq!(cartridge.read_rom(0x4000), 0x99);
+        cartridge.write_rom(0x0000, 0x0A);
+        cartridge.write_ram(0xBFFF, 0x12);
+        assert_eq!(cartridge.read_ram(0xBFFF), 0x12);
+    }
+
+    #[test]
+    fn test_check_rom_size() {
+        assert_eq!(check_rom_size(&vec![0; 0x8000]), Ok(()));
+        assert_eq!(check_rom_size(&vec![0; 0x100000]), Ok(()));
+        assert_eq!(check_rom_size(&[0; 7]), Err(RomError::BadSize { size: 7 }));
+        assert_eq!(check_rom_size(&vec![0; 0x4000]), Err(RomError::BadSize { size: 0x4000 }));
+        assert_eq!(check_rom_size(&vec![0; 0xC000]), Err(RomError::BadSize { size: 0xC000 }));
+        assert_eq!(
+            RomError::BadSize { size: 7 }.to_string(),
+            "ROM is 7 bytes, expected a power of two of at least 32768"
+        );
+    }
+}
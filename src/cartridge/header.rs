@@ -0,0 +1,305 @@
+/// Address of the logo bitmap the boot ROM scrolls in.
+const LOGO: usize = 0x0104;
+
+/// The logo every licensed cartridge carries, compared byte for byte by the
+/// boot ROM.
+pub const NINTENDO_LOGO: [u8; 48] = [
+    0xCE, 0xED, 0x66, 0x66, 0xCC, 0x0D, 0x00, 0x0B, 0x03, 0x73, 0x00, 0x83, 0x00, 0x0C, 0x00, 0x0D,
+    0x00, 0x08, 0x11, 0x1F, 0x88, 0x89, 0x00, 0x0E, 0xDC, 0xCC, 0x6E, 0xE6, 0xDD, 0xDD, 0xD9, 0x99,
+    0xBB, 0xBB, 0x67, 0x63, 0x6E, 0x0E, 0xEC, 0xCC, 0xDD, 0xDC, 0x99, 0x9F, 0xBB, 0xB9, 0x33, 0x3E,
+];
+
+/// Address of the first title byte.
+const TITLE: usize = 0x0134;
+/// Address of the manufacturer code on newer cartridges, which shortens the
+/// title to 11 bytes.
+const MANUFACTURER_CODE: usize = 0x013F;
+const CGB_FLAG: usize = 0x0143;
+const NEW_LICENSEE: usize = 0x0144;
+const SGB_FLAG: usize = 0x0146;
+pub(super) const CARTRIDGE_TYPE: usize = 0x0147;
+const ROM_SIZE: usize = 0x0148;
+pub(super) const RAM_SIZE: usize = 0x0149;
+const DESTINATION: usize = 0x014A;
+const OLD_LICENSEE: usize = 0x014B;
+const MASK_ROM_VERSION: usize = 0x014C;
+const HEADER_CHECKSUM: usize = 0x014D;
+const GLOBAL_CHECKSUM: usize = 0x014E;
+
+/// The header checksum the boot ROM checks: over 0x0134-0x014C, each byte
+/// subtracted along with one more.
+pub fn header_checksum(rom: &[u8]) -> u8 {
+    (TITLE..HEADER_CHECKSUM).fold(0u8, |checksum, address| {
+        checksum.wrapping_sub(rom.get(address).copied().unwrap_or(0x00)).wrapping_sub(1)
+    })
+}
+
+/// The sum of every ROM byte except the two holding it. Nothing checks it on
+/// hardware.
+pub fn global_checksum(rom: &[u8]) -> u16 {
+    rom.iter()
+        .enumerate()
+        .filter(|&(address, _)| address != GLOBAL_CHECKSUM && address != GLOBAL_CHECKSUM + 1)
+        .fold(0u16, |checksum, (_, &byte)| checksum.wrapping_add(byte as u16))
+}
+
+/// The old licensee code that defers to the two-character new one.
+const USE_NEW_LICENSEE: u8 = 0x33;
+
+/// Who published the game.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Licensee {
+    /// The one-byte code at 0x014B.
+    Old(u8),
+    /// The two ASCII characters at 0x0144-0x0145, used when the old code is
+    /// 0x33.
+    New(String),
+}
+
+impl Default for Licensee {
+    fn default() -> Self {
+        Licensee::Old(0x00)
+    }
+}
+
+/// The cartridge header at 0x0100-0x014F.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CartridgeHeader {
+    pub title: String,
+    /// Only present on newer cartridges.
+    pub manufacturer_code: Option<String>,
+    /// 0x80 or 0xC0 for CGB games, and 0x00 when the byte is the last title
+    /// character.
+    pub cgb_flag: u8,
+    pub sgb_flag: u8,
+    pub cartridge_type: u8,
+    pub rom_size: u8,
+    pub ram_size: u8,
+    /// 0x00 for Japan, 0x01 for everywhere else.
+    pub destination: u8,
+    pub licensee: Licensee,
+    pub mask_rom_version: u8,
+    pub header_checksum: u8,
+    /// Stored big-endian, unlike everything else.
+    pub global_checksum: u16,
+    /// Whether `header_checksum` matches the header. The boot ROM locks up
+    /// when it does not.
+    pub header_checksum_ok: bool,
+    pub global_checksum_ok: bool,
+    /// Whether 0x0104-0x0133 hold `NINTENDO_LOGO`. The boot ROM locks up
+    /// when they do not.
+    pub logo_ok: bool,
+}
+
+/// Turns header bytes into text, stopping at the first NUL and dropping the
+/// space padding some games use instead.
+fn text(bytes: &[u8]) -> String {
+    bytes.iter()
+        .take_while(|&&byte| byte != 0x00)
+        .map(|&byte| if byte.is_ascii_graphic() || byte == b' ' { byte as char } else { '?' })
+        .collect::<String>()
+        .trim_end()
+        .to_string()
+}
+
+impl CartridgeHeader {
+    /// Parses the header of `rom`. Bytes past the end of a short ROM read as
+    /// 0x00.
+    pub fn parse(rom: &[u8]) -> CartridgeHeader {
+        let byte = |address: usize| rom.get(address).copied().unwrap_or(0x00);
+        let bytes = |start: usize, end: usize| (start..end).map(byte).collect::<Vec<u8>>();
+
+        // The title took all 16 bytes up to 0x0143 until the CGB flag claimed
+        // the last one, and later the manufacturer code the 4 before it.
+        let cgb_flag = byte(CGB_FLAG);
+        let manufacturer_code = bytes(MANUFACTURER_CODE, CGB_FLAG);
+        let has_manufacturer_code = cgb_flag & 0x80 != 0
+            && manufacturer_code.iter().all(|byte| byte.is_ascii_uppercase() || byte.is_ascii_digit());
+        let title_end = match cgb_flag & 0x80 {
+            0x80 if has_manufacturer_code => MANUFACTURER_CODE,
+            0x80 => CGB_FLAG,
+            _ => CGB_FLAG + 1,
+        };
+
+        let licensee = match byte(OLD_LICENSEE) {
+            USE_NEW_LICENSEE => Licensee::New(text(&bytes(NEW_LICENSEE, NEW_LICENSEE + 2))),
+            code => Licensee::Old(code),
+        };
+
+        let stored_global_checksum = u16::from_be_bytes([byte(GLOBAL_CHECKSUM), byte(GLOBAL_CHECKSUM + 1)]);
+        CartridgeHeader {
+            title: text(&bytes(TITLE, title_end)),
+            manufacturer_code: if has_manufacturer_code { Some(text(&manufacturer_code)) } else { None },
+            cgb_flag: if cgb_flag & 0x80 != 0 { cgb_flag } else { 0x00 },
+            sgb_flag: byte(SGB_FLAG),
+            cartridge_type: byte(CARTRIDGE_TYPE),
+            rom_size: byte(ROM_SIZE),
+            ram_size: byte(RAM_SIZE),
+            destination: byte(DESTINATION),
+            licensee,
+            mask_rom_version: byte(MASK_ROM_VERSION),
+            header_checksum: byte(HEADER_CHECKSUM),
+            global_checksum: stored_global_checksum,
+            header_checksum_ok: header_checksum(rom) == byte(HEADER_CHECKSUM),
+            global_checksum_ok: global_checksum(rom) == stored_global_checksum,
+            logo_ok: rom.get(LOGO..LOGO + NINTENDO_LOGO.len()) == Some(&NINTENDO_LOGO[..]),
+        }
+    }
+
+    /// The ROM size the header declares, in bytes.
+    pub fn rom_size_bytes(&self) -> Option<usize> {
+        match self.rom_size {
+            0x00..=0x08 => Some(0x8000 << self.rom_size),
+            _ => None,
+        }
+    }
+
+    /// The external RAM size the header declares, in bytes.
+    pub fn ram_size_bytes(&self) -> usize {
+        match self.ram_size {
+            0x01 => 0x800,
+            0x02 => 0x2000,
+            0x03 => 0x8000,
+            0x04 => 0x20000,
+            0x05 => 0x10000,
+            _ => 0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_old_header() {
+        let mut rom = vec![0; 0x8000];
+        rom[0x0134..0x0144].copy_from_slice(b"SIXTEEN BYTE TTL");
+        rom[0x0146] = 0x03;
+        rom[0x0147] = 0x13;
+        rom[0x0148] = 0x05;
+        rom[0x0149] = 0x03;
+        rom[0x014A] = 0x01;
+        rom[0x014B] = 0x01;
+        rom[0x014C] = 0x02;
+        rom[0x014D] = 0xA5;
+        rom[0x014E..0x0150].copy_from_slice(&[0x12, 0x34]);
+
+        let header = CartridgeHeader::parse(&rom);
+        assert_eq!(header.title, "SIXTEEN BYTE TTL");
+        assert_eq!(header.manufacturer_code, None);
+        assert_eq!(header.cgb_flag, 0x00);
+        assert_eq!(header.sgb_flag, 0x03);
+        assert_eq!(header.cartridge_type, 0x13);
+        assert_eq!((header.rom_size, header.rom_size_bytes()), (0x05, Some(0x100000)));
+        assert_eq!((header.ram_size, header.ram_size_bytes()), (0x03, 0x8000));
+        assert_eq!(header.destination, 0x01);
+        assert_eq!(header.licensee, Licensee::Old(0x01));
+        assert_eq!(header.mask_rom_version, 0x02);
+        assert_eq!(header.header_checksum, 0xA5);
+        assert_eq!(header.global_checksum, 0x1234);
+    }
+
+    #[test]
+    fn test_parse_cgb_header() {
+        // Laid out like a dual-mode homebrew release: short title padded with
+        // NULs, manufacturer code, CGB flag and a new licensee code.
+        let mut rom = vec![0; 0x8000];
+        rom[0x0134..0x0138].copy_from_slice(b"DEMO");
+        rom[0x013F..0x0143].copy_from_slice(b"ADME");
+        rom[0x0143] = 0x80;
+        rom[0x0144..0x0146].copy_from_slice(b"HB");
+        rom[0x0147] = 0x1B;
+        rom[0x014B] = 0x33;
+
+        let header = CartridgeHeader::parse(&rom);
+        assert_eq!(header.title, "DEMO");
+        assert_eq!(header.manufacturer_code.as_deref(), Some("ADME"));
+        assert_eq!(header.cgb_flag, 0x80);
+        assert_eq!(header.licensee, Licensee::New("HB".to_string()));
+        assert_eq!(header.rom_size_bytes(), Some(0x8000));
+        assert_eq!(header.ram_size_bytes(), 0);
+    }
+
+    #[test]
+    fn test_parse_fifteen_byte_title() {
+        // Without a valid manufacturer code the title runs up to the flag.
+        let mut rom = vec![0; 0x150];
+        rom[0x0134..0x0143].copy_from_slice(b"FIFTEEN bytes!!");
+        rom[0x0143] = 0xC0;
+
+        let header = CartridgeHeader::parse(&rom);
+        assert_eq!(header.title, "FIFTEEN bytes!!");
+        assert_eq!(header.manufacturer_code, None);
+
+        let header = CartridgeHeader::parse(&[0; 0x10]);
+        assert_eq!(header.title, "");
+        assert_eq!(header.cartridge_type, 0x00);
+    }
+
+    #[test]
+    fn test_parse_homebrew_rom() {
+        // A complete ROM that runs, checksums and all; see tests/data/README.md.
+        let rom = include_bytes!("../../tests/data/hello.gb");
+        let header = CartridgeHeader::parse(rom);
+        assert_eq!(header.title, "HELLO DMG");
+        assert_eq!(header.manufacturer_code.as_deref(), Some("DTHB"));
+        assert_eq!(header.cgb_flag, 0x80);
+        assert_eq!(header.sgb_flag, 0x03);
+        assert_eq!(header.licensee, Licensee::New("HB".to_string()));
+        assert_eq!(header.cartridge_type, 0x00);
+        assert_eq!((header.rom_size, header.rom_size_bytes()), (0x00, Some(rom.len())));
+        assert_eq!((header.ram_size, header.ram_size_bytes()), (0x00, 0));
+        assert_eq!(header.destination, 0x01);
+        assert_eq!(header.mask_rom_version, 0x01);
+        assert_eq!(header.header_checksum, 0x17);
+        assert_eq!(header.global_checksum, 0x32EE);
+        assert!(header.header_checksum_ok);
+        assert!(header.global_checksum_ok);
+        assert!(header.logo_ok);
+    }
+
+    /// Writes both checksums into `rom`.
+    fn fix_checksums(rom: &mut [u8]) {
+        rom[HEADER_CHECKSUM] = header_checksum(rom);
+        let [high, low] = global_checksum(rom).to_be_bytes();
+        rom[GLOBAL_CHECKSUM] = high;
+        rom[GLOBAL_CHECKSUM + 1] = low;
+    }
+
+    #[test]
+    fn test_checksums() {
+        let mut rom = vec![0; 0x8000];
+        rom[0x0134..0x0138].copy_from_slice(b"GOOD");
+        rom[0x4000] = 0x42;
+        fix_checksums(&mut rom);
+        // -(25 + 'G' + 'O' + 'O' + 'D') mod 256
+        assert_eq!(rom[HEADER_CHECKSUM], 0xBE);
+        let header = CartridgeHeader::parse(&rom);
+        assert!(header.header_checksum_ok);
+        assert!(header.global_checksum_ok);
+
+        rom[0x0135] ^= 0x01;
+        let header = CartridgeHeader::parse(&rom);
+        assert!(!header.header_checksum_ok);
+        assert!(!header.global_checksum_ok);
+
+        rom[0x0135] ^= 0x01;
+        rom[0x7FFF] = 0x01;
+        let header = CartridgeHeader::parse(&rom);
+        assert!(header.header_checksum_ok);
+        assert!(!header.global_checksum_ok);
+    }
+
+    #[test]
+    fn test_logo() {
+        let mut rom = vec![0; 0x8000];
+        assert!(!CartridgeHeader::parse(&rom).logo_ok);
+
+        rom[LOGO..LOGO + 48].copy_from_slice(&NINTENDO_LOGO);
+        assert!(CartridgeHeader::parse(&rom).logo_ok);
+        rom[0x0133] ^= 0x80;
+        assert!(!CartridgeHeader::parse(&rom).logo_ok);
+        assert!(!CartridgeHeader::parse(&rom[..0x0120]).logo_ok);
+    }
+}
@@ -0,0 +1,224 @@
+use super::{Cartridge, ROM_BANK_SIZE, RAM_BANK_SIZE};
+
+/// The MBC1 bank controller, for up to 2 MiB of ROM and 32 KiB of RAM.
+pub struct Mbc1 {
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+    ram_enabled: bool,
+    /// The 5-bit ROM bank register written at 0x2000-0x3FFF.
+    bank1: u8,
+    /// The 2-bit register written at 0x4000-0x5FFF: the upper ROM bank bits,
+    /// or in mode 1 the RAM bank.
+    bank2: u8,
+    /// The banking mode selected at 0x6000-0x7FFF.
+    mode: u8,
+}
+
+impl Mbc1 {
+    pub fn new(rom: Vec<u8>, ram_size: usize) -> Mbc1 {
+        Mbc1 {
+            rom,
+            ram: vec![0; ram_size],
+            ram_enabled: false,
+            bank1: 1,
+            bank2: 0,
+            mode: 0,
+        }
+    }
+
+    /// Bank numbers wrap around the banks the ROM actually has.
+    fn rom_bank_count(&self) -> usize {
+        (self.rom.len() / ROM_BANK_SIZE).max(1)
+    }
+
+    /// The bank at 0x4000-0x7FFF. The bank 0 check only looks at the 5-bit
+    /// register, so 0x20, 0x40 and 0x60 read as 0x21, 0x41 and 0x61.
+    fn rom_bank(&self) -> usize {
+        ((self.bank2 as usize) << 5 | self.bank1 as usize) % self.rom_bank_count()
+    }
+
+    /// The bank at 0x0000-0x3FFF, which mode 1 moves by the upper bank bits
+    /// on ROMs of 1 MiB and more.
+    fn lower_rom_bank(&self) -> usize {
+        if self.mode == 1 {
+            ((self.bank2 as usize) << 5) % self.rom_bank_count()
+        } else {
+            0
+        }
+    }
+
+    fn ram_offset(&self, address: u16) -> usize {
+        let bank = if self.mode == 1 { self.bank2 as usize } else { 0 };
+        (bank * RAM_BANK_SIZE + (address - 0xA000) as usize) % self.ram.len()
+    }
+}
+
+impl Cartridge for Mbc1 {
+    fn read_rom(&self, address: u16) -> u8 {
+        let offset = match address {
+            0x0000..=0x3FFF => self.lower_rom_bank() * ROM_BANK_SIZE + address as usize,
+            _ => self.rom_bank() * ROM_BANK_SIZE + (address - 0x4000) as usize,
+        };
+        self.rom.get(offset).copied().unwrap_or(0xFF)
+    }
+
+    fn write_rom(&mut self, address: u16, value: u8) {
+        match address {
+            0x0000..=0x1FFF => self.ram_enabled = value & 0x0F == 0x0A,
+            // Bank 0 cannot be selected here; it reads as bank 1.
+            0x2000..=0x3FFF => self.bank1 = (value & 0x1F).max(1),
+            0x4000..=0x5FFF => self.bank2 = value & 0x03,
+            _ => self.mode = value & 0x01,
+        }
+    }
+
+    /// Disabled or missing RAM reads 0xFF.
+    fn read_ram(&self, address: u16) -> u8 {
+        if !self.ram_enabled || self.ram.is_empty() {
+            return 0xFF;
+        }
+        self.ram[self.ram_offset(address)]
+    }
+
+    fn write_ram(&mut self, address: u16, value: u8) {
+        if self.ram_enabled && !self.ram.is_empty() {
+            let offset = self.ram_offset(address);
+            self.ram[offset] = value;
+        }
+    }
+
+    fn rom(&self) -> &[u8] {
+        &self.rom
+    }
+
+    fn ram(&self) -> &[u8] {
+        &self.ram
+    }
+
+    fn ram_mut(&mut self) -> &mut [u8] {
+        &mut self.ram
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cartridge::numbered_rom;
+
+    #[test]
+    fn test_bank_zero_selects_bank_one() {
+        let mut mbc = Mbc1::new(numbered_rom(32), 0);
+        assert_eq!(mbc.read_rom(0x4000), 1);
+
+        mbc.write_rom(0x2000, 0x00);
+        assert_eq!(mbc.read_rom(0x4000), 1);
+        assert_eq!(mbc.read_rom(0x0000), 0);
+    }
+
+    #[test]
+    fn test_switch_rom_bank() {
+        let mut mbc = Mbc1::new(numbered_rom(32), 0);
+        mbc.write_rom(0x2000, 0x02);
+        assert_eq!(mbc.read_rom(0x4000), 2);
+
+        mbc.write_rom(0x3FFF, 0x1F);
+        assert_eq!(mbc.read_rom(0x4000), 31);
+        // Only the low 5 bits are kept.
+        mbc.write_rom(0x2000, 0xE3);
+        assert_eq!(mbc.read_rom(0x4000), 3);
+    }
+
+    #[test]
+    fn test_upper_bank_bits() {
+        let mut mbc = Mbc1::new(numbered_rom(128), 0);
+        mbc.write_rom(0x2000, 0x05);
+        mbc.write_rom(0x4000, 0x02);
+        assert_eq!(mbc.read_rom(0x4000), 0x45);
+        assert_eq!(mbc.read_rom(0x0000), 0);
+    }
+
+    #[test]
+    fn test_ram_enable_gating() {
+        let mut mbc = Mbc1::new(numbered_rom(4), 0x2000);
+        mbc.write_ram(0xA000, 0x12);
+        assert_eq!(mbc.read_ram(0xA000), 0xFF);
+
+        mbc.write_rom(0x0000, 0x0A);
+        assert_eq!(mbc.read_ram(0xA000), 0x00);
+        mbc.write_ram(0xA000, 0x34);
+        assert_eq!(mbc.read_ram(0xA000), 0x34);
+
+        mbc.write_rom(0x1FFF, 0x00);
+        assert_eq!(mbc.read_ram(0xA000), 0xFF);
+        mbc.write_rom(0x0000, 0x1A);
+        assert_eq!(mbc.read_ram(0xA000), 0x34);
+    }
+
+    #[test]
+    fn test_ram_banks_in_mode_one() {
+        let mut mbc = Mbc1::new(numbered_rom(4), 0x8000);
+        mbc.write_rom(0x0000, 0x0A);
+        mbc.write_ram(0xA000, 0x11);
+
+        mbc.write_rom(0x6000, 0x01);
+        mbc.write_rom(0x4000, 0x03);
+        assert_eq!(mbc.read_ram(0xA000), 0x00);
+        mbc.write_ram(0xA000, 0x33);
+
+        mbc.write_rom(0x4000, 0x00);
+        assert_eq!(mbc.read_ram(0xA000), 0x11);
+    }
+
+    #[test]
+    fn test_mode_one_banks_lower_window() {
+        // (bank2, bank1, mode, lower window, upper window) on 1 MiB of ROM
+        let cases = [
+            (0, 0x00, 0, 0x00, 0x01),
+            (1, 0x00, 0, 0x00, 0x21),
+            (1, 0x00, 1, 0x20, 0x21),
+            (1, 0x20, 1, 0x20, 0x21),
+            // Bit 6 of the bank number is past the end of 1 MiB.
+            (2, 0x02, 1, 0x00, 0x02),
+            (3, 0x1F, 0, 0x00, 0x3F),
+            (3, 0x1F, 1, 0x20, 0x3F),
+        ];
+
+        for (bank2, bank1, mode, lower, upper) in cases {
+            let mut mbc = Mbc1::new(numbered_rom(64), 0);
+            mbc.write_rom(0x2000, bank1);
+            mbc.write_rom(0x4000, bank2);
+            mbc.write_rom(0x6000, mode);
+            let case = format!("bank2 {} bank1 {:02X} mode {}", bank2, bank1, mode);
+            assert_eq!(mbc.read_rom(0x0000), lower, "{}", case);
+            assert_eq!(mbc.read_rom(0x4000), upper, "{}", case);
+        }
+    }
+
+    #[test]
+    fn test_banks_wrap_to_rom_size() {
+        // 256 KiB: bank 0x1F wraps to 0x0F and the upper bits are ignored.
+        let mut mbc = Mbc1::new(numbered_rom(16), 0);
+        mbc.write_rom(0x2000, 0x1F);
+        assert_eq!(mbc.read_rom(0x4000), 0x0F);
+        mbc.write_rom(0x4000, 0x01);
+        mbc.write_rom(0x6000, 0x01);
+        assert_eq!(mbc.read_rom(0x4000), 0x0F);
+        assert_eq!(mbc.read_rom(0x0000), 0x00);
+
+        // 64 KiB: bank 0x04 masks down to bank 0 in the upper window.
+        let mut mbc = Mbc1::new(numbered_rom(4), 0);
+        mbc.write_rom(0x2000, 0x04);
+        assert_eq!(mbc.read_rom(0x4000), 0x00);
+    }
+
+    #[test]
+    fn test_ram_banks_wrap_to_ram_size() {
+        let mut mbc = Mbc1::new(numbered_rom(4), 0x2000);
+        mbc.write_rom(0x0000, 0x0A);
+        mbc.write_rom(0x6000, 0x01);
+        mbc.write_ram(0xA123, 0x5A);
+
+        mbc.write_rom(0x4000, 0x03);
+        assert_eq!(mbc.read_ram(0xA123), 0x5A);
+    }
+}
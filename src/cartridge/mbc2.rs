@@ -0,0 +1,125 @@
+use super::{Cartridge, ROM_BANK_SIZE};
+
+/// The MBC2 bank controller, for up to 256 KiB of ROM, with 512 half-bytes
+/// of RAM built in.
+pub struct Mbc2 {
+    rom: Vec<u8>,
+    ram: [u8; 0x200],
+    ram_enabled: bool,
+    rom_bank: u8,
+}
+
+impl Mbc2 {
+    pub fn new(rom: Vec<u8>) -> Mbc2 {
+        Mbc2 {
+            rom,
+            ram: [0; 0x200],
+            ram_enabled: false,
+            rom_bank: 1,
+        }
+    }
+
+    fn rom_bank_count(&self) -> usize {
+        (self.rom.len() / ROM_BANK_SIZE).max(1)
+    }
+}
+
+impl Cartridge for Mbc2 {
+    fn read_rom(&self, address: u16) -> u8 {
+        let offset = match address {
+            0x0000..=0x3FFF => address as usize,
+            _ => (self.rom_bank as usize % self.rom_bank_count()) * ROM_BANK_SIZE + (address - 0x4000) as usize,
+        };
+        self.rom.get(offset).copied().unwrap_or(0xFF)
+    }
+
+    /// Both registers live in 0x0000-0x3FFF, bit 8 of the address picking
+    /// the ROM bank register over RAM enable.
+    fn write_rom(&mut self, address: u16, value: u8) {
+        match address {
+            0x0000..=0x3FFF if address & 0x0100 == 0 => self.ram_enabled = value & 0x0F == 0x0A,
+            0x0000..=0x3FFF => self.rom_bank = (value & 0x0F).max(1),
+            _ => {},
+        }
+    }
+
+    /// Only the low nibble is stored, the upper one reading as 1s, and the
+    /// 512 entries repeat across the whole window.
+    fn read_ram(&self, address: u16) -> u8 {
+        if !self.ram_enabled {
+            return 0xFF;
+        }
+        0xF0 | self.ram[(address & 0x01FF) as usize]
+    }
+
+    fn write_ram(&mut self, address: u16, value: u8) {
+        if self.ram_enabled {
+            self.ram[(address & 0x01FF) as usize] = value & 0x0F;
+        }
+    }
+
+    fn rom(&self) -> &[u8] {
+        &self.rom
+    }
+
+    /// One byte per half-byte cell, its upper nibble unused.
+    fn ram(&self) -> &[u8] {
+        &self.ram
+    }
+
+    fn ram_mut(&mut self) -> &mut [u8] {
+        &mut self.ram
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cartridge::numbered_rom;
+
+    #[test]
+    fn test_address_bit_8_selects_register() {
+        let mut mbc = Mbc2::new(numbered_rom(16));
+
+        // Bit 8 clear: RAM enable, leaving the ROM bank alone.
+        mbc.write_rom(0x0000, 0x0A);
+        assert_eq!(mbc.read_rom(0x4000), 1);
+        assert_eq!(mbc.read_ram(0xA000), 0xF0);
+
+        // Bit 8 set: ROM bank, leaving RAM enabled.
+        mbc.write_rom(0x2100, 0x05);
+        assert_eq!(mbc.read_rom(0x4000), 5);
+        assert_eq!(mbc.read_ram(0xA000), 0xF0);
+        mbc.write_rom(0x0100, 0x00);
+        assert_eq!(mbc.read_rom(0x4000), 1);
+
+        mbc.write_rom(0x3E00, 0x00);
+        assert_eq!(mbc.read_ram(0xA000), 0xFF);
+        assert_eq!(mbc.read_rom(0x4000), 1);
+
+        // The upper half of the ROM area has no registers.
+        mbc.write_rom(0x4100, 0x03);
+        assert_eq!(mbc.read_rom(0x4000), 1);
+    }
+
+    #[test]
+    fn test_ram_keeps_low_nibble() {
+        let mut mbc = Mbc2::new(numbered_rom(2));
+        mbc.write_rom(0x0000, 0x0A);
+        mbc.write_ram(0xA000, 0xAB);
+        assert_eq!(mbc.read_ram(0xA000), 0xFB);
+    }
+
+    #[test]
+    fn test_ram_repeats_across_window() {
+        let mut mbc = Mbc2::new(numbered_rom(2));
+        mbc.write_rom(0x0000, 0x0A);
+        mbc.write_ram(0xA1FF, 0x07);
+        mbc.write_ram(0xA200, 0x03);
+
+        assert_eq!(mbc.read_ram(0xA000), 0xF3);
+        assert_eq!(mbc.read_ram(0xB000), 0xF3);
+        assert_eq!(mbc.read_ram(0xA3FF), 0xF7);
+        assert_eq!(mbc.read_ram(0xBFFF), 0xF7);
+    }
+}
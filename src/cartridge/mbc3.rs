@@ -0,0 +1,374 @@
+use super::{Cartridge, RAM_BANK_SIZE, ROM_BANK_SIZE};
+
+/// T-cycles per emulated second, so the clock follows emulated time rather
+/// than the host's.
+const CYCLES_PER_SECOND: u32 = 4_194_304;
+
+/// The real-time clock: seconds, minutes, hours and a 9-bit day counter with
+/// its overflow flag. Halting it freezes the count.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+struct Rtc {
+    seconds: u8,
+    minutes: u8,
+    hours: u8,
+    days: u16,
+    halted: bool,
+    day_carry: bool,
+    /// T-cycles into the current second.
+    cycles: u32,
+}
+
+impl Rtc {
+    /// Registers 0x08-0x0C. The day high register holds day bit 8 in bit 0,
+    /// the halt flag in bit 6 and the day counter overflow in bit 7.
+    fn read(&self, register: u8) -> u8 {
+        match register {
+            0x08 => self.seconds,
+            0x09 => self.minutes,
+            0x0A => self.hours,
+            0x0B => self.days as u8,
+            _ => (self.days >> 8) as u8 | (self.halted as u8) << 6 | (self.day_carry as u8) << 7,
+        }
+    }
+
+    fn write(&mut self, register: u8, value: u8) {
+        match register {
+            0x08 => { self.seconds = value & 0x3F; self.cycles = 0 },
+            0x09 => self.minutes = value & 0x3F,
+            0x0A => self.hours = value & 0x1F,
+            0x0B => self.days = (self.days & 0x100) | value as u16,
+            _ => {
+                self.days = (self.days & 0xFF) | ((value as u16 & 0x01) << 8);
+                self.halted = value & 0x40 != 0;
+                self.day_carry = value & 0x80 != 0;
+            }
+        }
+    }
+
+    fn tick(&mut self, cycles: u8) {
+        if self.halted {
+            return;
+        }
+        self.cycles += cycles as u32;
+        while self.cycles >= CYCLES_PER_SECOND {
+            self.cycles -= CYCLES_PER_SECOND;
+            self.advance_second();
+        }
+    }
+
+    /// Counters set out of range keep counting up to their bit width before
+    /// wrapping to 0, without carrying.
+    fn advance_second(&mut self) {
+        self.seconds = (self.seconds + 1) & 0x3F;
+        if self.seconds != 60 {
+            return;
+        }
+        self.seconds = 0;
+        self.minutes = (self.minutes + 1) & 0x3F;
+        if self.minutes != 60 {
+            return;
+        }
+        self.minutes = 0;
+        self.hours = (self.hours + 1) & 0x1F;
+        if self.hours != 24 {
+            return;
+        }
+        self.hours = 0;
+        self.days += 1;
+        if self.days > 0x1FF {
+            self.days = 0;
+            self.day_carry = true;
+        }
+    }
+}
+
+/// The MBC3 bank controller, for up to 2 MiB of ROM and 32 KiB of RAM, and
+/// on types 0x0F and 0x10 a real-time clock.
+pub struct Mbc3 {
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+    /// Without the clock chip, selects 0x08-0x0C map nothing.
+    has_rtc: bool,
+    /// Enables both the RAM and the clock registers.
+    ram_enabled: bool,
+    rom_bank: u8,
+    /// A RAM bank 0x00-0x03, or a clock register 0x08-0x0C.
+    ram_select: u8,
+    rtc: Rtc,
+    /// The snapshot the clock registers read from.
+    latched: Rtc,
+    /// The last value written to the latch register; 0x00 then 0x01 latches.
+    latch: u8,
+}
+
+impl Mbc3 {
+    pub fn new(rom: Vec<u8>, ram_size: usize, has_rtc: bool) -> Mbc3 {
+        Mbc3 {
+            rom,
+            ram: vec![0; ram_size],
+            has_rtc,
+            ram_enabled: false,
+            rom_bank: 1,
+            ram_select: 0,
+            rtc: Rtc::default(),
+            latched: Rtc::default(),
+            latch: 0xFF,
+        }
+    }
+
+    fn rom_bank_count(&self) -> usize {
+        (self.rom.len() / ROM_BANK_SIZE).max(1)
+    }
+
+    /// The RAM offset for `address`, unless a clock register is mapped or the
+    /// cartridge has no RAM.
+    fn ram_offset(&self, address: u16) -> Option<usize> {
+        match self.ram_select {
+            0x00..=0x03 if !self.ram.is_empty() => {
+                Some((self.ram_select as usize * RAM_BANK_SIZE + (address - 0xA000) as usize) % self.ram.len())
+            }
+            _ => None,
+        }
+    }
+}
+
+impl Cartridge for Mbc3 {
+    fn read_rom(&self, address: u16) -> u8 {
+        let offset = match address {
+            0x0000..=0x3FFF => address as usize,
+            _ => (self.rom_bank as usize % self.rom_bank_count()) * ROM_BANK_SIZE + (address - 0x4000) as usize,
+        };
+        self.rom.get(offset).copied().unwrap_or(0xFF)
+    }
+
+    fn write_rom(&mut self, address: u16, value: u8) {
+        match address {
+            0x0000..=0x1FFF => self.ram_enabled = value & 0x0F == 0x0A,
+            0x2000..=0x3FFF => self.rom_bank = (value & 0x7F).max(1),
+            0x4000..=0x5FFF => self.ram_select = value,
+            _ => {
+                if self.has_rtc && self.latch == 0x00 && value == 0x01 {
+                    self.latched = self.rtc;
+                }
+                self.latch = value;
+            }
+        }
+    }
+
+    /// Disabled or missing RAM reads 0xFF, as do the clock registers on a
+    /// cartridge without the clock. Otherwise they read the latched snapshot.
+    fn read_ram(&self, address: u16) -> u8 {
+        if !self.ram_enabled {
+            return 0xFF;
+        }
+        match (self.ram_select, self.ram_offset(address)) {
+            (0x08..=0x0C, _) if self.has_rtc => self.latched.read(self.ram_select),
+            (_, Some(offset)) => self.ram[offset],
+            _ => 0xFF,
+        }
+    }
+
+    /// Clock register writes set the running clock.
+    fn write_ram(&mut self, address: u16, value: u8) {
+        if !self.ram_enabled {
+            return;
+        }
+        match (self.ram_select, self.ram_offset(address)) {
+            (0x08..=0x0C, _) if self.has_rtc => self.rtc.write(self.ram_select, value),
+            (_, Some(offset)) => self.ram[offset] = value,
+            _ => {},
+        }
+    }
+
+    fn rom(&self) -> &[u8] {
+        &self.rom
+    }
+
+    fn ram(&self) -> &[u8] {
+        &self.ram
+    }
+
+    fn ram_mut(&mut self) -> &mut [u8] {
+        &mut self.ram
+    }
+
+    fn tick(&mut self, cycles: u8) {
+        if self.has_rtc {
+            self.rtc.tick(cycles);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cartridge::header::{CARTRIDGE_TYPE, RAM_SIZE};
+    use crate::cartridge::{from_rom, numbered_rom};
+
+    #[test]
+    fn test_rom_banks_of_2_mib() {
+        let mut mbc = Mbc3::new(numbered_rom(128), 0, false);
+        assert_eq!(mbc.read_rom(0x4000), 1);
+
+        mbc.write_rom(0x2000, 0x7F);
+        assert_eq!(mbc.read_rom(0x4000), 0x7F);
+        mbc.write_rom(0x3FFF, 0x45);
+        assert_eq!(mbc.read_rom(0x4000), 0x45);
+        assert_eq!(mbc.read_rom(0x0000), 0x00);
+
+        // Unlike MBC1, all 7 bits take part in the bank 0 check.
+        mbc.write_rom(0x2000, 0x00);
+        assert_eq!(mbc.read_rom(0x4000), 1);
+        mbc.write_rom(0x2000, 0x80);
+        assert_eq!(mbc.read_rom(0x4000), 1);
+    }
+
+    #[test]
+    fn test_ram_banks_are_isolated() {
+        let mut mbc = Mbc3::new(numbered_rom(4), 0x8000, true);
+        mbc.write_rom(0x0000, 0x0A);
+        mbc.write_ram(0xA000, 0x11);
+
+        mbc.write_rom(0x4000, 0x03);
+        assert_eq!(mbc.read_ram(0xA000), 0x00);
+        mbc.write_ram(0xA000, 0x33);
+
+        mbc.write_rom(0x4000, 0x00);
+        assert_eq!(mbc.read_ram(0xA000), 0x11);
+        mbc.write_rom(0x4000, 0x03);
+        assert_eq!(mbc.read_ram(0xA000), 0x33);
+    }
+
+    /// Runs the clock for `seconds` emulated seconds.
+    fn run_seconds(mbc: &mut Mbc3, seconds: u32) {
+        for _ in 0..seconds * CYCLES_PER_SECOND / 128 {
+            mbc.tick(128);
+        }
+    }
+
+    fn latch(mbc: &mut Mbc3) {
+        mbc.write_rom(0x6000, 0x00);
+        mbc.write_rom(0x6000, 0x01);
+    }
+
+    fn read_clock(mbc: &mut Mbc3, register: u8) -> u8 {
+        mbc.write_rom(0x4000, register);
+        mbc.read_ram(0xA000)
+    }
+
+    #[test]
+    fn test_latched_clock_is_stable() {
+        let mut mbc = Mbc3::new(numbered_rom(4), 0, true);
+        mbc.write_rom(0x0000, 0x0A);
+        run_seconds(&mut mbc, 3);
+        latch(&mut mbc);
+        assert_eq!(read_clock(&mut mbc, 0x08), 3);
+
+        run_seconds(&mut mbc, 2);
+        assert_eq!(read_clock(&mut mbc, 0x08), 3);
+        // Writing 0x01 again without 0x00 first does not latch.
+        mbc.write_rom(0x6000, 0x01);
+        assert_eq!(read_clock(&mut mbc, 0x08), 3);
+
+        latch(&mut mbc);
+        assert_eq!(read_clock(&mut mbc, 0x08), 5);
+    }
+
+    #[test]
+    fn test_clock_rolls_over() {
+        let mut mbc = Mbc3::new(numbered_rom(4), 0, true);
+        mbc.write_rom(0x0000, 0x0A);
+        mbc.write_rom(0x4000, 0x08);
+        mbc.write_ram(0xA000, 59);
+        mbc.write_rom(0x4000, 0x09);
+        mbc.write_ram(0xA000, 59);
+        mbc.write_rom(0x4000, 0x0A);
+        mbc.write_ram(0xA000, 23);
+        mbc.write_rom(0x4000, 0x0B);
+        mbc.write_ram(0xA000, 0xFF);
+        mbc.write_rom(0x4000, 0x0C);
+        mbc.write_ram(0xA000, 0x01);
+
+        run_seconds(&mut mbc, 1);
+        latch(&mut mbc);
+        assert_eq!(read_clock(&mut mbc, 0x08), 0);
+        assert_eq!(read_clock(&mut mbc, 0x09), 0);
+        assert_eq!(read_clock(&mut mbc, 0x0A), 0);
+        assert_eq!(read_clock(&mut mbc, 0x0B), 0);
+        assert_eq!(read_clock(&mut mbc, 0x0C), 0x80);
+    }
+
+    #[test]
+    fn test_seconds_carry_into_minutes() {
+        let mut mbc = Mbc3::new(numbered_rom(4), 0, true);
+        mbc.write_rom(0x0000, 0x0A);
+        mbc.write_rom(0x4000, 0x08);
+        mbc.write_ram(0xA000, 58);
+
+        run_seconds(&mut mbc, 2);
+        latch(&mut mbc);
+        assert_eq!(read_clock(&mut mbc, 0x08), 0);
+        assert_eq!(read_clock(&mut mbc, 0x09), 1);
+    }
+
+    #[test]
+    fn test_halted_clock_stops() {
+        let mut mbc = Mbc3::new(numbered_rom(4), 0, true);
+        mbc.write_rom(0x0000, 0x0A);
+        mbc.write_rom(0x4000, 0x0C);
+        mbc.write_ram(0xA000, 0x40);
+
+        run_seconds(&mut mbc, 2);
+        latch(&mut mbc);
+        assert_eq!(read_clock(&mut mbc, 0x08), 0);
+        assert_eq!(read_clock(&mut mbc, 0x0C), 0x40);
+
+        mbc.write_rom(0x4000, 0x0C);
+        mbc.write_ram(0xA000, 0x00);
+        run_seconds(&mut mbc, 1);
+        latch(&mut mbc);
+        assert_eq!(read_clock(&mut mbc, 0x08), 1);
+    }
+
+    #[test]
+    fn test_clock_select_leaves_ram_alone() {
+        let mut mbc = Mbc3::new(numbered_rom(4), 0x8000, true);
+        mbc.write_rom(0x0000, 0x0A);
+        mbc.write_ram(0xA000, 0x11);
+
+        for register in 0x08..=0x0C {
+            mbc.write_rom(0x4000, register);
+            mbc.write_ram(0xA000, 0x99);
+        }
+
+        mbc.write_rom(0x4000, 0x00);
+        assert_eq!(mbc.read_ram(0xA000), 0x11);
+    }
+
+    #[test]
+    fn test_no_clock_without_rtc() {
+        let mut rom = numbered_rom(4);
+        rom[CARTRIDGE_TYPE] = 0x13;
+        rom[RAM_SIZE] = 0x03;
+        let mut cartridge = from_rom(rom);
+        cartridge.write_rom(0x0000, 0x0A);
+        cartridge.write_ram(0xA000, 0x11);
+
+        for register in 0x08..=0x0C {
+            cartridge.write_rom(0x4000, register);
+            cartridge.write_ram(0xA000, 0x01);
+        }
+        for _ in 0..2 * CYCLES_PER_SECOND / 128 {
+            cartridge.tick(128);
+        }
+        cartridge.write_rom(0x6000, 0x00);
+        cartridge.write_rom(0x6000, 0x01);
+        for register in 0x08..=0x0C {
+            cartridge.write_rom(0x4000, register);
+            assert_eq!(cartridge.read_ram(0xA000), 0xFF, "register {:02X}", register);
+        }
+
+        cartridge.write_rom(0x4000, 0x00);
+        assert_eq!(cartridge.read_ram(0xA000), 0x11);
+    }
+}
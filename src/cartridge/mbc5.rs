@@ -0,0 +1,161 @@
+use super::{Cartridge, ROM_BANK_SIZE, RAM_BANK_SIZE};
+
+/// The MBC5 bank controller, for up to 8 MiB of ROM and 128 KiB of RAM.
+pub struct Mbc5 {
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+    ram_enabled: bool,
+    /// The 9-bit ROM bank, its low byte written at 0x2000-0x2FFF and bit 8
+    /// at 0x3000-0x3FFF.
+    rom_bank: u16,
+    /// The 4-bit RAM bank written at 0x4000-0x5FFF.
+    ram_bank: u8,
+    /// Rumble cartridges wire bit 3 of the RAM bank register to the motor.
+    has_rumble: bool,
+    rumble: bool,
+}
+
+impl Mbc5 {
+    pub fn new(rom: Vec<u8>, ram_size: usize) -> Mbc5 {
+        Mbc5 {
+            rom,
+            ram: vec![0; ram_size],
+            ram_enabled: false,
+            rom_bank: 1,
+            ram_bank: 0,
+            has_rumble: false,
+            rumble: false,
+        }
+    }
+
+    pub fn with_rumble(rom: Vec<u8>, ram_size: usize) -> Mbc5 {
+        Mbc5 {
+            has_rumble: true,
+            ..Mbc5::new(rom, ram_size)
+        }
+    }
+
+    fn rom_bank_count(&self) -> usize {
+        (self.rom.len() / ROM_BANK_SIZE).max(1)
+    }
+
+    fn ram_offset(&self, address: u16) -> usize {
+        (self.ram_bank as usize * RAM_BANK_SIZE + (address - 0xA000) as usize) % self.ram.len()
+    }
+}
+
+impl Cartridge for Mbc5 {
+    fn read_rom(&self, address: u16) -> u8 {
+        let offset = match address {
+            0x0000..=0x3FFF => address as usize,
+            _ => (self.rom_bank as usize % self.rom_bank_count()) * ROM_BANK_SIZE + (address - 0x4000) as usize,
+        };
+        self.rom.get(offset).copied().unwrap_or(0xFF)
+    }
+
+    fn write_rom(&mut self, address: u16, value: u8) {
+        match address {
+            0x0000..=0x1FFF => self.ram_enabled = value & 0x0F == 0x0A,
+            // Bank 0 is a valid choice here, unlike on the older controllers.
+            0x2000..=0x2FFF => self.rom_bank = (self.rom_bank & 0x100) | value as u16,
+            0x3000..=0x3FFF => self.rom_bank = (self.rom_bank & 0xFF) | (value as u16 & 0x01) << 8,
+            0x4000..=0x5FFF if self.has_rumble => { self.rumble = value & 0x08 != 0; self.ram_bank = value & 0x07; },
+            0x4000..=0x5FFF => self.ram_bank = value & 0x0F,
+            _ => {},
+        }
+    }
+
+    /// Disabled or missing RAM reads 0xFF.
+    fn read_ram(&self, address: u16) -> u8 {
+        if !self.ram_enabled || self.ram.is_empty() {
+            return 0xFF;
+        }
+        self.ram[self.ram_offset(address)]
+    }
+
+    fn write_ram(&mut self, address: u16, value: u8) {
+        if self.ram_enabled && !self.ram.is_empty() {
+            let offset = self.ram_offset(address);
+            self.ram[offset] = value;
+        }
+    }
+
+    fn rom(&self) -> &[u8] {
+        &self.rom
+    }
+
+    fn ram(&self) -> &[u8] {
+        &self.ram
+    }
+
+    fn ram_mut(&mut self) -> &mut [u8] {
+        &mut self.ram
+    }
+
+    fn rumble(&self) -> bool {
+        self.rumble
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cartridge::numbered_rom;
+
+    #[test]
+    fn test_rom_banks_of_8_mib() {
+        let mut mbc = Mbc5::new(numbered_rom(512), 0);
+        assert_eq!(mbc.read_rom(0x4000), 1);
+
+        mbc.write_rom(0x2000, 0x00);
+        mbc.write_rom(0x3000, 0x01);
+        assert_eq!((mbc.read_rom(0x4000), mbc.read_rom(0x4001)), (0x00, 0x01));
+        mbc.write_rom(0x2FFF, 0xFF);
+        assert_eq!((mbc.read_rom(0x4000), mbc.read_rom(0x4001)), (0xFF, 0x01));
+
+        // Only bit 0 of the high register is used.
+        mbc.write_rom(0x3FFF, 0xFE);
+        assert_eq!((mbc.read_rom(0x4000), mbc.read_rom(0x4001)), (0xFF, 0x00));
+        assert_eq!(mbc.read_rom(0x0000), 0x00);
+    }
+
+    #[test]
+    fn test_bank_0_is_selectable() {
+        let mut mbc = Mbc5::new(numbered_rom(4), 0);
+        mbc.write_rom(0x2000, 0x00);
+        assert_eq!(mbc.read_rom(0x4000), 0);
+
+        // Banks past the end of the ROM wrap.
+        mbc.write_rom(0x2000, 0x06);
+        assert_eq!(mbc.read_rom(0x4000), 2);
+    }
+
+    #[test]
+    fn test_ram_banks() {
+        let mut mbc = Mbc5::new(numbered_rom(4), 0x20000);
+        assert_eq!(mbc.read_ram(0xA000), 0xFF);
+        mbc.write_rom(0x0000, 0x0A);
+        for bank in 0..16 {
+            mbc.write_rom(0x4000, bank);
+            mbc.write_ram(0xBFFF, bank);
+        }
+        for bank in 0..16 {
+            mbc.write_rom(0x4000, bank);
+            assert_eq!(mbc.read_ram(0xBFFF), bank, "bank {}", bank);
+        }
+    }
+
+    #[test]
+    fn test_rumble_bit_is_not_a_ram_bank() {
+        let mut mbc = Mbc5::with_rumble(numbered_rom(4), 0x8000);
+        mbc.write_rom(0x0000, 0x0A);
+        mbc.write_rom(0x4000, 0x01);
+        mbc.write_ram(0xA000, 0x11);
+
+        mbc.write_rom(0x4000, 0x09);
+        assert!(mbc.rumble());
+        assert_eq!(mbc.read_ram(0xA000), 0x11);
+        mbc.write_rom(0x4000, 0x01);
+        assert!(!mbc.rumble());
+    }
+}
@@ -1,27 +1,18 @@
 use std::fmt::{Formatter, Display};
 
+use crate::cartridge::{self, Cartridge, RomError};
+use crate::instructions::{decode, CbOperation, Condition, Instruction, Operand, Register, RegisterPair};
+use crate::joypad::Button;
+use crate::memory::io::IF;
+use crate::memory::MemoryBus;
+use crate::ppu::{FrameSink, Ppu};
+
 
 const ZERO_FLAG_BYTE_POSITION: u8 = 7;
 const SUBTRACT_FLAG_BYTE_POSITION: u8 = 6;
 const HALF_CARRY_FLAG_BYTE_POSITION: u8 = 5;
 const CARRY_FLAG_BYTE_POSITION: u8 = 4;
 
-struct MemoryBus {
-    memory: [u8; 0xFFFF + 1],
-}
-
-impl MemoryBus {
-    pub fn new() -> MemoryBus {
-        MemoryBus {
-            memory: [0; 0xFFFF + 1],
-        }
-    }
-
-    pub fn set_range(&mut self, start: usize, len: usize, values: &[u8]) {
-       self.memory[start..(start + len) as usize].copy_from_slice(values);
-    }
-}
-
 #[derive(Debug)]
 struct FlagsRegister {
     pub zero: bool,
@@ -80,24 +71,25 @@ impl Registers {
     }
 
     pub fn get_register_word(&self, codes: (usize, usize)) -> u16 {
-        self.registers[codes.0] as u16 + ((self.registers[codes.1] as u16) << 8)
+        ((self.registers[codes.0] as u16) << 8) + self.registers[codes.1] as u16
     }
 
     pub fn set_register_word(&mut self, codes: (usize, usize), value: u16) {
-        self.registers[codes.0] = value as u8;
-        self.registers[codes.1] = (value >> 8) as u8;
+        self.set_register(codes.0, (value >> 8) as u8);
+        self.set_register(codes.1, value as u8);
     }
 
     pub fn get_register(&self, register: usize) -> u8 {
         self.registers[register]
     }
 
+    /// The low nibble of F does not exist in hardware and always reads back as zero.
     pub fn set_register(&mut self, register: usize, value: u8) {
-        self.registers[register] = value;
+        self.registers[register] = if register == "f".code() { value & 0xF0 } else { value };
     }
 
     pub fn get_flags(&self) -> FlagsRegister {
-        FlagsRegister::from(self.registers[0xF])
+        FlagsRegister::from(self.registers["f".code()])
     }
 
     pub fn set_flags(&mut self, zero: Option<bool>, subtraction: Option<bool>, half_carry: Option<bool>, carry: Option<bool>) {
@@ -108,7 +100,7 @@ impl Registers {
             half_carry: half_carry.unwrap_or(c_flag.half_carry),
             carry: carry.unwrap_or(c_flag.carry),
         };
-        self.registers[0xF] = u8::from(flags);
+        self.registers["f".code()] = u8::from(flags);
     }
 }
 
@@ -129,13 +121,105 @@ impl ToRegisterCode for &str {
             _ => panic!("Unknown register code"),
         }
     }
+
+    fn code(&self) -> usize {
+        match self.to_lowercase().as_str() {
+            "a" => 0,
+            "f" => 1,
+            "b" => 2,
+            "c" => 3,
+            "d" => 4,
+            "e" => 5,
+            "h" => 6,
+            "l" => 7,
+            _ => Self::codes(self).0,
+        }
+    }
+}
+
+/// The index of an 8-bit register in the register file.
+fn register_code(register: Register) -> usize {
+    match register {
+        Register::A => "a".code(),
+        Register::B => "b".code(),
+        Register::C => "c".code(),
+        Register::D => "d".code(),
+        Register::E => "e".code(),
+        Register::H => "h".code(),
+        Register::L => "l".code(),
+    }
+}
+
+/// The extra T-cycles a taken branch costs over its `CYCLES` entry, which
+/// for conditional branches is the not-taken cost.
+fn branch_penalty(condition: Option<Condition>, penalty: u8) -> u8 {
+    if condition.is_some() { penalty } else { 0 }
+}
+
+/// T-cycles taken by each opcode. Conditional jumps, calls and returns are
+/// listed with their not-taken cost, the handler adding the extra cycles when
+/// the branch is taken, and 0xCB defers to `CB_CYCLES`.
+const CYCLES: [u8; 256] = [
+//  x0  x1  x2  x3  x4  x5  x6  x7  x8  x9  xA  xB  xC  xD  xE  xF
+     4, 12,  8,  8,  4,  4,  8,  4, 20,  8,  8,  8,  4,  4,  8,  4, // 0x
+     4, 12,  8,  8,  4,  4,  8,  4, 12,  8,  8,  8,  4,  4,  8,  4, // 1x
+     8, 12,  8,  8,  4,  4,  8,  4,  8,  8,  8,  8,  4,  4,  8,  4, // 2x
+     8, 12,  8,  8, 12, 12, 12,  4,  8,  8,  8,  8,  4,  4,  8,  4, // 3x
+     4,  4,  4,  4,  4,  4,  8,  4,  4,  4,  4,  4,  4,  4,  8,  4, // 4x
+     4,  4,  4,  4,  4,  4,  8,  4,  4,  4,  4,  4,  4,  4,  8,  4, // 5x
+     4,  4,  4,  4,  4,  4,  8,  4,  4,  4,  4,  4,  4,  4,  8,  4, // 6x
+     8,  8,  8,  8,  8,  8,  4,  8,  4,  4,  4,  4,  4,  4,  8,  4, // 7x
+     4,  4,  4,  4,  4,  4,  8,  4,  4,  4,  4,  4,  4,  4,  8,  4, // 8x
+     4,  4,  4,  4,  4,  4,  8,  4,  4,  4,  4,  4,  4,  4,  8,  4, // 9x
+     4,  4,  4,  4,  4,  4,  8,  4,  4,  4,  4,  4,  4,  4,  8,  4, // Ax
+     4,  4,  4,  4,  4,  4,  8,  4,  4,  4,  4,  4,  4,  4,  8,  4, // Bx
+     8, 12, 12, 16, 12, 16,  8, 16,  8, 16, 12,  0, 12, 24,  8, 16, // Cx
+     8, 12, 12,  0, 12, 16,  8, 16,  8, 16, 12,  0, 12,  0,  8, 16, // Dx
+    12, 12,  8,  0,  0, 16,  8, 16, 16,  4, 16,  0,  0,  0,  8, 16, // Ex
+    12, 12,  8,  4,  0, 16,  8, 16, 12,  8, 16,  4,  0,  0,  8, 16, // Fx
+];
+
+/// T-cycles taken by each 0xCB-prefixed opcode, prefix included. Register
+/// forms take 8 cycles, (HL) forms 16 except BIT which only reads.
+const CB_CYCLES: [u8; 256] = {
+    let mut cycles = [8; 256];
+    let mut opcode = 0;
+    while opcode < 256 {
+        if opcode & 0b111 == 6 {
+            cycles[opcode] = if opcode >> 6 == 1 { 12 } else { 16 };
+        }
+        opcode += 1;
+    }
+    cycles
+};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CpuError {
+    /// An undefined opcode was executed; the CPU is locked up until reset.
+    IllegalOpcode { opcode: u8, address: u16 },
+}
+
+impl Display for CpuError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CpuError::IllegalOpcode { opcode, address } => write!(f, "illegal opcode {:02X} at {:04X}, CPU locked", opcode, address),
+        }
+    }
 }
 
+impl std::error::Error for CpuError {}
+
 pub struct Cpu {
     registers: Registers,
     sp: u16,
     pc: u16,
     ram: MemoryBus,
+    ime: bool,
+    ime_scheduled: bool,
+    halted: bool,
+    halt_bug: bool,
+    stopped: bool,
+    locked: Option<CpuError>,
     dump_registers_after: Option<u8>,
 }
 
@@ -146,75 +230,657 @@ impl Cpu {
             sp: 0,
             pc: 0,
             ram: MemoryBus::new(),
+            ime: false,
+            ime_scheduled: false,
+            halted: false,
+            halt_bug: false,
+            stopped: false,
+            locked: None,
             dump_registers_after: None,
         }
     }
 
-    pub fn load_rom(&mut self, rom_in: Vec<u8>) {
-        let mut rom = [0; 0x3FFF + 1];
-        rom.copy_from_slice(&rom_in);
-        self.ram.set_range(0x0000, 0x3FFF + 1, &rom);
+    /// Inserts the cartridge `rom` describes, keeping the whole image.
+    pub fn load_rom(&mut self, rom: Vec<u8>) -> Result<(), RomError> {
+        cartridge::check_rom_size(&rom)?;
+        self.ram.load_cartridge(cartridge::from_rom(rom));
+        Ok(())
+    }
+
+    /// The inserted cartridge, for getting at its ROM and RAM.
+    pub fn cartridge(&self) -> &dyn Cartridge {
+        self.ram.cartridge()
+    }
+
+    pub fn cartridge_mut(&mut self) -> &mut dyn Cartridge {
+        self.ram.cartridge_mut()
+    }
+
+    /// The PPU, for getting at the picture.
+    pub fn ppu(&self) -> &Ppu {
+        self.ram.ppu()
+    }
+
+    pub fn vram(&self) -> &[u8; 0x2000] {
+        self.ram.vram()
+    }
+
+    pub fn oam(&self) -> &[u8; 0xA0] {
+        self.ram.oam()
+    }
+
+    /// Calls `sink` with each completed frame, or stops with `None`.
+    pub fn set_frame_sink(&mut self, sink: Option<FrameSink>) {
+        self.ram.set_frame_sink(sink);
+    }
+
+    /// Lets the CPU reach VRAM and OAM whatever the PPU is doing, for
+    /// debugging.
+    pub fn set_ppu_access_restricted(&mut self, restricted: bool) {
+        self.ram.set_ppu_access_restricted(restricted);
+    }
+
+    /// Calls `callback` with the motor state on each change of a rumble
+    /// cartridge's motor.
+    pub fn set_rumble_callback(&mut self, callback: Box<dyn FnMut(bool)>) {
+        self.ram.set_rumble_callback(callback);
+    }
+
+    pub fn pc(&self) -> u16 {
+        self.pc
+    }
+
+    pub fn sp(&self) -> u16 {
+        self.sp
+    }
+
+    pub fn reg_a(&self) -> u8 {
+        self.registers.get_register("a".code())
+    }
+
+    pub fn reg_f(&self) -> u8 {
+        self.registers.get_register("f".code())
+    }
+
+    pub fn reg_b(&self) -> u8 {
+        self.registers.get_register("b".code())
+    }
+
+    pub fn reg_c(&self) -> u8 {
+        self.registers.get_register("c".code())
+    }
+
+    pub fn reg_d(&self) -> u8 {
+        self.registers.get_register("d".code())
+    }
+
+    pub fn reg_e(&self) -> u8 {
+        self.registers.get_register("e".code())
+    }
+
+    pub fn reg_h(&self) -> u8 {
+        self.registers.get_register("h".code())
+    }
+
+    pub fn reg_l(&self) -> u8 {
+        self.registers.get_register("l".code())
+    }
+
+    pub fn reg_af(&self) -> u16 {
+        self.registers.get_register_word("af".codes())
+    }
+
+    pub fn reg_bc(&self) -> u16 {
+        self.registers.get_register_word("bc".codes())
+    }
+
+    pub fn reg_de(&self) -> u16 {
+        self.registers.get_register_word("de".codes())
+    }
+
+    pub fn reg_hl(&self) -> u16 {
+        self.registers.get_register_word("hl".codes())
+    }
+
+    /// Reads a byte as the CPU would see it, through the memory bus.
+    pub fn read_mem(&self, address: u16) -> u8 {
+        self.ram.read_byte(address)
+    }
+
+    /// Writes a byte as the CPU would, through the memory bus.
+    pub fn write_mem(&mut self, address: u16, value: u8) {
+        self.ram.write_byte(address, value);
+    }
+
+    /// Puts the CPU and I/O registers in the state the DMG boot ROM hands
+    /// over to the cartridge with, at its entry point 0x0100.
+    pub fn reset_post_boot(&mut self) {
+        self.registers.set_register_word("af".codes(), 0x01B0);
+        self.registers.set_register_word("bc".codes(), 0x0013);
+        self.registers.set_register_word("de".codes(), 0x00D8);
+        self.registers.set_register_word("hl".codes(), 0x014D);
+        self.sp = 0xFFFE;
+        self.pc = 0x0100;
+        self.ram.reset_io();
+    }
+
+    /// Runs `boot_rom` from 0x0000 instead of starting in its post-boot state.
+    pub fn load_boot_rom(&mut self, boot_rom: &[u8; 0x100]) {
+        self.ram.load_boot_rom(boot_rom);
+        self.reset();
+    }
+
+    /// Restarts the loaded game: RAM and CPU state are cleared, then the boot
+    /// ROM runs again or, without one, the post-boot state is restored.
+    pub fn reset(&mut self) {
+        self.ram.reset();
+        self.ime = false;
+        self.ime_scheduled = false;
+        self.halted = false;
+        self.halt_bug = false;
+        self.stopped = false;
+        self.locked = None;
+        if self.ram.boot_rom_mapped() {
+            self.registers = Registers::new();
+            self.sp = 0;
+            self.pc = 0;
+        } else {
+            self.reset_post_boot();
+        }
     }
 
+    /// Reads the byte at PC and moves PC past it.
     fn fetch_byte(&mut self) -> u8 {
-        let byte = self.ram.memory[self.pc as usize];
+        let byte = self.ram.read_byte(self.pc);
+        self.pc = self.pc.wrapping_add(1);
         byte
     }
 
-    fn fetch_word(&mut self) -> u16 {
-        let byte = self.fetch_byte();
-        let word = byte as u16 + ((self.ram.memory[self.pc as usize + 1] as u16) << 8);
-        word
-    }
-
-    fn inc_reg_byte(&mut self, register: usize) {
-        let value = self.registers.get_register(register);
-        let (result, carry) = value.overflowing_add(1);
-        self.registers.set_register(register, result);
-        self.registers.set_flags(Some(result == 0), Some(false), Some(carry), None);
-    }
-
-    fn dec_reg_byte(&mut self, register: usize) {
-        let value = self.registers.get_register(register);
-        let (result, carry) = value.overflowing_sub(1);
-        self.registers.set_register(register, result);
-        self.registers.set_flags(Some(result == 0), Some(true), Some(carry), None);
-    }
-
-    pub fn execute(&mut self) {
-        let opcode = self.ram.memory[self.pc as usize];
-        let increment = match opcode & 0xFF {
-            0x00 => 1,
-            0x01 => { let nn = self.fetch_word(); self.registers.set_register_word("bc".codes(), nn); 3 },
-            0x02 => { let v = self.registers.get_register_word("bc".codes()); self.ram.memory[v as usize] = self.registers.get_register("a".code()); 1 },
-            0x03 => { let v = self.registers.get_register_word("bc".codes()); self.registers.set_register_word("bc".codes(), v.wrapping_add(1)); 1 },
-            0x04 => { self.inc_reg_byte("b".code()); 1},
-            0x05 => { self.dec_reg_byte("b".code()); 1},
-            0x06 => { let v = self.fetch_byte(); self.registers.set_register("b".code(), v); 2 },
-            0x07 => todo!("RLCA"),
-            0x08 => { let nn = self.fetch_word(); self.sp = (nn & 0xFF) as u16 + (nn as u16) << 8;  3},
-            _=> {
-                println!("Unknown opcode: {:X}", opcode);
-                panic!("Unknown opcode");
+    fn read_pair(&self, pair: RegisterPair) -> u16 {
+        match pair {
+            RegisterPair::Af => self.registers.get_register_word("af".codes()),
+            RegisterPair::Bc => self.registers.get_register_word("bc".codes()),
+            RegisterPair::De => self.registers.get_register_word("de".codes()),
+            RegisterPair::Hl => self.registers.get_register_word("hl".codes()),
+            RegisterPair::Sp => self.sp,
+        }
+    }
+
+    fn write_pair(&mut self, pair: RegisterPair, value: u16) {
+        match pair {
+            RegisterPair::Af => self.registers.set_register_word("af".codes(), value),
+            RegisterPair::Bc => self.registers.set_register_word("bc".codes(), value),
+            RegisterPair::De => self.registers.set_register_word("de".codes(), value),
+            RegisterPair::Hl => self.registers.set_register_word("hl".codes(), value),
+            RegisterPair::Sp => self.sp = value,
+        }
+    }
+
+    /// The address a memory operand refers to. (HL+) and (HL-) adjust HL as
+    /// a side effect, so each access must resolve the address exactly once.
+    fn operand_address(&mut self, operand: Operand) -> u16 {
+        match operand {
+            Operand::Indirect(pair) => self.read_pair(pair),
+            Operand::HlIncrement => self.hli(),
+            Operand::HlDecrement => self.hld(),
+            Operand::Absolute(address) => address,
+            Operand::High(offset) => 0xFF00 + offset as u16,
+            Operand::HighC => 0xFF00 + self.registers.get_register("c".code()) as u16,
+            Operand::Register(_) | Operand::Immediate(_) => unreachable!("{:?} is not a memory operand", operand),
+        }
+    }
+
+    fn read_operand(&mut self, operand: Operand) -> u8 {
+        match operand {
+            Operand::Register(register) => self.registers.get_register(register_code(register)),
+            Operand::Immediate(value) => value,
+            _ => { let address = self.operand_address(operand); self.ram.read_byte(address) },
+        }
+    }
+
+    fn write_operand(&mut self, operand: Operand, value: u8) {
+        match operand {
+            Operand::Register(register) => self.registers.set_register(register_code(register), value),
+            Operand::Immediate(_) => unreachable!("immediates cannot be written"),
+            _ => { let address = self.operand_address(operand); self.ram.write_byte(address, value) },
+        }
+    }
+
+    fn push_word(&mut self, value: u16) {
+        self.sp = self.sp.wrapping_sub(1);
+        self.ram.write_byte(self.sp, (value >> 8) as u8);
+        self.sp = self.sp.wrapping_sub(1);
+        self.ram.write_byte(self.sp, value as u8);
+    }
+
+    fn pop_word(&mut self) -> u16 {
+        let low = self.ram.read_byte(self.sp);
+        self.sp = self.sp.wrapping_add(1);
+        let high = self.ram.read_byte(self.sp);
+        self.sp = self.sp.wrapping_add(1);
+        ((high as u16) << 8) | low as u16
+    }
+
+    /// ADD HL, rr leaves zero alone; half-carry comes from bit 11 and carry
+    /// from bit 15.
+    fn add_hl(&mut self, value: u16) {
+        let hl = self.registers.get_register_word("hl".codes());
+        let (result, carry) = hl.overflowing_add(value);
+        let half_carry = (hl & 0x0FFF) + (value & 0x0FFF) > 0x0FFF;
+        self.registers.set_register_word("hl".codes(), result);
+        self.registers.set_flags(None, Some(false), Some(half_carry), Some(carry));
+    }
+
+    /// SP plus a signed immediate, shared by ADD SP, r8 and LD HL, SP+r8.
+    /// Half-carry and carry come from the unsigned addition of the low bytes
+    /// whatever the sign of the offset, and zero is always cleared.
+    fn sp_plus_signed(&mut self, offset: u8) -> u16 {
+        let sp = self.sp;
+        let half_carry = (sp & 0x0F) + (offset as u16 & 0x0F) > 0x0F;
+        let carry = (sp & 0xFF) + offset as u16 > 0xFF;
+        self.registers.set_flags(Some(false), Some(false), Some(half_carry), Some(carry));
+        sp.wrapping_add(offset as i8 as u16)
+    }
+
+    /// Pushes the address of the next instruction and jumps to `address`.
+    fn call(&mut self, address: u16) {
+        self.push_word(self.pc);
+        self.pc = address;
+    }
+
+    /// RST is a one-byte call to one of the fixed vectors 0x00, 0x08, ... 0x38.
+    fn rst(&mut self, vector: u16) {
+        self.call(vector);
+    }
+
+    /// Whether a jump, call or return is taken; unconditional ones always are.
+    fn condition(&self, condition: Option<Condition>) -> bool {
+        let flags = self.registers.get_flags();
+        match condition {
+            None => true,
+            Some(Condition::NotZero) => !flags.zero,
+            Some(Condition::Zero) => flags.zero,
+            Some(Condition::NotCarry) => !flags.carry,
+            Some(Condition::Carry) => flags.carry,
+        }
+    }
+
+    /// The interrupt master enable, as set by EI/RETI and cleared by DI and
+    /// interrupt dispatch. A pending EI is not reflected until it lands.
+    pub fn ime(&self) -> bool {
+        self.ime
+    }
+
+    /// Interrupts both requested in IF and enabled in IE, whatever IME says.
+    pub fn interrupts_pending(&self) -> u8 {
+        self.ram.read_byte(IF) & self.ram.read_byte(0xFFFF) & 0x1F
+    }
+
+    /// Services the highest priority pending interrupt (VBlank first): its IF
+    /// bit is acknowledged, IME cleared and PC pushed before jumping to the
+    /// vector at 0x40 + 8 * bit. Returns the 20 T-cycles the sequence takes.
+    fn service_interrupt(&mut self) -> u8 {
+        let bit = self.interrupts_pending().trailing_zeros() as u8;
+        let interrupt_flag = self.ram.read_byte(IF);
+        self.ram.write_byte(IF, interrupt_flag & !(1 << bit));
+        self.ime = false;
+        self.call(0x0040 + 8 * bit as u16);
+        20
+    }
+
+    /// With IME clear and an interrupt already pending, HALT does not halt:
+    /// instead the next opcode fetch fails to increment PC, so the byte after
+    /// HALT is executed twice.
+    fn halt(&mut self) {
+        if !self.ime && self.interrupts_pending() != 0 {
+            self.halt_bug = true;
+        } else {
+            self.halted = true;
+        }
+    }
+
+    /// Returns HL and post-increments it, for the (HL+) addressing mode.
+    fn hli(&mut self) -> u16 {
+        let hl = self.registers.get_register_word("hl".codes());
+        self.registers.set_register_word("hl".codes(), hl.wrapping_add(1));
+        hl
+    }
+
+    /// Returns HL and post-decrements it, for the (HL-) addressing mode.
+    fn hld(&mut self) -> u16 {
+        let hl = self.registers.get_register_word("hl".codes());
+        self.registers.set_register_word("hl".codes(), hl.wrapping_sub(1));
+        hl
+    }
+
+    fn add_a(&mut self, value: u8) {
+        self.adc_a(value, false);
+    }
+
+    /// The carry-in takes part in both the nibble and the byte sums, so the
+    /// flags cannot be derived from two separate additions.
+    fn adc_a(&mut self, value: u8, carry_in: bool) {
+        let a = self.registers.get_register("a".code());
+        let carry_in = carry_in as u8;
+        let result = a.wrapping_add(value).wrapping_add(carry_in);
+        let half_carry = (a & 0x0F) + (value & 0x0F) + carry_in > 0x0F;
+        let carry = a as u16 + value as u16 + carry_in as u16 > 0xFF;
+        self.registers.set_register("a".code(), result);
+        self.registers.set_flags(Some(result == 0), Some(false), Some(half_carry), Some(carry));
+    }
+
+    fn sub_a(&mut self, value: u8) {
+        self.sbc_a(value, false);
+    }
+
+    fn sbc_a(&mut self, value: u8, carry_in: bool) {
+        let result = self.subtract_from_a(value, carry_in);
+        self.registers.set_register("a".code(), result);
+    }
+
+    /// CP only keeps the flags of the subtraction.
+    fn cp_a(&mut self, value: u8) {
+        self.subtract_from_a(value, false);
+    }
+
+    /// Computes A - value - carry and sets the flags, leaving A untouched.
+    /// The borrow-in is part of both the nibble and byte comparisons.
+    fn subtract_from_a(&mut self, value: u8, carry_in: bool) -> u8 {
+        let a = self.registers.get_register("a".code());
+        let carry_in = carry_in as u8;
+        let result = a.wrapping_sub(value).wrapping_sub(carry_in);
+        let half_carry = (a & 0x0F) < (value & 0x0F) + carry_in;
+        let carry = (a as u16) < value as u16 + carry_in as u16;
+        self.registers.set_flags(Some(result == 0), Some(true), Some(half_carry), Some(carry));
+        result
+    }
+
+    /// AND is the only logical operation that sets half-carry.
+    fn and_a(&mut self, value: u8) {
+        let result = self.registers.get_register("a".code()) & value;
+        self.registers.set_register("a".code(), result);
+        self.registers.set_flags(Some(result == 0), Some(false), Some(true), Some(false));
+    }
+
+    fn xor_a(&mut self, value: u8) {
+        let result = self.registers.get_register("a".code()) ^ value;
+        self.registers.set_register("a".code(), result);
+        self.registers.set_flags(Some(result == 0), Some(false), Some(false), Some(false));
+    }
+
+    fn or_a(&mut self, value: u8) {
+        let result = self.registers.get_register("a".code()) | value;
+        self.registers.set_register("a".code(), result);
+        self.registers.set_flags(Some(result == 0), Some(false), Some(false), Some(false));
+    }
+
+    /// Rotates `value` left by one bit, returning the result and the bit
+    /// shifted out. Through carry, the old carry flag is shifted in instead of
+    /// bit 7.
+    fn rotate_left(&self, value: u8, through_carry: bool) -> (u8, bool) {
+        let bit_in = if through_carry { self.registers.get_flags().carry as u8 } else { value >> 7 };
+        ((value << 1) | bit_in, value & 0x80 != 0)
+    }
+
+    /// Rotates `value` right by one bit, returning the result and the bit
+    /// shifted out. Through carry, the old carry flag is shifted in instead of
+    /// bit 0.
+    fn rotate_right(&self, value: u8, through_carry: bool) -> (u8, bool) {
+        let bit_in = if through_carry { self.registers.get_flags().carry as u8 } else { value & 0x01 };
+        ((value >> 1) | (bit_in << 7), value & 0x01 != 0)
+    }
+
+    /// RLCA, RRCA, RLA and RRA. Unlike the CB-prefixed rotates these always
+    /// clear the zero flag.
+    fn rotate_a(&mut self, left: bool, through_carry: bool) {
+        let a = self.registers.get_register("a".code());
+        let (result, carry) = if left { self.rotate_left(a, through_carry) } else { self.rotate_right(a, through_carry) };
+        self.registers.set_register("a".code(), result);
+        self.registers.set_flags(Some(false), Some(false), Some(false), Some(carry));
+    }
+
+    /// Adjusts A to packed BCD after an addition or subtraction, going by the
+    /// subtract, half-carry and carry flags left by that operation.
+    fn daa(&mut self) {
+        let flags = self.registers.get_flags();
+        let mut a = self.registers.get_register("a".code());
+        let mut carry = flags.carry;
+
+        if !flags.subtraction {
+            if flags.carry || a > 0x99 {
+                a = a.wrapping_add(0x60);
+                carry = true;
+            }
+            if flags.half_carry || a & 0x0F > 0x09 {
+                a = a.wrapping_add(0x06);
+            }
+        } else {
+            if flags.carry {
+                a = a.wrapping_sub(0x60);
             }
+            if flags.half_carry {
+                a = a.wrapping_sub(0x06);
+            }
+        }
+
+        self.registers.set_register("a".code(), a);
+        self.registers.set_flags(Some(a == 0), None, Some(false), Some(carry));
+    }
+
+    /// INC never touches carry; half-carry is set when the low nibble overflows.
+    fn inc_byte(&mut self, value: u8) -> u8 {
+        let result = value.wrapping_add(1);
+        self.registers.set_flags(Some(result == 0), Some(false), Some(value & 0x0F == 0x0F), None);
+        result
+    }
+
+    /// DEC never touches carry; half-carry is set when the low nibble borrows.
+    fn dec_byte(&mut self, value: u8) -> u8 {
+        let result = value.wrapping_sub(1);
+        self.registers.set_flags(Some(result == 0), Some(true), Some(value & 0x0F == 0x00), None);
+        result
+    }
+
+    fn inc_operand(&mut self, operand: Operand) {
+        let value = self.read_operand(operand);
+        let result = self.inc_byte(value);
+        self.write_operand(operand, result);
+    }
+
+    fn dec_operand(&mut self, operand: Operand) {
+        let value = self.read_operand(operand);
+        let result = self.dec_byte(value);
+        self.write_operand(operand, result);
+    }
+
+    /// The CB-prefixed rotates, shifts and SWAP. They share the accumulator
+    /// rotate core but set zero from the result, and (HL) is read-modify-write.
+    fn rotate_shift_operand(&mut self, operation: CbOperation, operand: Operand) {
+        let value = self.read_operand(operand);
+        let (result, carry) = match operation {
+            CbOperation::Rlc => self.rotate_left(value, false),
+            CbOperation::Rrc => self.rotate_right(value, false),
+            CbOperation::Rl => self.rotate_left(value, true),
+            CbOperation::Rr => self.rotate_right(value, true),
+            CbOperation::Sla => (value << 1, value & 0x80 != 0),
+            CbOperation::Sra => ((value >> 1) | (value & 0x80), value & 0x01 != 0),
+            CbOperation::Srl => (value >> 1, value & 0x01 != 0),
+            CbOperation::Swap => (value.rotate_left(4), false),
+            operation => unreachable!("CB {:?} is not a rotate or shift", operation),
         };
+        self.write_operand(operand, result);
+        self.registers.set_flags(Some(result == 0), Some(false), Some(false), Some(carry));
+    }
+
+    /// BIT only reads its operand; zero reflects the tested bit being clear.
+    fn bit_operand(&mut self, bit: u8, operand: Operand) {
+        let value = self.read_operand(operand);
+        self.registers.set_flags(Some(value & (1 << bit) == 0), Some(false), Some(true), None);
+    }
+
+    fn res_operand(&mut self, bit: u8, operand: Operand) {
+        let value = self.read_operand(operand);
+        self.write_operand(operand, value & !(1 << bit));
+    }
+
+    fn set_operand(&mut self, bit: u8, operand: Operand) {
+        let value = self.read_operand(operand);
+        self.write_operand(operand, value | (1 << bit));
+    }
+
+    fn execute_cb(&mut self, operation: CbOperation, operand: Operand) {
+        match operation {
+            CbOperation::Bit(bit) => self.bit_operand(bit, operand),
+            CbOperation::Res(bit) => self.res_operand(bit, operand),
+            CbOperation::Set(bit) => self.set_operand(bit, operand),
+            operation => self.rotate_shift_operand(operation, operand),
+        }
+    }
+
+    /// Presses a button, requesting the joypad interrupt and leaving STOP
+    /// when it pulls a P1 line low.
+    pub fn press_button(&mut self, button: Button) {
+        if self.ram.io.joypad.press(button) {
+            let interrupt_flag = self.ram.read_byte(IF);
+            self.ram.write_byte(IF, interrupt_flag | 0x10);
+            self.stopped = false;
+        }
+    }
+
+    pub fn release_button(&mut self, button: Button) {
+        self.ram.io.joypad.release(button);
+    }
+
+    /// Runs a decoded instruction, returning the T-cycles a taken branch adds
+    /// to its `CYCLES` entry.
+    fn execute(&mut self, instruction: Instruction) -> u8 {
+        match instruction {
+            Instruction::Nop => {},
+            Instruction::Stop => self.stopped = true,
+            Instruction::Halt => self.halt(),
+            Instruction::Di => { self.ime = false; self.ime_scheduled = false },
+            Instruction::Ei => self.ime_scheduled = true,
+            Instruction::Ld(target, source) => { let v = self.read_operand(source); self.write_operand(target, v) },
+            Instruction::Ld16(pair, nn) => self.write_pair(pair, nn),
+            Instruction::LdAbsoluteSp(nn) => self.ram.write_word(nn, self.sp),
+            Instruction::LdSpHl => self.sp = self.registers.get_register_word("hl".codes()),
+            Instruction::LdHlSpOffset(e) => { let v = self.sp_plus_signed(e as u8); self.registers.set_register_word("hl".codes(), v) },
+            Instruction::Push(pair) => { let v = self.read_pair(pair); self.push_word(v) },
+            Instruction::Pop(pair) => { let v = self.pop_word(); self.write_pair(pair, v) },
+            Instruction::Add(operand) => { let v = self.read_operand(operand); self.add_a(v) },
+            Instruction::Adc(operand) => { let v = self.read_operand(operand); let c = self.registers.get_flags().carry; self.adc_a(v, c) },
+            Instruction::Sub(operand) => { let v = self.read_operand(operand); self.sub_a(v) },
+            Instruction::Sbc(operand) => { let v = self.read_operand(operand); let c = self.registers.get_flags().carry; self.sbc_a(v, c) },
+            Instruction::And(operand) => { let v = self.read_operand(operand); self.and_a(v) },
+            Instruction::Xor(operand) => { let v = self.read_operand(operand); self.xor_a(v) },
+            Instruction::Or(operand) => { let v = self.read_operand(operand); self.or_a(v) },
+            Instruction::Cp(operand) => { let v = self.read_operand(operand); self.cp_a(v) },
+            Instruction::Inc(operand) => self.inc_operand(operand),
+            Instruction::Dec(operand) => self.dec_operand(operand),
+            Instruction::Inc16(pair) => { let v = self.read_pair(pair); self.write_pair(pair, v.wrapping_add(1)) },
+            Instruction::Dec16(pair) => { let v = self.read_pair(pair); self.write_pair(pair, v.wrapping_sub(1)) },
+            Instruction::AddHl(pair) => { let v = self.read_pair(pair); self.add_hl(v) },
+            Instruction::AddSp(e) => self.sp = self.sp_plus_signed(e as u8),
+            Instruction::Rlca => self.rotate_a(true, false),
+            Instruction::Rrca => self.rotate_a(false, false),
+            Instruction::Rla => self.rotate_a(true, true),
+            Instruction::Rra => self.rotate_a(false, true),
+            Instruction::Daa => self.daa(),
+            Instruction::Cpl => { let a = self.registers.get_register("a".code()); self.registers.set_register("a".code(), !a); self.registers.set_flags(None, Some(true), Some(true), None) },
+            Instruction::Scf => self.registers.set_flags(None, Some(false), Some(false), Some(true)),
+            Instruction::Ccf => { let carry = self.registers.get_flags().carry; self.registers.set_flags(None, Some(false), Some(false), Some(!carry)) },
+            Instruction::Jp(condition, nn) => if self.condition(condition) { self.pc = nn; return branch_penalty(condition, 4) },
+            Instruction::JpHl => self.pc = self.registers.get_register_word("hl".codes()),
+            Instruction::Jr(condition, e) => if self.condition(condition) { self.pc = self.pc.wrapping_add(e as u16); return branch_penalty(condition, 4) },
+            Instruction::Call(condition, nn) => if self.condition(condition) { self.call(nn); return branch_penalty(condition, 12) },
+            Instruction::Ret(condition) => if self.condition(condition) { self.pc = self.pop_word(); return branch_penalty(condition, 12) },
+            Instruction::Reti => { self.pc = self.pop_word(); self.ime = true },
+            Instruction::Rst(vector) => self.rst(vector),
+            Instruction::Cb(operation, operand) => self.execute_cb(operation, operand),
+            Instruction::Illegal(opcode) => unreachable!("illegal opcode {:02X} reached execute", opcode),
+        }
+        0
+    }
+
+    /// Executes one instruction and returns the T-cycles it took. A halted or
+    /// stopped CPU idles for one machine cycle instead, and a locked one keeps
+    /// reporting the illegal opcode that locked it.
+    pub fn step(&mut self) -> Result<u8, CpuError> {
+        let cycles = self.advance()?;
+        self.ram.tick(cycles);
+        Ok(cycles)
+    }
+
+    fn advance(&mut self) -> Result<u8, CpuError> {
+        if let Some(error) = &self.locked {
+            return Err(error.clone());
+        }
+
+        if self.stopped {
+            return Ok(4);
+        }
+
+        if self.halted {
+            if self.interrupts_pending() == 0 {
+                return Ok(4);
+            }
+            self.halted = false;
+        }
 
-        self.sp = self.sp.wrapping_add(increment);
-        if self.dump_registers_after.is_some() {
-            if self.dump_registers_after.unwrap() == opcode {
-                
-                println!("Registers: {}", self.registers.to_string());
-                println!("SP: {:X}", self.sp);
-                println!("PC: {:X}", self.pc);
-                println!("Opcode: {:X}", opcode);
+        if self.ime && self.interrupts_pending() != 0 {
+            return Ok(self.service_interrupt());
+        }
+
+        // EI only takes effect once the instruction following it completes.
+        let enable_ime = self.ime_scheduled;
+
+        let address = self.pc;
+        let opcode = if self.halt_bug {
+            self.halt_bug = false;
+            self.ram.read_byte(self.pc)
+        } else {
+            self.fetch_byte()
+        };
+        // The CB table is keyed by the second byte, which is still at PC.
+        let mut cycles = match opcode {
+            0xCB => CB_CYCLES[self.ram.read_byte(self.pc) as usize],
+            _ => CYCLES[opcode as usize],
+        };
+        match decode(opcode, || self.fetch_byte()) {
+            Instruction::Illegal(opcode) => {
+                let error = CpuError::IllegalOpcode { opcode, address };
+                self.locked = Some(error.clone());
+                return Err(error);
             }
+            instruction => cycles += self.execute(instruction),
+        }
+
+        if enable_ime && self.ime_scheduled {
+            self.ime = true;
+            self.ime_scheduled = false;
+        }
+
+        if self.dump_registers_after == Some(opcode) {
+            println!("Registers: {}", self.registers);
+            println!("SP: {:X}", self.sp);
+            println!("PC: {:X}", self.pc);
+            println!("Opcode: {:X}", opcode);
         }
+
+        Ok(cycles)
+    }
+}
+
+impl Default for Cpu {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::memory::io::POST_BOOT_IO;
 
     #[test]
     fn test_flags_register() {
@@ -231,10 +897,10 @@ mod tests {
 
         let flags = FlagsRegister::from(byte);
 
-        assert_eq!(flags.zero, true);
-        assert_eq!(flags.subtraction, true);
-        assert_eq!(flags.half_carry, true);
-        assert_eq!(flags.carry, true);
+        assert!(flags.zero);
+        assert!(flags.subtraction);
+        assert!(flags.half_carry);
+        assert!(flags.carry);
 
 
         let flags = FlagsRegister {
@@ -248,4 +914,1564 @@ mod tests {
 
         assert_eq!(byte, 0b0000_0000);
     }
-}
\ No newline at end of file
+
+    fn run_program(cpu: &mut Cpu, program: &[u8], steps: usize) {
+        cpu.ram.set_range(cpu.pc as usize, program.len(), program);
+        for _ in 0..steps {
+            cpu.step().unwrap();
+        }
+    }
+
+    fn program_steps(cpu: &mut Cpu, opcodes: &[u8]) {
+        for &opcode in opcodes {
+            execute_opcode(cpu, opcode);
+        }
+    }
+
+    fn execute_opcode(cpu: &mut Cpu, opcode: u8) {
+        cpu.ram.set_range(cpu.pc as usize, 1, &[opcode]);
+        cpu.step().unwrap();
+    }
+
+    #[test]
+    fn test_ld_register_to_register() {
+        let mut cpu = Cpu::new();
+        cpu.registers.set_register("c".code(), 0x42);
+
+        execute_opcode(&mut cpu, 0x41); // LD B, C
+        assert_eq!(cpu.registers.get_register("b".code()), 0x42);
+
+        execute_opcode(&mut cpu, 0x78); // LD A, B
+        assert_eq!(cpu.registers.get_register("a".code()), 0x42);
+
+        cpu.registers.set_register("a".code(), 0x17);
+        execute_opcode(&mut cpu, 0x6F); // LD L, A
+        assert_eq!(cpu.registers.get_register("l".code()), 0x17);
+
+        execute_opcode(&mut cpu, 0x5D); // LD E, L
+        assert_eq!(cpu.registers.get_register("e".code()), 0x17);
+
+        execute_opcode(&mut cpu, 0x7F); // LD A, A
+        assert_eq!(cpu.registers.get_register("a".code()), 0x17);
+    }
+
+    #[test]
+    fn test_ld_register_from_hl() {
+        let mut cpu = Cpu::new();
+        cpu.registers.set_register_word("hl".codes(), 0xC000);
+        cpu.ram.write_byte(0xC000, 0x99);
+
+        execute_opcode(&mut cpu, 0x46); // LD B, (HL)
+        assert_eq!(cpu.registers.get_register("b".code()), 0x99);
+
+        execute_opcode(&mut cpu, 0x7E); // LD A, (HL)
+        assert_eq!(cpu.registers.get_register("a".code()), 0x99);
+    }
+
+    #[test]
+    fn test_ld_hl_from_register() {
+        let mut cpu = Cpu::new();
+        cpu.registers.set_register_word("hl".codes(), 0xC010);
+        cpu.registers.set_register("d".code(), 0x5A);
+
+        execute_opcode(&mut cpu, 0x72); // LD (HL), D
+        assert_eq!(cpu.ram.read_byte(0xC010), 0x5A);
+
+        execute_opcode(&mut cpu, 0x75); // LD (HL), L
+        assert_eq!(cpu.ram.read_byte(0xC010), 0x10);
+        assert_eq!(cpu.registers.get_register_word("hl".codes()), 0xC010);
+    }
+
+    #[test]
+    fn test_ld_register_immediate() {
+        let cases = [
+            (0x06, "b", 0x11),
+            (0x0E, "c", 0x22),
+            (0x16, "d", 0x33),
+            (0x1E, "e", 0x44),
+            (0x26, "h", 0x55),
+            (0x2E, "l", 0x66),
+            (0x3E, "a", 0x77),
+        ];
+
+        let mut cpu = Cpu::new();
+        let program: Vec<u8> = cases.iter().flat_map(|&(opcode, _, value)| [opcode, value]).collect();
+        run_program(&mut cpu, &program, cases.len());
+
+        for (_, register, value) in cases {
+            assert_eq!(cpu.registers.get_register(register.code()), value, "register {}", register);
+        }
+        assert_eq!(cpu.pc, 2 * cases.len() as u16);
+    }
+
+    #[test]
+    fn test_ld_hl_immediate() {
+        let mut cpu = Cpu::new();
+        cpu.registers.set_register_word("hl".codes(), 0xC020);
+
+        run_program(&mut cpu, &[0x36, 0xAB], 1); // LD (HL), 0xAB
+        assert_eq!(cpu.ram.read_byte(0xC020), 0xAB);
+        assert_eq!(cpu.pc, 2);
+    }
+
+    #[test]
+    fn test_ld_a_indirect_bc_de() {
+        let mut cpu = Cpu::new();
+        cpu.registers.set_register_word("bc".codes(), 0xC100);
+        cpu.registers.set_register_word("de".codes(), 0xC200);
+        cpu.ram.write_byte(0xC100, 0x12);
+        cpu.ram.write_byte(0xC200, 0x34);
+
+        execute_opcode(&mut cpu, 0x0A); // LD A, (BC)
+        assert_eq!(cpu.registers.get_register("a".code()), 0x12);
+
+        execute_opcode(&mut cpu, 0x1A); // LD A, (DE)
+        assert_eq!(cpu.registers.get_register("a".code()), 0x34);
+
+        cpu.registers.set_register("a".code(), 0x56);
+        execute_opcode(&mut cpu, 0x12); // LD (DE), A
+        assert_eq!(cpu.ram.read_byte(0xC200), 0x56);
+    }
+
+    #[test]
+    fn test_copy_loop_with_hl_increment() {
+        let mut cpu = Cpu::new();
+        cpu.ram.set_range(0xC000, 3, &[0xDE, 0xAD, 0xBE]);
+        cpu.registers.set_register_word("hl".codes(), 0xC000);
+        cpu.registers.set_register_word("bc".codes(), 0xD000);
+
+        // LD A, (HL+) ; LD (BC), A ; INC BC, three times
+        let program = [0x2A, 0x02, 0x03].repeat(3);
+        run_program(&mut cpu, &program, program.len());
+
+        assert_eq!([0xD000, 0xD001, 0xD002].map(|address| cpu.ram.read_byte(address)), [0xDE, 0xAD, 0xBE]);
+        assert_eq!(cpu.registers.get_register_word("hl".codes()), 0xC003);
+    }
+
+    #[test]
+    fn test_store_with_hl_decrement() {
+        let mut cpu = Cpu::new();
+        cpu.registers.set_register_word("hl".codes(), 0x9FFF);
+        cpu.registers.set_register("a".code(), 0x00);
+
+        // LD (HL-), A clearing downwards, the way the boot ROM clears VRAM
+        run_program(&mut cpu, &[0x32, 0x32, 0x32], 3);
+
+        assert_eq!(cpu.registers.get_register_word("hl".codes()), 0x9FFC);
+        execute_opcode(&mut cpu, 0x3A); // LD A, (HL-)
+        assert_eq!(cpu.registers.get_register_word("hl".codes()), 0x9FFB);
+    }
+
+    #[test]
+    fn test_hl_increment_and_decrement_wrap() {
+        let mut cpu = Cpu::new();
+        cpu.registers.set_register_word("hl".codes(), 0xFFFF);
+        cpu.registers.set_register("a".code(), 0x42);
+
+        execute_opcode(&mut cpu, 0x22); // LD (HL+), A
+        assert_eq!(cpu.ram.read_byte(0xFFFF), 0x42);
+        assert_eq!(cpu.registers.get_register_word("hl".codes()), 0x0000);
+
+        cpu.pc = 0x0100;
+        execute_opcode(&mut cpu, 0x3A); // LD A, (HL-)
+        assert_eq!(cpu.registers.get_register_word("hl".codes()), 0xFFFF);
+    }
+
+    #[test]
+    fn test_ld_absolute_address() {
+        let mut cpu = Cpu::new();
+        cpu.registers.set_register("a".code(), 0x77);
+
+        run_program(&mut cpu, &[0xEA, 0x23, 0xC1], 1); // LD (0xC123), A
+        assert_eq!(cpu.ram.read_byte(0xC123), 0x77);
+        assert_eq!(cpu.pc, 3);
+
+        cpu.registers.set_register("a".code(), 0x00);
+        run_program(&mut cpu, &[0xFA, 0x23, 0xC1], 1); // LD A, (0xC123)
+        assert_eq!(cpu.registers.get_register("a".code()), 0x77);
+        assert_eq!(cpu.pc, 6);
+    }
+
+    #[test]
+    fn test_ld_a_from_rom() {
+        let mut rom = vec![0; 0x8000];
+        rom[0x0000..0x0003].copy_from_slice(&[0xFA, 0x50, 0x01]); // LD A, (0x0150)
+        rom[0x0150] = 0xC3;
+
+        let mut cpu = Cpu::new();
+        cpu.load_rom(rom).unwrap();
+        cpu.step().unwrap();
+
+        assert_eq!(cpu.registers.get_register("a".code()), 0xC3);
+    }
+
+    #[test]
+    fn test_ldh_immediate_offset() {
+        let mut cpu = Cpu::new();
+        cpu.registers.set_register("a".code(), 0x91);
+
+        run_program(&mut cpu, &[0xE0, 0x00, 0xE0, 0xFF], 2); // LDH (0x00), A ; LDH (0xFF), A
+        assert_eq!(cpu.ram.read_byte(0xFF00), 0xDF); // only the P1 select bits stick
+        assert_eq!(cpu.ram.read_byte(0xFFFF), 0x91);
+        assert_eq!(cpu.pc, 4);
+
+        cpu.ram.write_byte(0xFF80, 0x3C);
+        run_program(&mut cpu, &[0xF0, 0x80], 1); // LDH A, (0x80)
+        assert_eq!(cpu.registers.get_register("a".code()), 0x3C);
+        assert_eq!(cpu.pc, 6);
+    }
+
+    #[test]
+    fn test_ld_high_page_through_c() {
+        let mut cpu = Cpu::new();
+        cpu.registers.set_register("a".code(), 0x5E);
+        cpu.registers.set_register("c".code(), 0x47);
+
+        execute_opcode(&mut cpu, 0xE2); // LD (C), A
+        assert_eq!(cpu.ram.read_byte(0xFF47), 0x5E);
+
+        cpu.registers.set_register("c".code(), 0xFF);
+        cpu.ram.write_byte(0xFFFF, 0x1F);
+        execute_opcode(&mut cpu, 0xF2); // LD A, (C)
+        assert_eq!(cpu.registers.get_register("a".code()), 0x1F);
+        assert_eq!(cpu.pc, 2);
+    }
+
+    #[test]
+    fn test_ld_register_pair_immediate() {
+        let mut cpu = Cpu::new();
+        run_program(&mut cpu, &[0x11, 0x34, 0x12, 0x21, 0x00, 0x80], 2); // LD DE, 0x1234 ; LD HL, 0x8000
+
+        assert_eq!(cpu.registers.get_register_word("de".codes()), 0x1234);
+        assert_eq!(cpu.registers.get_register_word("hl".codes()), 0x8000);
+        assert_eq!(cpu.pc, 6);
+    }
+
+    #[test]
+    fn test_ld_sp_immediate() {
+        let mut cpu = Cpu::new();
+        run_program(&mut cpu, &[0x31, 0xFE, 0xFF], 1); // LD SP, 0xFFFE
+
+        assert_eq!(cpu.sp, 0xFFFE);
+        assert_eq!(cpu.pc, 3);
+    }
+
+    #[test]
+    fn test_push_pop_register_pair() {
+        let mut cpu = Cpu::new();
+        cpu.sp = 0xFFFE;
+        cpu.registers.set_register_word("bc".codes(), 0xBEEF);
+
+        execute_opcode(&mut cpu, 0xC5); // PUSH BC
+        assert_eq!(cpu.sp, 0xFFFC);
+        assert_eq!(cpu.ram.read_byte(0xFFFD), 0xBE);
+        assert_eq!(cpu.ram.read_byte(0xFFFC), 0xEF);
+
+        execute_opcode(&mut cpu, 0xE1); // POP HL
+        assert_eq!(cpu.sp, 0xFFFE);
+        assert_eq!(cpu.registers.get_register_word("hl".codes()), 0xBEEF);
+    }
+
+    #[test]
+    fn test_pop_af_masks_flags() {
+        let mut cpu = Cpu::new();
+        cpu.sp = 0xC000;
+        cpu.registers.set_register_word("de".codes(), 0x12FF);
+
+        execute_opcode(&mut cpu, 0xD5); // PUSH DE
+        execute_opcode(&mut cpu, 0xF1); // POP AF
+        assert_eq!(cpu.registers.get_register("a".code()), 0x12);
+        assert_eq!(cpu.registers.get_register("f".code()), 0xF0);
+        assert_eq!(cpu.registers.get_register_word("af".codes()), 0x12F0);
+    }
+
+    #[test]
+    fn test_push_pop_wrap_stack_pointer() {
+        let mut cpu = Cpu::new();
+        cpu.pc = 0x0100;
+        cpu.sp = 0x0001;
+        cpu.ram.set_range(0x0000, 1, &[0xA1]);
+        cpu.registers.set_register_word("hl".codes(), 0x55B2);
+
+        // The high byte lands in ROM, where the write is dropped.
+        execute_opcode(&mut cpu, 0xE5); // PUSH HL
+        assert_eq!(cpu.sp, 0xFFFF);
+        assert_eq!(cpu.ram.read_byte(0x0000), 0xA1);
+        assert_eq!(cpu.ram.read_byte(0xFFFF), 0xB2);
+
+        execute_opcode(&mut cpu, 0xC1); // POP BC
+        assert_eq!(cpu.sp, 0x0001);
+        assert_eq!(cpu.registers.get_register_word("bc".codes()), 0xA1B2);
+    }
+
+    #[test]
+    fn test_ld_sp_hl() {
+        let mut cpu = Cpu::new();
+        cpu.registers.set_register_word("hl".codes(), 0xDFF0);
+
+        execute_opcode(&mut cpu, 0xF9); // LD SP, HL
+        assert_eq!(cpu.sp, 0xDFF0);
+    }
+
+    #[test]
+    fn test_ld_hl_sp_plus_offset() {
+        // (sp, offset, result, half carry, carry)
+        let cases = [
+            (0x000F, 0x01, 0x0010, true, false),
+            (0x00FF, 0x01, 0x0100, true, true),
+            (0x0000, 0xFF, 0xFFFF, false, false),
+            (0xFFFF, 0xFF, 0xFFFE, true, true),
+        ];
+
+        for (sp, offset, result, half_carry, carry) in cases {
+            let mut cpu = Cpu::new();
+            cpu.sp = sp;
+            cpu.registers.set_flags(Some(true), Some(true), None, None);
+            run_program(&mut cpu, &[0xF8, offset], 1); // LD HL, SP+r8
+
+            let flags = cpu.registers.get_flags();
+            assert_eq!(cpu.registers.get_register_word("hl".codes()), result);
+            assert_eq!(cpu.sp, sp);
+            assert!(!flags.zero);
+            assert!(!flags.subtraction);
+            assert_eq!(flags.half_carry, half_carry, "half carry for {:04X} + {:02X}", sp, offset);
+            assert_eq!(flags.carry, carry, "carry for {:04X} + {:02X}", sp, offset);
+        }
+    }
+
+    #[test]
+    fn test_ld_absolute_sp() {
+        let mut cpu = Cpu::new();
+        cpu.sp = 0xBEEF;
+        run_program(&mut cpu, &[0x08, 0x00, 0xC0], 1); // LD (0xC000), SP
+
+        assert_eq!(cpu.ram.read_byte(0xC000), 0xEF);
+        assert_eq!(cpu.ram.read_byte(0xC001), 0xBE);
+        assert_eq!(cpu.sp, 0xBEEF);
+        assert_eq!(cpu.pc, 3);
+    }
+
+    #[test]
+    fn test_add_a() {
+        // (a, operand, result, zero, half carry, carry)
+        let cases = [
+            (0x0F, 0x01, 0x10, false, true, false),
+            (0xFF, 0x01, 0x00, true, true, true),
+            (0x80, 0x80, 0x00, true, false, true),
+            (0x12, 0x34, 0x46, false, false, false),
+        ];
+
+        for (a, operand, result, zero, half_carry, carry) in cases {
+            let mut cpu = Cpu::new();
+            cpu.registers.set_register("a".code(), a);
+            cpu.registers.set_register("b".code(), operand);
+            cpu.registers.set_flags(None, Some(true), None, None);
+            execute_opcode(&mut cpu, 0x80); // ADD A, B
+
+            let flags = cpu.registers.get_flags();
+            assert_eq!(cpu.registers.get_register("a".code()), result);
+            assert_eq!(flags.zero, zero);
+            assert!(!flags.subtraction);
+            assert_eq!(flags.half_carry, half_carry);
+            assert_eq!(flags.carry, carry);
+        }
+    }
+
+    #[test]
+    fn test_add_a_hl_and_immediate() {
+        let mut cpu = Cpu::new();
+        cpu.registers.set_register("a".code(), 0x01);
+        cpu.registers.set_register_word("hl".codes(), 0xC000);
+        cpu.ram.write_byte(0xC000, 0x02);
+
+        run_program(&mut cpu, &[0x86, 0xC6, 0x04], 2); // ADD A, (HL) ; ADD A, 0x04
+        assert_eq!(cpu.registers.get_register("a".code()), 0x07);
+        assert_eq!(cpu.pc, 3);
+    }
+
+    #[test]
+    fn test_adc_a() {
+        // (a, operand, carry in, result, zero, half carry, carry)
+        let cases = [
+            (0xFF, 0x00, true, 0x00, true, true, true),
+            (0x0E, 0x01, true, 0x10, false, true, false),
+            (0x0E, 0x01, false, 0x0F, false, false, false),
+            (0xF0, 0x0F, true, 0x00, true, true, true),
+            (0x80, 0x7F, true, 0x00, true, true, true),
+            (0x80, 0x7F, false, 0xFF, false, false, false),
+            (0x00, 0x00, false, 0x00, true, false, false),
+            (0x00, 0x00, true, 0x01, false, false, false),
+            (0x08, 0x08, true, 0x11, false, true, false),
+            (0xFF, 0xFF, true, 0xFF, false, true, true),
+        ];
+
+        for (a, operand, carry_in, result, zero, half_carry, carry) in cases {
+            let mut cpu = Cpu::new();
+            cpu.registers.set_register("a".code(), a);
+            cpu.registers.set_register("c".code(), operand);
+            cpu.registers.set_flags(None, Some(true), None, Some(carry_in));
+            execute_opcode(&mut cpu, 0x89); // ADC A, C
+
+            let flags = cpu.registers.get_flags();
+            let case = format!("{:02X} + {:02X} + {}", a, operand, carry_in as u8);
+            assert_eq!(cpu.registers.get_register("a".code()), result, "{}", case);
+            assert_eq!(flags.zero, zero, "{}", case);
+            assert!(!flags.subtraction, "{}", case);
+            assert_eq!(flags.half_carry, half_carry, "{}", case);
+            assert_eq!(flags.carry, carry, "{}", case);
+        }
+    }
+
+    #[test]
+    fn test_adc_a_hl_and_immediate() {
+        let mut cpu = Cpu::new();
+        cpu.registers.set_register("a".code(), 0xFF);
+        cpu.registers.set_register_word("hl".codes(), 0xC000);
+        cpu.ram.write_byte(0xC000, 0x01);
+
+        // ADC A, (HL) overflows and sets carry, which ADC A, 0x10 then adds in
+        run_program(&mut cpu, &[0x8E, 0xCE, 0x10], 2);
+        assert_eq!(cpu.registers.get_register("a".code()), 0x11);
+        assert!(!cpu.registers.get_flags().carry);
+    }
+
+    #[test]
+    fn test_sub_a() {
+        // (a, operand, result, zero, half carry, carry)
+        let cases = [
+            (0x00, 0x01, 0xFF, false, true, true),
+            (0x10, 0x01, 0x0F, false, true, false),
+            (0x42, 0x42, 0x00, true, false, false),
+            (0x30, 0x40, 0xF0, false, false, true),
+        ];
+
+        for (a, operand, result, zero, half_carry, carry) in cases {
+            let mut cpu = Cpu::new();
+            cpu.registers.set_register("a".code(), a);
+            cpu.registers.set_register("d".code(), operand);
+            execute_opcode(&mut cpu, 0x92); // SUB D
+
+            let flags = cpu.registers.get_flags();
+            assert_eq!(cpu.registers.get_register("a".code()), result);
+            assert_eq!(flags.zero, zero);
+            assert!(flags.subtraction);
+            assert_eq!(flags.half_carry, half_carry);
+            assert_eq!(flags.carry, carry);
+        }
+    }
+
+    #[test]
+    fn test_sbc_a() {
+        // (a, operand, carry in, result, zero, half carry, carry)
+        let cases = [
+            (0x00, 0xFF, true, 0x00, true, true, true),
+            (0x10, 0x0F, true, 0x00, true, true, false),
+            (0x10, 0x0F, false, 0x01, false, true, false),
+            (0x00, 0x00, true, 0xFF, false, true, true),
+            (0xFF, 0xFE, true, 0x00, true, false, false),
+        ];
+
+        for (a, operand, carry_in, result, zero, half_carry, carry) in cases {
+            let mut cpu = Cpu::new();
+            cpu.registers.set_register("a".code(), a);
+            cpu.registers.set_register("e".code(), operand);
+            cpu.registers.set_flags(None, None, None, Some(carry_in));
+            execute_opcode(&mut cpu, 0x9B); // SBC A, E
+
+            let flags = cpu.registers.get_flags();
+            let case = format!("{:02X} - {:02X} - {}", a, operand, carry_in as u8);
+            assert_eq!(cpu.registers.get_register("a".code()), result, "{}", case);
+            assert_eq!(flags.zero, zero, "{}", case);
+            assert!(flags.subtraction, "{}", case);
+            assert_eq!(flags.half_carry, half_carry, "{}", case);
+            assert_eq!(flags.carry, carry, "{}", case);
+        }
+    }
+
+    #[test]
+    fn test_sub_and_sbc_hl_and_immediate() {
+        let mut cpu = Cpu::new();
+        cpu.registers.set_register("a".code(), 0x20);
+        cpu.registers.set_register_word("hl".codes(), 0xC000);
+        cpu.ram.write_byte(0xC000, 0x21);
+
+        // SUB (HL) borrows, SBC A, 0x01 subtracts the borrow too, SUB 0x0D
+        run_program(&mut cpu, &[0x96, 0xDE, 0x01, 0xD6, 0x0D], 3);
+        assert_eq!(cpu.registers.get_register("a".code()), 0xF0);
+        assert_eq!(cpu.pc, 5);
+    }
+
+    #[test]
+    fn test_logical_operations_flags() {
+        // (opcode, a, operand, result, half carry)
+        let cases = [
+            (0xA0, 0b1100, 0b1010, 0b1000, true), // AND B
+            (0xA0, 0xF0, 0x0F, 0x00, true),
+            (0xA8, 0b1100, 0b1010, 0b0110, false), // XOR B
+            (0xA8, 0x5A, 0x5A, 0x00, false),
+            (0xB0, 0b1100, 0b1010, 0b1110, false), // OR B
+            (0xB0, 0x00, 0x00, 0x00, false),
+        ];
+
+        for (opcode, a, operand, result, half_carry) in cases {
+            let mut cpu = Cpu::new();
+            cpu.registers.set_register("a".code(), a);
+            cpu.registers.set_register("b".code(), operand);
+            cpu.registers.set_flags(None, Some(true), None, Some(true));
+            execute_opcode(&mut cpu, opcode);
+
+            let flags = cpu.registers.get_flags();
+            assert_eq!(cpu.registers.get_register("a".code()), result);
+            assert_eq!(flags.zero, result == 0);
+            assert!(!flags.subtraction);
+            assert_eq!(flags.half_carry, half_carry);
+            assert!(!flags.carry);
+        }
+    }
+
+    #[test]
+    fn test_xor_a_clears_accumulator() {
+        let mut cpu = Cpu::new();
+        cpu.registers.set_register("a".code(), 0x9C);
+
+        execute_opcode(&mut cpu, 0xAF); // XOR A
+        assert_eq!(cpu.registers.get_register("a".code()), 0x00);
+        assert!(cpu.registers.get_flags().zero);
+    }
+
+    #[test]
+    fn test_logical_operations_hl_and_immediate() {
+        let mut cpu = Cpu::new();
+        cpu.registers.set_register("a".code(), 0xFF);
+        cpu.registers.set_register_word("hl".codes(), 0xC000);
+        cpu.ram.write_byte(0xC000, 0x3C);
+
+        // AND (HL) ; XOR 0x0F ; OR (HL) ; AND 0xF0
+        run_program(&mut cpu, &[0xA6, 0xEE, 0x0F, 0xB6, 0xE6, 0xF0], 4);
+        assert_eq!(cpu.registers.get_register("a".code()), 0x30);
+        assert!(cpu.registers.get_flags().half_carry);
+        assert_eq!(cpu.pc, 6);
+    }
+
+    #[test]
+    fn test_cp_leaves_a_untouched() {
+        // (operand, zero, carry)
+        let cases = [
+            (0x90, true, false),
+            (0x91, false, true),
+            (0x10, false, false),
+        ];
+
+        for (operand, zero, carry) in cases {
+            let mut cpu = Cpu::new();
+            cpu.registers.set_register("a".code(), 0x90);
+            run_program(&mut cpu, &[0xFE, operand], 1); // CP d8
+
+            let flags = cpu.registers.get_flags();
+            assert_eq!(cpu.registers.get_register("a".code()), 0x90);
+            assert_eq!(flags.zero, zero);
+            assert!(flags.subtraction);
+            assert_eq!(flags.carry, carry);
+        }
+    }
+
+    #[test]
+    fn test_cp_register_and_hl() {
+        let mut cpu = Cpu::new();
+        cpu.registers.set_register("a".code(), 0x44);
+        cpu.registers.set_register("h".code(), 0xC0);
+        cpu.registers.set_register("l".code(), 0x00);
+        cpu.ram.write_byte(0xC000, 0x44);
+
+        execute_opcode(&mut cpu, 0xBC); // CP H
+        assert!(!cpu.registers.get_flags().zero);
+        assert!(cpu.registers.get_flags().carry);
+
+        execute_opcode(&mut cpu, 0xBE); // CP (HL)
+        assert!(cpu.registers.get_flags().zero);
+        assert!(!cpu.registers.get_flags().carry);
+        assert_eq!(cpu.registers.get_register("a".code()), 0x44);
+    }
+
+    #[test]
+    fn test_inc_dec_hl_memory() {
+        let mut cpu = Cpu::new();
+        cpu.registers.set_register_word("hl".codes(), 0xC000);
+        cpu.registers.set_flags(None, None, None, Some(true));
+
+        cpu.ram.write_byte(0xC000, 0xFF);
+        execute_opcode(&mut cpu, 0x34); // INC (HL)
+        let flags = cpu.registers.get_flags();
+        assert_eq!(cpu.ram.read_byte(0xC000), 0x00);
+        assert!(flags.zero);
+        assert!(!flags.subtraction);
+        assert!(flags.half_carry);
+        assert!(flags.carry);
+
+        execute_opcode(&mut cpu, 0x35); // DEC (HL)
+        let flags = cpu.registers.get_flags();
+        assert_eq!(cpu.ram.read_byte(0xC000), 0xFF);
+        assert!(!flags.zero);
+        assert!(flags.subtraction);
+        assert!(flags.half_carry);
+        assert!(flags.carry);
+    }
+
+    #[test]
+    fn test_inc_dec_register_flags() {
+        // (opcode, value, result, zero, half carry)
+        let cases = [
+            (0x04, 0x0F, 0x10, false, true), // INC B
+            (0x04, 0xFF, 0x00, true, true),
+            (0x04, 0x41, 0x42, false, false),
+            (0x05, 0x10, 0x0F, false, true), // DEC B
+            (0x05, 0x00, 0xFF, false, true),
+            (0x05, 0x01, 0x00, true, false),
+        ];
+
+        for (opcode, value, result, zero, half_carry) in cases {
+            for carry in [false, true] {
+                let mut cpu = Cpu::new();
+                cpu.registers.set_register("b".code(), value);
+                cpu.registers.set_flags(None, None, None, Some(carry));
+                execute_opcode(&mut cpu, opcode);
+
+                let flags = cpu.registers.get_flags();
+                assert_eq!(cpu.registers.get_register("b".code()), result);
+                assert_eq!(flags.zero, zero);
+                assert_eq!(flags.subtraction, opcode == 0x05);
+                assert_eq!(flags.half_carry, half_carry);
+                assert_eq!(flags.carry, carry);
+            }
+        }
+    }
+
+    #[test]
+    fn test_inc_dec_each_register() {
+        let mut cpu = Cpu::new();
+        for (inc, dec, register) in [(0x0C, 0x0D, "c"), (0x14, 0x15, "d"), (0x1C, 0x1D, "e"), (0x24, 0x25, "h"), (0x2C, 0x2D, "l"), (0x3C, 0x3D, "a")] {
+            execute_opcode(&mut cpu, inc);
+            execute_opcode(&mut cpu, inc);
+            assert_eq!(cpu.registers.get_register(register.code()), 2, "register {}", register);
+            execute_opcode(&mut cpu, dec);
+            assert_eq!(cpu.registers.get_register(register.code()), 1, "register {}", register);
+        }
+    }
+
+    #[test]
+    fn test_add_hl() {
+        // (hl, operand, result, half carry, carry)
+        let cases = [
+            (0x0FFF, 0x0001, 0x1000, true, false),
+            (0xFFFF, 0x0001, 0x0000, true, true),
+            (0x8000, 0x8000, 0x0000, false, true),
+            (0x1234, 0x0101, 0x1335, false, false),
+        ];
+
+        for (hl, operand, result, half_carry, carry) in cases {
+            for zero in [false, true] {
+                let mut cpu = Cpu::new();
+                cpu.registers.set_register_word("hl".codes(), hl);
+                cpu.registers.set_register_word("de".codes(), operand);
+                cpu.registers.set_flags(Some(zero), Some(true), None, None);
+                execute_opcode(&mut cpu, 0x19); // ADD HL, DE
+
+                let flags = cpu.registers.get_flags();
+                assert_eq!(cpu.registers.get_register_word("hl".codes()), result);
+                assert_eq!(flags.zero, zero);
+                assert!(!flags.subtraction);
+                assert_eq!(flags.half_carry, half_carry);
+                assert_eq!(flags.carry, carry);
+            }
+        }
+    }
+
+    #[test]
+    fn test_add_hl_hl_and_sp() {
+        let mut cpu = Cpu::new();
+        cpu.registers.set_register_word("hl".codes(), 0x0120);
+        cpu.sp = 0x0003;
+
+        execute_opcode(&mut cpu, 0x29); // ADD HL, HL
+        assert_eq!(cpu.registers.get_register_word("hl".codes()), 0x0240);
+
+        execute_opcode(&mut cpu, 0x39); // ADD HL, SP
+        assert_eq!(cpu.registers.get_register_word("hl".codes()), 0x0243);
+    }
+
+    #[test]
+    fn test_add_sp_signed() {
+        // (sp, offset, result, half carry, carry)
+        let cases = [
+            (0x0001, 0xFF, 0x0000, true, true),
+            (0x00FF, 0x01, 0x0100, true, true),
+            (0x00F0, 0x10, 0x0100, false, true),
+            (0x0FFF, 0x01, 0x1000, true, true),
+            (0x0FFF, 0x80, 0x0F7F, false, true),
+            (0x1000, 0xFF, 0x0FFF, false, false),
+        ];
+
+        for (sp, offset, result, half_carry, carry) in cases {
+            let mut cpu = Cpu::new();
+            cpu.sp = sp;
+            cpu.registers.set_flags(Some(true), Some(true), None, None);
+            run_program(&mut cpu, &[0xE8, offset], 1); // ADD SP, r8
+
+            let flags = cpu.registers.get_flags();
+            let case = format!("{:04X} + {:02X}", sp, offset);
+            assert_eq!(cpu.sp, result, "{}", case);
+            assert!(!flags.zero, "{}", case);
+            assert!(!flags.subtraction, "{}", case);
+            assert_eq!(flags.half_carry, half_carry, "{}", case);
+            assert_eq!(flags.carry, carry, "{}", case);
+        }
+    }
+
+    #[test]
+    fn test_dec_bc_loop() {
+        let mut cpu = Cpu::new();
+        cpu.registers.set_register_word("bc".codes(), 3);
+        cpu.registers.set_flags(Some(false), Some(false), Some(true), Some(true));
+
+        for expected in [2, 1, 0, 0xFFFF] {
+            execute_opcode(&mut cpu, 0x0B); // DEC BC
+            assert_eq!(cpu.registers.get_register_word("bc".codes()), expected);
+        }
+        let flags = cpu.registers.get_flags();
+        assert!(!flags.zero && !flags.subtraction && flags.half_carry && flags.carry);
+    }
+
+    #[test]
+    fn test_inc_dec_register_pairs_and_sp() {
+        let mut cpu = Cpu::new();
+        cpu.registers.set_register_word("de".codes(), 0xFFFF);
+        cpu.registers.set_register_word("hl".codes(), 0x0000);
+        cpu.sp = 0xFFFF;
+
+        program_steps(&mut cpu, &[0x13, 0x2B, 0x33]); // INC DE ; DEC HL ; INC SP
+        assert_eq!(cpu.registers.get_register_word("de".codes()), 0x0000);
+        assert_eq!(cpu.registers.get_register_word("hl".codes()), 0xFFFF);
+        assert_eq!(cpu.sp, 0x0000);
+
+        program_steps(&mut cpu, &[0x1B, 0x23, 0x3B]); // DEC DE ; INC HL ; DEC SP
+        assert_eq!(cpu.registers.get_register_word("de".codes()), 0xFFFF);
+        assert_eq!(cpu.registers.get_register_word("hl".codes()), 0x0000);
+        assert_eq!(cpu.sp, 0xFFFF);
+    }
+
+    #[test]
+    fn test_rotate_accumulator() {
+        // (opcode, a, carry in, result, carry out)
+        let cases = [
+            (0x07, 0x85, false, 0x0B, true), // RLCA
+            (0x07, 0x00, true, 0x00, false),
+            (0x0F, 0x01, false, 0x80, true), // RRCA
+            (0x0F, 0x00, true, 0x00, false),
+            (0x17, 0x80, false, 0x00, true), // RLA
+            (0x17, 0x80, true, 0x01, true),
+            (0x17, 0x40, true, 0x81, false),
+            (0x1F, 0x01, false, 0x00, true), // RRA
+            (0x1F, 0x01, true, 0x80, true),
+            (0x1F, 0x02, true, 0x81, false),
+        ];
+
+        for (opcode, a, carry_in, result, carry) in cases {
+            let mut cpu = Cpu::new();
+            cpu.registers.set_register("a".code(), a);
+            cpu.registers.set_flags(Some(true), Some(true), Some(true), Some(carry_in));
+            execute_opcode(&mut cpu, opcode);
+
+            let flags = cpu.registers.get_flags();
+            let case = format!("{:02X} on {:02X} with carry {}", opcode, a, carry_in);
+            assert_eq!(cpu.registers.get_register("a".code()), result, "{}", case);
+            assert!(!flags.zero, "{}", case);
+            assert!(!flags.subtraction, "{}", case);
+            assert!(!flags.half_carry, "{}", case);
+            assert_eq!(flags.carry, carry, "{}", case);
+        }
+    }
+
+    #[test]
+    fn test_cb_cycles() {
+        for (opcode, cycles) in [(0x00, 8), (0x06, 16), (0x46, 12), (0x47, 8), (0x86, 16), (0xFE, 16), (0xFD, 8)] {
+            let mut cpu = Cpu::new();
+            cpu.registers.set_register_word("hl".codes(), 0xC000);
+            cpu.ram.set_range(0, 2, &[0xCB, opcode]);
+            assert_eq!(cpu.step().unwrap(), cycles, "CB {:02X}", opcode);
+        }
+    }
+
+    #[test]
+    fn test_cb_rotates() {
+        // (cb opcode, value, carry in, result, carry out)
+        let cases = [
+            (0x00, 0x00, true, 0x00, false), // RLC B
+            (0x00, 0x85, false, 0x0B, true),
+            (0x08, 0x01, false, 0x80, true), // RRC B
+            (0x10, 0x80, false, 0x00, true), // RL B
+            (0x10, 0x80, true, 0x01, true),
+            (0x18, 0x01, false, 0x00, true), // RR B
+            (0x18, 0x00, true, 0x80, false),
+        ];
+
+        for (opcode, value, carry_in, result, carry) in cases {
+            let mut cpu = Cpu::new();
+            cpu.registers.set_register("b".code(), value);
+            cpu.registers.set_flags(None, Some(true), Some(true), Some(carry_in));
+            run_program(&mut cpu, &[0xCB, opcode], 1);
+
+            let flags = cpu.registers.get_flags();
+            let case = format!("CB {:02X} on {:02X} with carry {}", opcode, value, carry_in);
+            assert_eq!(cpu.registers.get_register("b".code()), result, "{}", case);
+            assert_eq!(flags.zero, result == 0, "{}", case);
+            assert!(!flags.subtraction, "{}", case);
+            assert!(!flags.half_carry, "{}", case);
+            assert_eq!(flags.carry, carry, "{}", case);
+        }
+    }
+
+    #[test]
+    fn test_cb_rotate_hl_writes_back() {
+        let mut cpu = Cpu::new();
+        cpu.registers.set_register_word("hl".codes(), 0xC000);
+        cpu.ram.write_byte(0xC000, 0x81);
+
+        run_program(&mut cpu, &[0xCB, 0x06], 1); // RLC (HL)
+        assert_eq!(cpu.ram.read_byte(0xC000), 0x03);
+        assert!(cpu.registers.get_flags().carry);
+        assert_eq!(cpu.pc, 2);
+    }
+
+    #[test]
+    fn test_cb_shifts_and_swap() {
+        // (cb opcode, value, result, carry out)
+        let cases = [
+            (0x21, 0x81, 0x02, true), // SLA C
+            (0x21, 0x80, 0x00, true),
+            (0x29, 0x81, 0xC0, true), // SRA C
+            (0x29, 0x01, 0x00, true),
+            (0x39, 0x81, 0x40, true), // SRL C
+            (0x39, 0x02, 0x01, false),
+            (0x31, 0xF0, 0x0F, false), // SWAP C
+            (0x31, 0x00, 0x00, false),
+            (0x31, 0x4E, 0xE4, false),
+        ];
+
+        for (opcode, value, result, carry) in cases {
+            let mut cpu = Cpu::new();
+            cpu.registers.set_register("c".code(), value);
+            cpu.registers.set_flags(None, Some(true), Some(true), Some(!carry));
+            run_program(&mut cpu, &[0xCB, opcode], 1);
+
+            let flags = cpu.registers.get_flags();
+            let case = format!("CB {:02X} on {:02X}", opcode, value);
+            assert_eq!(cpu.registers.get_register("c".code()), result, "{}", case);
+            assert_eq!(flags.zero, result == 0, "{}", case);
+            assert!(!flags.subtraction, "{}", case);
+            assert!(!flags.half_carry, "{}", case);
+            assert_eq!(flags.carry, carry, "{}", case);
+        }
+    }
+
+    #[test]
+    fn test_cb_shift_hl_writes_back() {
+        let mut cpu = Cpu::new();
+        cpu.registers.set_register_word("hl".codes(), 0xC000);
+        cpu.ram.write_byte(0xC000, 0x81);
+
+        run_program(&mut cpu, &[0xCB, 0x2E, 0xCB, 0x36], 2); // SRA (HL) ; SWAP (HL)
+        assert_eq!(cpu.ram.read_byte(0xC000), 0x0C);
+    }
+
+    #[test]
+    fn test_cb_bit_register() {
+        for carry in [false, true] {
+            let mut cpu = Cpu::new();
+            cpu.registers.set_register("h".code(), 0x80);
+            cpu.registers.set_flags(None, Some(true), Some(false), Some(carry));
+
+            run_program(&mut cpu, &[0xCB, 0x7C], 1); // BIT 7, H
+            let flags = cpu.registers.get_flags();
+            assert!(!flags.zero);
+            assert!(!flags.subtraction);
+            assert!(flags.half_carry);
+            assert_eq!(flags.carry, carry);
+
+            run_program(&mut cpu, &[0xCB, 0x74], 1); // BIT 6, H
+            let flags = cpu.registers.get_flags();
+            assert!(flags.zero);
+            assert_eq!(flags.carry, carry);
+            assert_eq!(cpu.registers.get_register("h".code()), 0x80);
+        }
+    }
+
+    #[test]
+    fn test_cb_bit_hl() {
+        let mut cpu = Cpu::new();
+        cpu.registers.set_register_word("hl".codes(), 0xC000);
+        cpu.ram.write_byte(0xC000, 0x01);
+
+        run_program(&mut cpu, &[0xCB, 0x46], 1); // BIT 0, (HL)
+        assert!(!cpu.registers.get_flags().zero);
+
+        run_program(&mut cpu, &[0xCB, 0x4E], 1); // BIT 1, (HL)
+        assert!(cpu.registers.get_flags().zero);
+        assert_eq!(cpu.ram.read_byte(0xC000), 0x01);
+    }
+
+    #[test]
+    fn test_cb_set_and_res_register() {
+        let mut cpu = Cpu::new();
+        cpu.registers.set_flags(Some(true), Some(false), Some(true), Some(false));
+
+        let mut expected = 0u8;
+        for bit in 0..8 {
+            run_program(&mut cpu, &[0xCB, 0xC7 | (bit << 3)], 1); // SET bit, A
+            expected |= 1 << bit;
+            assert_eq!(cpu.registers.get_register("a".code()), expected);
+        }
+        for bit in 0..8 {
+            run_program(&mut cpu, &[0xCB, 0x87 | (bit << 3)], 1); // RES bit, A
+            expected &= !(1 << bit);
+            assert_eq!(cpu.registers.get_register("a".code()), expected);
+        }
+
+        let flags = cpu.registers.get_flags();
+        assert!(flags.zero && !flags.subtraction && flags.half_carry && !flags.carry);
+    }
+
+    #[test]
+    fn test_cb_set_and_res_hl() {
+        let mut cpu = Cpu::new();
+        cpu.registers.set_register_word("hl".codes(), 0xC000);
+        cpu.ram.write_byte(0xC000, 0x0F);
+
+        run_program(&mut cpu, &[0xCB, 0xFE, 0xCB, 0x86], 2); // SET 7, (HL) ; RES 0, (HL)
+        assert_eq!(cpu.ram.read_byte(0xC000), 0x8E);
+    }
+
+    #[test]
+    fn test_jp_absolute() {
+        let mut cpu = Cpu::new();
+        run_program(&mut cpu, &[0xC3, 0x50, 0x01], 1); // JP 0x0150
+
+        assert_eq!(cpu.pc, 0x0150);
+    }
+
+    #[test]
+    fn test_jp_conditional() {
+        // (opcode, zero, carry, taken)
+        let cases = [
+            (0xC2, false, false, true), // JP NZ
+            (0xC2, true, false, false),
+            (0xCA, true, false, true), // JP Z
+            (0xCA, false, true, false),
+            (0xD2, false, false, true), // JP NC
+            (0xD2, false, true, false),
+            (0xDA, false, true, true), // JP C
+            (0xDA, true, false, false),
+        ];
+
+        for (opcode, zero, carry, taken) in cases {
+            let mut cpu = Cpu::new();
+            cpu.pc = 0x0200;
+            cpu.registers.set_flags(Some(zero), None, None, Some(carry));
+            run_program(&mut cpu, &[opcode, 0x34, 0x12], 1);
+
+            assert_eq!(cpu.pc, if taken { 0x1234 } else { 0x0203 }, "{:02X} z={} c={}", opcode, zero, carry);
+        }
+    }
+
+    #[test]
+    fn test_jr_relative() {
+        let mut cpu = Cpu::new();
+        cpu.pc = 0x0100;
+        run_program(&mut cpu, &[0x18, 0xFE], 3); // JR -2
+        assert_eq!(cpu.pc, 0x0100);
+
+        cpu.pc = 0x00F0;
+        run_program(&mut cpu, &[0x18, 0x20], 1); // JR +0x20, across the page boundary
+        assert_eq!(cpu.pc, 0x0112);
+
+        cpu.pc = 0x0000;
+        run_program(&mut cpu, &[0x18, 0x80], 1); // JR -128 wraps below zero
+        assert_eq!(cpu.pc, 0xFF82);
+    }
+
+    #[test]
+    fn test_jr_conditional() {
+        // (opcode, zero, carry, taken)
+        let cases = [
+            (0x20, false, true, true), // JR NZ
+            (0x20, true, false, false),
+            (0x28, true, false, true), // JR Z
+            (0x28, false, true, false),
+            (0x30, true, false, true), // JR NC
+            (0x30, false, true, false),
+            (0x38, false, true, true), // JR C
+            (0x38, true, false, false),
+        ];
+
+        for (opcode, zero, carry, taken) in cases {
+            let mut cpu = Cpu::new();
+            cpu.pc = 0x0200;
+            cpu.registers.set_flags(Some(zero), None, None, Some(carry));
+            run_program(&mut cpu, &[opcode, 0x05], 1);
+
+            assert_eq!(cpu.pc, if taken { 0x0207 } else { 0x0202 }, "{:02X} z={} c={}", opcode, zero, carry);
+        }
+    }
+
+    #[test]
+    fn test_jp_hl() {
+        let mut cpu = Cpu::new();
+        cpu.registers.set_register_word("hl".codes(), 0x1234);
+        // A JP to the address stored at (HL) would land on 0x00C3 instead
+        cpu.ram.set_range(0x1234, 2, &[0xC3, 0x00]);
+
+        execute_opcode(&mut cpu, 0xE9); // JP (HL)
+        assert_eq!(cpu.pc, 0x1234);
+    }
+
+    #[test]
+    fn test_call() {
+        let mut cpu = Cpu::new();
+        cpu.pc = 0x0150;
+        cpu.sp = 0xFFFE;
+        run_program(&mut cpu, &[0xCD, 0x00, 0x20], 1); // CALL 0x2000
+
+        assert_eq!(cpu.pc, 0x2000);
+        assert_eq!(cpu.sp, 0xFFFC);
+        assert_eq!(cpu.ram.read_byte(0xFFFC), 0x53);
+        assert_eq!(cpu.ram.read_byte(0xFFFD), 0x01);
+    }
+
+    #[test]
+    fn test_call_conditional() {
+        // (opcode, zero, carry, taken)
+        let cases = [
+            (0xC4, false, false, true), // CALL NZ
+            (0xC4, true, false, false),
+            (0xCC, true, false, true), // CALL Z
+            (0xCC, false, false, false),
+            (0xD4, false, false, true), // CALL NC
+            (0xD4, false, true, false),
+            (0xDC, false, true, true), // CALL C
+            (0xDC, false, false, false),
+        ];
+
+        for (opcode, zero, carry, taken) in cases {
+            let mut cpu = Cpu::new();
+            cpu.pc = 0x0300;
+            cpu.sp = 0xD000;
+            cpu.registers.set_flags(Some(zero), None, None, Some(carry));
+            run_program(&mut cpu, &[opcode, 0x00, 0x40], 1);
+
+            let case = format!("{:02X} z={} c={}", opcode, zero, carry);
+            if taken {
+                assert_eq!(cpu.pc, 0x4000, "{}", case);
+                assert_eq!(cpu.sp, 0xCFFE, "{}", case);
+                assert_eq!(cpu.ram.read_byte(0xCFFE), 0x03, "{}", case);
+                assert_eq!(cpu.ram.read_byte(0xCFFF), 0x03, "{}", case);
+            } else {
+                assert_eq!(cpu.pc, 0x0303, "{}", case);
+                assert_eq!(cpu.sp, 0xD000, "{}", case);
+                assert_eq!(cpu.ram.read_byte(0xCFFE), 0x00, "{}", case);
+            }
+        }
+    }
+
+    #[test]
+    fn test_call_ret_round_trip() {
+        let mut cpu = Cpu::new();
+        cpu.sp = 0xFFFE;
+        cpu.ram.set_range(0x0100, 4, &[0xCD, 0x00, 0x20, 0x00]); // CALL 0x2000 ; NOP
+        cpu.ram.set_range(0x2000, 3, &[0x06, 0x99, 0xC9]); // LD B, 0x99 ; RET
+        cpu.pc = 0x0100;
+
+        for _ in 0..3 {
+            cpu.step().unwrap();
+        }
+        assert_eq!(cpu.pc, 0x0103);
+        assert_eq!(cpu.sp, 0xFFFE);
+        assert_eq!(cpu.registers.get_register("b".code()), 0x99);
+    }
+
+    #[test]
+    fn test_ret_conditional() {
+        for (opcode, zero, carry, taken) in [(0xC0, true, false, false), (0xC8, true, false, true), (0xD0, false, false, true), (0xD8, false, false, false)] {
+            let mut cpu = Cpu::new();
+            cpu.pc = 0x0200;
+            cpu.sp = 0xC000;
+            cpu.ram.set_range(0xC000, 2, &[0x34, 0x12]);
+            cpu.registers.set_flags(Some(zero), None, None, Some(carry));
+            execute_opcode(&mut cpu, opcode);
+
+            assert_eq!(cpu.pc, if taken { 0x1234 } else { 0x0201 }, "{:02X}", opcode);
+            assert_eq!(cpu.sp, if taken { 0xC002 } else { 0xC000 }, "{:02X}", opcode);
+        }
+    }
+
+    #[test]
+    fn test_reti_enables_interrupts() {
+        let mut cpu = Cpu::new();
+        cpu.sp = 0xC000;
+        cpu.ram.set_range(0xC000, 2, &[0x00, 0x01]);
+
+        execute_opcode(&mut cpu, 0xD9); // RETI
+        assert_eq!(cpu.pc, 0x0100);
+        assert!(cpu.ime);
+    }
+
+    #[test]
+    fn test_rst() {
+        for (opcode, vector) in [(0xC7, 0x00), (0xCF, 0x08), (0xD7, 0x10), (0xDF, 0x18), (0xE7, 0x20), (0xEF, 0x28), (0xF7, 0x30), (0xFF, 0x38)] {
+            let mut cpu = Cpu::new();
+            cpu.pc = 0x0456;
+            cpu.sp = 0xFFFE;
+            execute_opcode(&mut cpu, opcode);
+
+            assert_eq!(cpu.pc, vector, "{:02X}", opcode);
+            assert_eq!(cpu.sp, 0xFFFC);
+            assert_eq!(cpu.ram.read_byte(0xFFFC), 0x57);
+            assert_eq!(cpu.ram.read_byte(0xFFFD), 0x04);
+        }
+    }
+
+    #[test]
+    fn test_daa() {
+        // (opcode, a, operand, result, zero, carry)
+        let cases = [
+            (0x80, 0x09, 0x01, 0x10, false, false), // ADD A, B
+            (0x80, 0x45, 0x38, 0x83, false, false),
+            (0x80, 0x99, 0x01, 0x00, true, true),
+            (0x80, 0x90, 0x90, 0x80, false, true),
+            (0x80, 0x99, 0x99, 0x98, false, true),
+            (0x80, 0x50, 0x50, 0x00, true, true),
+            (0x90, 0x10, 0x01, 0x09, false, false), // SUB B
+            (0x90, 0x45, 0x38, 0x07, false, false),
+            (0x90, 0x00, 0x01, 0x99, false, true),
+            (0x90, 0x20, 0x30, 0x90, false, true),
+            (0x90, 0x42, 0x42, 0x00, true, false),
+        ];
+
+        for (opcode, a, operand, result, zero, carry) in cases {
+            let mut cpu = Cpu::new();
+            cpu.registers.set_register("a".code(), a);
+            cpu.registers.set_register("b".code(), operand);
+            run_program(&mut cpu, &[opcode, 0x27], 2);
+
+            let flags = cpu.registers.get_flags();
+            let case = format!("{:02X} with {:02X} and {:02X}", opcode, a, operand);
+            assert_eq!(cpu.registers.get_register("a".code()), result, "{}", case);
+            assert_eq!(flags.zero, zero, "{}", case);
+            assert_eq!(flags.subtraction, opcode == 0x90, "{}", case);
+            assert!(!flags.half_carry, "{}", case);
+            assert_eq!(flags.carry, carry, "{}", case);
+        }
+    }
+
+    #[test]
+    fn test_cpl_scf_ccf_flags() {
+        // (opcode, initial flags, expected flags as znhc)
+        let cases = [
+            (0x2F, true, (true, true, true, true)), // CPL
+            (0x2F, false, (false, true, true, false)),
+            (0x37, true, (true, false, false, true)), // SCF
+            (0x37, false, (false, false, false, true)),
+            (0x3F, true, (true, false, false, false)), // CCF
+            (0x3F, false, (false, false, false, true)),
+        ];
+
+        for (opcode, initial, (zero, subtraction, half_carry, carry)) in cases {
+            let mut cpu = Cpu::new();
+            cpu.registers.set_register("a".code(), 0x35);
+            cpu.registers.set_flags(Some(initial), Some(initial), Some(initial), Some(initial));
+            execute_opcode(&mut cpu, opcode);
+
+            let flags = cpu.registers.get_flags();
+            let case = format!("{:02X} from flags {}", opcode, initial);
+            assert_eq!(flags.zero, zero, "{}", case);
+            assert_eq!(flags.subtraction, subtraction, "{}", case);
+            assert_eq!(flags.half_carry, half_carry, "{}", case);
+            assert_eq!(flags.carry, carry, "{}", case);
+            assert_eq!(cpu.registers.get_register("a".code()), if opcode == 0x2F { 0xCA } else { 0x35 }, "{}", case);
+        }
+    }
+
+    #[test]
+    fn test_halt_until_interrupt_pending() {
+        let mut cpu = Cpu::new();
+        cpu.ram.write_byte(0xFFFF, 0x04);
+        run_program(&mut cpu, &[0x76, 0x3C], 10); // HALT ; INC A
+
+        assert!(cpu.halted);
+        assert_eq!(cpu.pc, 1);
+        assert_eq!(cpu.registers.get_register("a".code()), 0x00);
+
+        cpu.ram.write_byte(0xFF0F, 0x04);
+        cpu.step().unwrap();
+        assert!(!cpu.halted);
+        assert_eq!(cpu.registers.get_register("a".code()), 0x01);
+        assert_eq!(cpu.pc, 2);
+    }
+
+    #[test]
+    fn test_halt_bug_executes_next_byte_twice() {
+        let mut cpu = Cpu::new();
+        cpu.ram.write_byte(0xFFFF, 0x01);
+        cpu.ram.write_byte(0xFF0F, 0x01);
+        run_program(&mut cpu, &[0x76, 0x3C, 0x00], 3); // HALT ; INC A ; NOP
+
+        assert!(!cpu.halted);
+        assert_eq!(cpu.registers.get_register("a".code()), 0x02);
+        assert_eq!(cpu.pc, 2);
+    }
+
+    #[test]
+    fn test_stop_until_button_press() {
+        let mut cpu = Cpu::new();
+        run_program(&mut cpu, &[0x10, 0x00, 0x3C], 100); // STOP ; INC A
+
+        assert!(cpu.stopped);
+        assert_eq!(cpu.pc, 2);
+        assert_eq!(cpu.registers.get_register("a".code()), 0x00);
+
+        cpu.press_button(Button::Start);
+        cpu.step().unwrap();
+        assert!(!cpu.stopped);
+        assert_eq!(cpu.registers.get_register("a".code()), 0x01);
+        assert_eq!(cpu.ram.read_byte(0xFF0F) & 0x10, 0x10);
+    }
+
+    #[test]
+    fn test_ei_is_delayed_by_one_instruction() {
+        let mut cpu = Cpu::new();
+        run_program(&mut cpu, &[0xFB, 0x00, 0x00], 1); // EI ; NOP ; NOP
+        assert!(!cpu.ime);
+
+        cpu.step().unwrap();
+        assert!(cpu.ime);
+    }
+
+    #[test]
+    fn test_di_is_immediate_and_cancels_ei() {
+        let mut cpu = Cpu::new();
+        cpu.ime = true;
+        run_program(&mut cpu, &[0xF3], 1); // DI
+        assert!(!cpu.ime);
+
+        run_program(&mut cpu, &[0xFB, 0xF3, 0x00], 3); // EI ; DI ; NOP
+        assert!(!cpu.ime);
+    }
+
+    #[test]
+    fn test_execute_advances_pc_not_sp() {
+        let mut cpu = Cpu::new();
+        cpu.sp = 0xFFFE;
+        run_program(&mut cpu, &[0x00, 0x00, 0x00], 3);
+
+        assert_eq!(cpu.pc, 3);
+        assert_eq!(cpu.sp, 0xFFFE);
+    }
+
+    #[test]
+    fn test_operands_follow_the_opcode() {
+        let mut cpu = Cpu::new();
+        run_program(&mut cpu, &[0x06, 0x42, 0x01, 0x34, 0x12], 1); // LD B, 0x42
+        assert_eq!(cpu.registers.get_register("b".code()), 0x42);
+        assert_eq!(cpu.pc, 2);
+
+        cpu.step().unwrap(); // LD BC, 0x1234
+        assert_eq!(cpu.registers.get_register_word("bc".codes()), 0x1234);
+        assert_eq!(cpu.pc, 5);
+    }
+
+    #[test]
+    fn test_step_returns_cycles() {
+        let mut cpu = Cpu::new();
+        cpu.sp = 0xFFFE;
+        let program = [
+            0x00, // NOP                4
+            0x31, 0xF0, 0xDF, // LD SP, 0xDFF0   12
+            0x21, 0x00, 0xC0, // LD HL, 0xC000   12
+            0x36, 0x12, // LD (HL), 0x12   12
+            0x7E, // LD A, (HL)          8
+            0xF5, // PUSH AF            16
+            0x08, 0x00, 0xC1, // LD (0xC100), SP 20
+            0xCB, 0x7E, // BIT 7, (HL)     12
+            0xC3, 0x00, 0x10, // JP 0x1000       16
+        ];
+        cpu.ram.set_range(0, program.len(), &program);
+
+        let total: u32 = (0..9).map(|_| cpu.step().unwrap() as u32).sum();
+        assert_eq!(total, 4 + 12 + 12 + 12 + 8 + 16 + 20 + 12 + 16);
+        assert_eq!(cpu.pc, 0x1000);
+    }
+
+    #[test]
+    fn test_conditional_branch_cycles() {
+        // (program, not taken cycles, taken cycles), each conditional on Z
+        let cases: [(&[u8], u8, u8); 4] = [
+            (&[0x28, 0x10], 8, 12), // JR Z
+            (&[0xCA, 0x00, 0x10], 12, 16), // JP Z
+            (&[0xCC, 0x00, 0x10], 12, 24), // CALL Z
+            (&[0xC8], 8, 20), // RET Z
+        ];
+
+        for (program, not_taken, taken) in cases {
+            for zero in [false, true] {
+                let mut cpu = Cpu::new();
+                cpu.sp = 0xFFFE;
+                cpu.registers.set_flags(Some(zero), None, None, None);
+                cpu.ram.set_range(0, program.len(), program);
+
+                assert_eq!(cpu.step().unwrap(), if zero { taken } else { not_taken }, "{:02X} z={}", program[0], zero);
+            }
+        }
+    }
+
+    #[test]
+    fn test_illegal_opcode_locks_cpu() {
+        let mut cpu = Cpu::new();
+        cpu.ram.set_range(0x0200, 2, &[0x00, 0xDD]);
+        cpu.pc = 0x0200;
+
+        assert_eq!(cpu.step(), Ok(4));
+        let error = CpuError::IllegalOpcode { opcode: 0xDD, address: 0x0201 };
+        assert_eq!(cpu.step(), Err(error.clone()));
+        assert_eq!(cpu.step(), Err(error));
+        assert_eq!(cpu.pc, 0x0202);
+    }
+
+    #[test]
+    fn test_interrupt_dispatch() {
+        // (requested, vector, remaining requests)
+        let cases = [
+            (0x01, 0x0040, 0x00),
+            (0x02, 0x0048, 0x00),
+            (0x04, 0x0050, 0x00),
+            (0x08, 0x0058, 0x00),
+            (0x10, 0x0060, 0x00),
+            (0x1F, 0x0040, 0x1E),
+            (0x0C, 0x0050, 0x08),
+        ];
+
+        for (requested, vector, remaining) in cases {
+            let mut cpu = Cpu::new();
+            cpu.pc = 0x1234;
+            cpu.sp = 0xFFFE;
+            cpu.ime = true;
+            cpu.ram.write_byte(0xFFFF, 0x1F);
+            cpu.ram.write_byte(0xFF0F, requested);
+
+            assert_eq!(cpu.step(), Ok(20));
+            assert_eq!(cpu.pc, vector, "requested {:02X}", requested);
+            assert_eq!(cpu.sp, 0xFFFC);
+            assert_eq!(cpu.ram.read_byte(0xFFFC), 0x34);
+            assert_eq!(cpu.ram.read_byte(0xFFFD), 0x12);
+            assert_eq!(cpu.ram.read_byte(0xFF0F) & 0x1F, remaining, "requested {:02X}", requested);
+            assert!(!cpu.ime);
+        }
+    }
+
+    #[test]
+    fn test_vblank_requested_with_ime_clear() {
+        let mut cpu = Cpu::new();
+        cpu.ram.write_byte(0xFFFF, 0x01);
+        cpu.ram.write_byte(0xFF40, 0x80);
+        cpu.ram.set_range(0, 2, &[0x18, 0xFE]); // JR -2
+        let mut cycles = 0;
+        while cycles < 70224 {
+            cycles += cpu.step().unwrap() as u32;
+        }
+        assert_eq!(cpu.ram.read_byte(0xFF0F) & 0x01, 0x01);
+        assert_eq!(cpu.pc, 0x0000);
+    }
+
+    #[test]
+    fn test_vblank_interrupt_ends_halt() {
+        let mut cpu = Cpu::new();
+        cpu.sp = 0xFFFE;
+        cpu.ram.write_byte(0xFFFF, 0x01);
+        cpu.ram.write_byte(0xFF40, 0x80);
+        cpu.ram.set_range(0, 3, &[0xFB, 0x76, 0x00]); // EI ; HALT ; NOP
+
+        let mut cycles = 0;
+        while cpu.pc != 0x0040 && cycles < 70224 {
+            cycles += cpu.step().unwrap() as u32;
+        }
+        assert_eq!(cpu.pc, 0x0040);
+        assert_eq!(cpu.ram.read_byte(0xFF44), 144);
+        assert_eq!(cpu.pop_word(), 0x0002);
+        assert_eq!(cpu.ram.read_byte(0xFF0F) & 0x01, 0x00);
+    }
+
+    #[test]
+    fn test_interrupt_not_enabled_in_ie() {
+        let mut cpu = Cpu::new();
+        cpu.ime = true;
+        cpu.ram.write_byte(0xFFFF, 0x01);
+        cpu.ram.write_byte(0xFF0F, 0x04);
+
+        assert_eq!(cpu.step(), Ok(4));
+        assert_eq!(cpu.pc, 1);
+        assert_eq!(cpu.ram.read_byte(0xFF0F) & 0x1F, 0x04);
+    }
+
+    #[test]
+    fn test_instruction_after_ei_runs_before_handler() {
+        let mut cpu = Cpu::new();
+        cpu.sp = 0xFFFE;
+        cpu.ram.write_byte(0xFFFF, 0x01);
+        cpu.ram.write_byte(0xFF0F, 0x01);
+        cpu.ram.set_range(0, 3, &[0xFB, 0x3C, 0x3C]); // EI ; INC A ; INC A
+
+        cpu.step().unwrap();
+        cpu.step().unwrap();
+        assert_eq!(cpu.registers.get_register("a".code()), 0x01);
+        assert_eq!(cpu.step(), Ok(20));
+        assert_eq!(cpu.pc, 0x0040);
+        assert_eq!(cpu.ram.read_byte(0xFFFC), 0x02);
+    }
+
+    #[test]
+    fn test_interrupt_wakes_halt_and_returns_after_it() {
+        let mut cpu = Cpu::new();
+        cpu.sp = 0xFFFE;
+        cpu.ime = true;
+        cpu.ram.write_byte(0xFFFF, 0x04);
+        run_program(&mut cpu, &[0x76, 0x00], 3); // HALT ; NOP
+        assert!(cpu.halted);
+
+        cpu.ram.write_byte(0xFF0F, 0x04);
+        assert_eq!(cpu.step(), Ok(20));
+        assert!(!cpu.halted);
+        assert_eq!(cpu.pc, 0x0050);
+        assert_eq!(cpu.pop_word(), 0x0001);
+    }
+
+    #[test]
+    fn test_pending_interrupt_with_ime_clear_only_wakes_halt() {
+        let mut cpu = Cpu::new();
+        cpu.ram.write_byte(0xFFFF, 0x05);
+        run_program(&mut cpu, &[0x76, 0x3C], 3); // HALT ; INC A
+        assert!(!cpu.ime());
+        assert_eq!(cpu.interrupts_pending(), 0x00);
+
+        cpu.ram.write_byte(0xFF0F, 0x06);
+        assert_eq!(cpu.interrupts_pending(), 0x04);
+        assert_eq!(cpu.step(), Ok(4));
+        assert!(!cpu.halted);
+        assert_eq!(cpu.pc, 2);
+        assert_eq!(cpu.registers.get_register("a".code()), 0x01);
+        assert_eq!(cpu.ram.read_byte(0xFF0F), 0xE6);
+        assert_eq!(cpu.interrupts_pending(), 0x04);
+    }
+
+    #[test]
+    fn test_ime_accessor_follows_ei_di_and_dispatch() {
+        let mut cpu = Cpu::new();
+        cpu.sp = 0xFFFE;
+        cpu.ram.set_range(0, 3, &[0xFB, 0x00, 0xF3]); // EI ; NOP ; DI
+        cpu.step().unwrap();
+        assert!(!cpu.ime());
+        cpu.step().unwrap();
+        assert!(cpu.ime());
+        cpu.step().unwrap();
+        assert!(!cpu.ime());
+
+        cpu.ime = true;
+        cpu.ram.write_byte(0xFFFF, 0x01);
+        cpu.ram.write_byte(0xFF0F, 0x01);
+        assert_eq!(cpu.step(), Ok(20));
+        assert!(!cpu.ime());
+        assert_eq!(cpu.interrupts_pending(), 0x00);
+    }
+
+    #[test]
+    fn test_reset_post_boot() {
+        let mut cpu = Cpu::new();
+        cpu.reset_post_boot();
+
+        for (pair, value) in [("af", 0x01B0), ("bc", 0x0013), ("de", 0x00D8), ("hl", 0x014D)] {
+            assert_eq!(cpu.registers.get_register_word(pair.codes()), value, "{}", pair);
+        }
+        assert_eq!(cpu.sp, 0xFFFE);
+        assert_eq!(cpu.pc, 0x0100);
+        for (address, value) in POST_BOOT_IO {
+            assert_eq!(cpu.ram.read_byte(address), value, "{:04X}", address);
+        }
+        assert_eq!(cpu.ram.read_byte(0xFF40), 0x91);
+        assert_eq!(cpu.ram.read_byte(0xFF47), 0xFC);
+    }
+
+    #[test]
+    fn test_reset_keeps_rom_and_restarts() {
+        let mut cpu = Cpu::new();
+        cpu.ram.set_range(0x0100, 1, &[0x3C]);
+        cpu.reset_post_boot();
+        cpu.ram.write_byte(0xC000, 0x42);
+        cpu.ram.write_byte(0xFF40, 0x00);
+        cpu.ram.set_range(0x0101, 1, &[0xDD]);
+        cpu.step().unwrap();
+        cpu.step().unwrap_err();
+        cpu.ime = true;
+
+        cpu.reset();
+        assert_eq!(cpu.pc, 0x0100);
+        assert_eq!(cpu.ram.read_byte(0x0100), 0x3C);
+        assert_eq!(cpu.ram.read_byte(0xC000), 0x00);
+        assert_eq!(cpu.ram.read_byte(0xFF40), 0x91);
+        assert!(!cpu.ime());
+        assert_eq!(cpu.step(), Ok(4));
+        assert_eq!(cpu.registers.get_register("a".code()), 0x02);
+    }
+
+    #[test]
+    fn test_oam_dma_routine_in_hram() {
+        let mut cpu = Cpu::new();
+        cpu.pc = 0xFF80;
+        cpu.ram.write_byte(0xC000, 0x99);
+        cpu.registers.set_register("a".code(), 0xC0);
+        // LDH (0x46), A ; LD A, 0x28 ; DEC A ; JR NZ, -3 ; LD A, (0xC000)
+        let routine = [0xE0, 0x46, 0x3E, 0x28, 0x3D, 0x20, 0xFD, 0xFA, 0x00, 0xC0];
+        cpu.ram.set_range(0xFF80, routine.len(), &routine);
+
+        cpu.step().unwrap();
+        assert!(cpu.ram.dma_active());
+        while cpu.pc != 0xFF87 {
+            cpu.step().unwrap();
+        }
+        assert!(!cpu.ram.dma_active());
+        cpu.step().unwrap();
+        assert_eq!(cpu.registers.get_register("a".code()), 0x99);
+        assert_eq!(cpu.ram.read_byte(0xFE00), 0x99);
+    }
+
+    #[test]
+    fn test_boot_rom_hands_over_to_cartridge() {
+        let mut rom = vec![0; 0x8000];
+        rom[0x0100] = 0x3C; // INC A
+        let mut boot_rom = [0; 0x100];
+        // LD A, 0x01 ; LDH (0x50), A, ending right before the entry point
+        boot_rom[0xFC..].copy_from_slice(&[0x3E, 0x01, 0xE0, 0x50]);
+
+        let mut cpu = Cpu::new();
+        cpu.load_rom(rom).unwrap();
+        cpu.reset_post_boot();
+        cpu.load_boot_rom(&boot_rom);
+        assert_eq!(cpu.pc, 0x0000);
+        assert_eq!(cpu.registers.get_register_word("af".codes()), 0x0000);
+
+        while cpu.pc != 0x0101 {
+            cpu.step().unwrap();
+        }
+        assert!(!cpu.ram.boot_rom_mapped());
+        assert_eq!(cpu.registers.get_register("a".code()), 0x02);
+
+        cpu.reset();
+        assert_eq!(cpu.pc, 0x0000);
+        assert!(cpu.ram.boot_rom_mapped());
+    }
+}
@@ -1,19 +1,312 @@
-use crate::cpu::Cpu;
+use std::io;
+use std::path::{Path, PathBuf};
 
+use crate::cartridge::{self, CartridgeHeader, RomError};
+use crate::cpu::{Cpu, CpuError};
+use crate::ppu::{FrameSink, SpriteEntry, TileMap, SCREEN_HEIGHT, SCREEN_WIDTH};
+
+
+
+/// How to treat a ROM without the Nintendo logo, which the boot ROM would
+/// refuse to start.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum LogoCheck {
+    #[default]
+    Skip,
+    /// Warn and run the game anyway, for homebrew that leaves the logo out.
+    Warn,
+    /// Fail the load with `RomError::BadLogo`.
+    Strict,
+}
+
+/// The 0xRRGGBB colors of shades 0-3, lightest first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Palette(pub [u32; 4]);
+
+impl Palette {
+    /// The pea green of the original screen.
+    pub const DMG_GREEN: Palette = Palette([0x9BBC0F, 0x8BAC0F, 0x306230, 0x0F380F]);
+    pub const GRAYSCALE: Palette = Palette([0xFFFFFF, 0xAAAAAA, 0x555555, 0x000000]);
+    /// The Game Boy Pocket's grayish screen.
+    pub const POCKET: Palette = Palette([0xC4CFA1, 0x8B956D, 0x4D533C, 0x1F1F1F]);
+
+    /// The color of `shade` as RGBA8888 bytes, fully opaque.
+    pub fn rgba(&self, shade: u8) -> [u8; 4] {
+        let [_, red, green, blue] = self.0[shade as usize & 0x03].to_be_bytes();
+        [red, green, blue, 0xFF]
+    }
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        Palette::GRAYSCALE
+    }
+}
+
+/// Emulator options.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Config {
+    /// Refuse ROMs whose header checksum is wrong, as the boot ROM does,
+    /// instead of only warning.
+    pub strict_header_checksum: bool,
+    pub verify_logo: LogoCheck,
+    /// Let the CPU reach VRAM and OAM while the PPU holds them, for
+    /// debugging.
+    pub unrestricted_vram_oam: bool,
+    /// The colors `frame_rgba` shows the shades in.
+    pub palette: Palette,
+}
 
 pub struct Emulator {
     cpu: Cpu,
+    config: Config,
+    header: CartridgeHeader,
+    /// Where the battery-backed RAM of the loaded game is kept, if it has
+    /// any.
+    save_path: Option<PathBuf>,
 }
 
 impl Emulator {
+    /// There is no boot ROM to run, so the CPU starts in the state it would
+    /// leave behind.
     pub fn new() -> Emulator {
+        Emulator::with_config(Config::default())
+    }
+
+    pub fn with_config(config: Config) -> Emulator {
+        let mut cpu = Cpu::new();
+        cpu.reset_post_boot();
+        cpu.set_ppu_access_restricted(!config.unrestricted_vram_oam);
         Emulator {
-            cpu: Cpu::new(),
+            cpu,
+            config,
+            header: CartridgeHeader::default(),
+            save_path: None,
+        }
+    }
+
+    /// Loads the game at `rom`. Games with battery-backed RAM pick up their
+    /// save from the `.sav` file next to it, starting blank when there is
+    /// none yet.
+    pub fn load_rom(&mut self, rom: &str) -> Result<(), RomError> {
+        let path = Path::new(rom);
+        let rom = std::fs::read(path).map_err(|error| RomError::Io { path: path.to_path_buf(), kind: error.kind() })?;
+        self.load_rom_bytes(&rom)?;
+        if !self.has_save() {
+            return Ok(());
+        }
+        let save_path = path.with_extension("sav");
+        match self.load_ram(&save_path) {
+            Err(error) if error.kind() != io::ErrorKind::NotFound => {
+                eprintln!("warning: could not load {}: {}", save_path.display(), error);
+            }
+            _ => {},
+        }
+        self.save_path = Some(save_path);
+        Ok(())
+    }
+
+    /// Loads the game from a ROM image in memory and restarts on it, as
+    /// `reset` does. A bad header checksum or logo only fails the load in
+    /// strict mode. Nothing ties the game to a save file; see `load_ram` and
+    /// `save_ram`.
+    pub fn load_rom_bytes(&mut self, rom: &[u8]) -> Result<(), RomError> {
+        if let Err(error) = self.flush_ram() {
+            eprintln!("warning: could not save cartridge RAM: {}", error);
+        }
+        cartridge::check_rom_size(rom)?;
+        let header = CartridgeHeader::parse(rom);
+        if !header.header_checksum_ok {
+            let error = RomError::BadHeaderChecksum { expected: header.header_checksum, computed: cartridge::header_checksum(rom) };
+            if self.config.strict_header_checksum {
+                return Err(error);
+            }
+            eprintln!("warning: {}", error);
+        }
+        if !header.logo_ok {
+            match self.config.verify_logo {
+                LogoCheck::Skip => {},
+                LogoCheck::Warn => eprintln!("warning: {}", RomError::BadLogo),
+                LogoCheck::Strict => return Err(RomError::BadLogo),
+            }
+        }
+        if !header.global_checksum_ok {
+            eprintln!("warning: global checksum does not match {:04X}", header.global_checksum);
+        }
+        match header.rom_size_bytes() {
+            Some(size) if size != rom.len() => eprintln!("warning: ROM is {} bytes, its header says {}", rom.len(), size),
+            None => eprintln!("warning: unknown ROM size code {:#04x}", header.rom_size),
+            _ => {},
+        }
+        self.header = header;
+        self.save_path = None;
+        self.cpu.load_rom(rom.to_vec())?;
+        self.cpu.reset();
+        Ok(())
+    }
+
+    /// Fills the cartridge RAM from a raw save file. A file of the wrong size
+    /// is truncated or padded with zeros.
+    pub fn load_ram(&mut self, path: impl AsRef<Path>) -> io::Result<()> {
+        let path = path.as_ref();
+        let mut save = std::fs::read(path)?;
+        let ram = self.cpu.cartridge_mut().ram_mut();
+        if save.len() != ram.len() {
+            eprintln!("warning: {} holds {} bytes, expected {}", path.display(), save.len(), ram.len());
+            save.resize(ram.len(), 0);
+        }
+        ram.copy_from_slice(&save);
+        Ok(())
+    }
+
+    /// Writes the cartridge RAM to a raw save file, as long as the cartridge
+    /// has a battery to keep it.
+    pub fn save_ram(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        if !self.has_save() {
+            return Ok(());
+        }
+        std::fs::write(path, self.cpu.cartridge().ram())
+    }
+
+    /// Whether the loaded game has battery-backed RAM to save. A battery with
+    /// no RAM, like that of an MBC3 with only the clock, leaves nothing.
+    fn has_save(&self) -> bool {
+        cartridge::has_battery(&self.header) && !self.cpu.cartridge().ram().is_empty()
+    }
+
+    /// Saves the cartridge RAM to the loaded game's `.sav` file. This also
+    /// happens when the emulator is dropped.
+    pub fn flush_ram(&self) -> io::Result<()> {
+        match &self.save_path {
+            Some(path) => self.save_ram(path),
+            None => Ok(()),
+        }
+    }
+
+    /// Calls `callback` with `true` when the cartridge starts its rumble
+    /// motor and `false` when it stops it, for frontends with force feedback.
+    pub fn set_rumble_callback(&mut self, callback: Box<dyn FnMut(bool)>) {
+        self.cpu.set_rumble_callback(callback);
+    }
+
+    /// The last complete frame, as shades 0-3 row by row: 0 is the
+    /// lightest.
+    pub fn frame(&self) -> &[u8; SCREEN_WIDTH * SCREEN_HEIGHT] {
+        self.cpu.ppu().frame()
+    }
+
+    /// Writes the last complete frame into `buffer` as RGBA8888, row by
+    /// row, in the colors of the configured palette.
+    ///
+    /// # Panics
+    ///
+    /// If `buffer` is not 4 bytes per pixel long.
+    pub fn frame_rgba(&self, buffer: &mut [u8]) {
+        assert_eq!(buffer.len(), SCREEN_WIDTH * SCREEN_HEIGHT * 4, "an RGBA frame is 4 bytes per pixel");
+        for (pixel, &shade) in buffer.chunks_exact_mut(4).zip(self.frame().iter()) {
+            pixel.copy_from_slice(&self.config.palette.rgba(shade));
+        }
+    }
+
+    /// The last completed frame as a binary PPM (P6) image in the palette's
+    /// colors, ready to write out as a file.
+    pub fn frame_ppm(&self) -> Vec<u8> {
+        let mut ppm = format!("P6\n{} {}\n255\n", SCREEN_WIDTH, SCREEN_HEIGHT).into_bytes();
+        for &shade in self.frame().iter() {
+            ppm.extend_from_slice(&self.config.palette.rgba(shade)[..3]);
+        }
+        ppm
+    }
+
+    /// How many frames have been completed. A change means `frame` has a
+    /// new one.
+    pub fn frame_count(&self) -> u64 {
+        self.cpu.ppu().frame_count()
+    }
+
+    /// Calls `sink` at the start of each VBlank with the frame just
+    /// completed and its number, replacing any sink set before.
+    pub fn set_frame_sink(&mut self, sink: FrameSink) {
+        self.cpu.set_frame_sink(Some(sink));
+    }
+
+    pub fn remove_frame_sink(&mut self) {
+        self.cpu.set_frame_sink(None);
+    }
+
+    /// Changes the colors `frame_rgba` shows the shades in. The frame is
+    /// kept as shades, so this applies to it straight away.
+    pub fn set_palette(&mut self, palette: Palette) {
+        self.config.palette = palette;
+    }
+
+    pub fn palette(&self) -> Palette {
+        self.config.palette
+    }
+
+    /// Draws the 384 tiles in VRAM into `buffer` as color numbers; see
+    /// `Ppu::debug_render_tiles`.
+    pub fn debug_render_tiles(&self, buffer: &mut [u8]) {
+        self.cpu.ppu().debug_render_tiles(self.cpu.vram(), buffer);
+    }
+
+    /// Draws the picture tile map `map` makes into `buffer` as shades,
+    /// returning where the screen's view into it starts; see
+    /// `Ppu::debug_render_tilemap`.
+    pub fn debug_render_tilemap(&self, map: TileMap, buffer: &mut [u8]) -> (u8, u8) {
+        self.cpu.ppu().debug_render_tilemap(self.cpu.vram(), map, buffer)
+    }
+
+    /// The 40 sprites in OAM, decoded.
+    pub fn debug_sprites(&self) -> [SpriteEntry; 40] {
+        self.cpu.ppu().debug_sprites(self.cpu.oam())
+    }
+
+    /// The header of the loaded game.
+    pub fn header(&self) -> &CartridgeHeader {
+        &self.header
+    }
+
+    /// Boots through `boot_rom` rather than the post-boot register preset.
+    pub fn load_boot_rom(&mut self, boot_rom: &[u8; 0x100]) {
+        self.cpu.load_boot_rom(boot_rom);
+    }
+
+    /// The CPU, for inspecting registers and memory between steps.
+    pub fn cpu(&self) -> &Cpu {
+        &self.cpu
+    }
+
+    pub fn read_mem(&self, address: u16) -> u8 {
+        self.cpu.read_mem(address)
+    }
+
+    pub fn write_mem(&mut self, address: u16, value: u8) {
+        self.cpu.write_mem(address, value);
+    }
+
+    /// Restarts the loaded game from its entry point.
+    pub fn reset(&mut self) {
+        self.cpu.reset();
+    }
+
+    /// Runs one CPU instruction, returning the T-cycles it took or the error
+    /// that stopped the CPU along with the offending opcode and address.
+    pub fn step(&mut self) -> Result<u8, CpuError> {
+        self.cpu.step()
+    }
+}
+
+impl Drop for Emulator {
+    fn drop(&mut self) {
+        if let Err(error) = self.flush_ram() {
+            eprintln!("warning: could not save cartridge RAM: {}", error);
         }
     }
+}
 
-    pub fn load_rom(&mut self, rom: &str) {
-        let rom = std::fs::read(rom).unwrap();
-        self.cpu.load_rom(rom);
+impl Default for Emulator {
+    fn default() -> Self {
+        Self::new()
     }
-}
\ No newline at end of file
+}
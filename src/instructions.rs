@@ -1 +1,379 @@
+/// An 8-bit register.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Register {
+    A,
+    B,
+    C,
+    D,
+    E,
+    H,
+    L,
+}
 
+/// A 16-bit register pair. AF only appears in PUSH and POP, which use it in
+/// place of SP.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RegisterPair {
+    Af,
+    Bc,
+    De,
+    Hl,
+    Sp,
+}
+
+/// The flag conditions of conditional jumps, calls and returns.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Condition {
+    NotZero,
+    Zero,
+    NotCarry,
+    Carry,
+}
+
+/// Where an 8-bit value is read from or written to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operand {
+    Register(Register),
+    Immediate(u8),
+    /// (BC), (DE) or (HL).
+    Indirect(RegisterPair),
+    /// (HL+): HL is incremented after the access.
+    HlIncrement,
+    /// (HL-): HL is decremented after the access.
+    HlDecrement,
+    /// (nn)
+    Absolute(u16),
+    /// (0xFF00 + n)
+    High(u8),
+    /// (0xFF00 + C)
+    HighC,
+}
+
+/// The operation selected by a 0xCB-prefixed opcode. Bits 7-6 select the
+/// class, bits 5-3 the sub-operation or bit index.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CbOperation {
+    Rlc,
+    Rrc,
+    Rl,
+    Rr,
+    Sla,
+    Sra,
+    Swap,
+    Srl,
+    Bit(u8),
+    Res(u8),
+    Set(u8),
+}
+
+/// A decoded instruction, immediate operands included. Branches without a
+/// condition are unconditional.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Instruction {
+    Nop,
+    Stop,
+    Halt,
+    Di,
+    Ei,
+    /// LD target, source
+    Ld(Operand, Operand),
+    Ld16(RegisterPair, u16),
+    /// LD (nn), SP
+    LdAbsoluteSp(u16),
+    LdSpHl,
+    /// LD HL, SP+e
+    LdHlSpOffset(i8),
+    Push(RegisterPair),
+    Pop(RegisterPair),
+    Add(Operand),
+    Adc(Operand),
+    Sub(Operand),
+    Sbc(Operand),
+    And(Operand),
+    Xor(Operand),
+    Or(Operand),
+    Cp(Operand),
+    Inc(Operand),
+    Dec(Operand),
+    Inc16(RegisterPair),
+    Dec16(RegisterPair),
+    AddHl(RegisterPair),
+    AddSp(i8),
+    Rlca,
+    Rrca,
+    Rla,
+    Rra,
+    Daa,
+    Cpl,
+    Scf,
+    Ccf,
+    Jp(Option<Condition>, u16),
+    JpHl,
+    Jr(Option<Condition>, i8),
+    Call(Option<Condition>, u16),
+    Ret(Option<Condition>),
+    Reti,
+    Rst(u16),
+    Cb(CbOperation, Operand),
+    /// One of the opcodes the SM83 leaves undefined.
+    Illegal(u8),
+}
+
+/// The opcodes the SM83 leaves undefined. Executing one hangs the CPU.
+pub const ILLEGAL_OPCODES: [u8; 11] = [0xD3, 0xDB, 0xDD, 0xE3, 0xE4, 0xEB, 0xEC, 0xED, 0xF4, 0xFC, 0xFD];
+
+/// Maps the 3-bit operand encoding used by the opcode table to an operand,
+/// 6 standing for the byte pointed to by HL.
+fn operand_from_bits(bits: u8) -> Operand {
+    match bits & 0b111 {
+        0 => Operand::Register(Register::B),
+        1 => Operand::Register(Register::C),
+        2 => Operand::Register(Register::D),
+        3 => Operand::Register(Register::E),
+        4 => Operand::Register(Register::H),
+        5 => Operand::Register(Register::L),
+        6 => Operand::Indirect(RegisterPair::Hl),
+        _ => Operand::Register(Register::A),
+    }
+}
+
+/// The register pair in bits 5-4, with SP as the fourth pair.
+fn pair_from_bits(opcode: u8) -> RegisterPair {
+    match (opcode >> 4) & 0b11 {
+        0 => RegisterPair::Bc,
+        1 => RegisterPair::De,
+        2 => RegisterPair::Hl,
+        _ => RegisterPair::Sp,
+    }
+}
+
+/// The register pair in bits 5-4 of PUSH and POP, where AF replaces SP.
+fn stack_pair_from_bits(opcode: u8) -> RegisterPair {
+    match pair_from_bits(opcode) {
+        RegisterPair::Sp => RegisterPair::Af,
+        pair => pair,
+    }
+}
+
+/// The NZ/Z/NC/C condition encoded in bits 4-3.
+fn condition_from_bits(opcode: u8) -> Option<Condition> {
+    Some(match (opcode >> 3) & 0b11 {
+        0 => Condition::NotZero,
+        1 => Condition::Zero,
+        2 => Condition::NotCarry,
+        _ => Condition::Carry,
+    })
+}
+
+/// The ALU operation selected by bits 5-3 of the 0x80–0xBF block and of the
+/// immediate forms in the 0xC6–0xFE column.
+fn alu(opcode: u8, operand: Operand) -> Instruction {
+    match (opcode >> 3) & 0b111 {
+        0 => Instruction::Add(operand),
+        1 => Instruction::Adc(operand),
+        2 => Instruction::Sub(operand),
+        3 => Instruction::Sbc(operand),
+        4 => Instruction::And(operand),
+        5 => Instruction::Xor(operand),
+        6 => Instruction::Or(operand),
+        _ => Instruction::Cp(operand),
+    }
+}
+
+/// Decodes a 0xCB-prefixed opcode into its operation and operand.
+pub fn decode_cb(opcode: u8) -> (CbOperation, Operand) {
+    let index = (opcode >> 3) & 0b111;
+    let operation = match opcode >> 6 {
+        0 => match index {
+            0 => CbOperation::Rlc,
+            1 => CbOperation::Rrc,
+            2 => CbOperation::Rl,
+            3 => CbOperation::Rr,
+            4 => CbOperation::Sla,
+            5 => CbOperation::Sra,
+            6 => CbOperation::Swap,
+            _ => CbOperation::Srl,
+        },
+        1 => CbOperation::Bit(index),
+        2 => CbOperation::Res(index),
+        _ => CbOperation::Set(index),
+    };
+    (operation, operand_from_bits(opcode))
+}
+
+/// Decodes the instruction starting with `opcode`, pulling any further bytes
+/// (immediates and the second byte of 0xCB opcodes) from `fetch`.
+pub fn decode(opcode: u8, mut fetch: impl FnMut() -> u8) -> Instruction {
+    let mut fetch_word = || {
+        let low = fetch();
+        low as u16 + ((fetch() as u16) << 8)
+    };
+
+    match opcode {
+        0x00 => Instruction::Nop,
+        0x01 | 0x11 | 0x21 | 0x31 => Instruction::Ld16(pair_from_bits(opcode), fetch_word()),
+        0x02 | 0x12 => Instruction::Ld(Operand::Indirect(pair_from_bits(opcode)), Operand::Register(Register::A)),
+        0x03 | 0x13 | 0x23 | 0x33 => Instruction::Inc16(pair_from_bits(opcode)),
+        0x04 | 0x0C | 0x14 | 0x1C | 0x24 | 0x2C | 0x34 | 0x3C => Instruction::Inc(operand_from_bits(opcode >> 3)),
+        0x05 | 0x0D | 0x15 | 0x1D | 0x25 | 0x2D | 0x35 | 0x3D => Instruction::Dec(operand_from_bits(opcode >> 3)),
+        0x06 | 0x0E | 0x16 | 0x1E | 0x26 | 0x2E | 0x36 | 0x3E => Instruction::Ld(operand_from_bits(opcode >> 3), Operand::Immediate(fetch())),
+        0x07 => Instruction::Rlca,
+        0x08 => Instruction::LdAbsoluteSp(fetch_word()),
+        0x09 | 0x19 | 0x29 | 0x39 => Instruction::AddHl(pair_from_bits(opcode)),
+        0x0A | 0x1A => Instruction::Ld(Operand::Register(Register::A), Operand::Indirect(pair_from_bits(opcode))),
+        0x0B | 0x1B | 0x2B | 0x3B => Instruction::Dec16(pair_from_bits(opcode)),
+        0x0F => Instruction::Rrca,
+        0x10 => { fetch(); Instruction::Stop },
+        0x17 => Instruction::Rla,
+        0x18 => Instruction::Jr(None, fetch() as i8),
+        0x1F => Instruction::Rra,
+        0x20 | 0x28 | 0x30 | 0x38 => Instruction::Jr(condition_from_bits(opcode), fetch() as i8),
+        0x22 => Instruction::Ld(Operand::HlIncrement, Operand::Register(Register::A)),
+        0x27 => Instruction::Daa,
+        0x2A => Instruction::Ld(Operand::Register(Register::A), Operand::HlIncrement),
+        0x2F => Instruction::Cpl,
+        0x32 => Instruction::Ld(Operand::HlDecrement, Operand::Register(Register::A)),
+        0x37 => Instruction::Scf,
+        0x3A => Instruction::Ld(Operand::Register(Register::A), Operand::HlDecrement),
+        0x3F => Instruction::Ccf,
+        0x76 => Instruction::Halt,
+        0xC0 | 0xC8 | 0xD0 | 0xD8 => Instruction::Ret(condition_from_bits(opcode)),
+        0xC1 | 0xD1 | 0xE1 | 0xF1 => Instruction::Pop(stack_pair_from_bits(opcode)),
+        0xC2 | 0xCA | 0xD2 | 0xDA => Instruction::Jp(condition_from_bits(opcode), fetch_word()),
+        0xC3 => Instruction::Jp(None, fetch_word()),
+        0xC4 | 0xCC | 0xD4 | 0xDC => Instruction::Call(condition_from_bits(opcode), fetch_word()),
+        0xC5 | 0xD5 | 0xE5 | 0xF5 => Instruction::Push(stack_pair_from_bits(opcode)),
+        0xC6 | 0xCE | 0xD6 | 0xDE | 0xE6 | 0xEE | 0xF6 | 0xFE => alu(opcode, Operand::Immediate(fetch())),
+        0xC7 | 0xCF | 0xD7 | 0xDF | 0xE7 | 0xEF | 0xF7 | 0xFF => Instruction::Rst((opcode & 0x38) as u16),
+        0xC9 => Instruction::Ret(None),
+        0xCB => { let (operation, operand) = decode_cb(fetch()); Instruction::Cb(operation, operand) },
+        0xCD => Instruction::Call(None, fetch_word()),
+        0xD9 => Instruction::Reti,
+        0xE0 => Instruction::Ld(Operand::High(fetch()), Operand::Register(Register::A)),
+        0xE2 => Instruction::Ld(Operand::HighC, Operand::Register(Register::A)),
+        0xE8 => Instruction::AddSp(fetch() as i8),
+        0xE9 => Instruction::JpHl,
+        0xEA => Instruction::Ld(Operand::Absolute(fetch_word()), Operand::Register(Register::A)),
+        0xF0 => Instruction::Ld(Operand::Register(Register::A), Operand::High(fetch())),
+        0xF2 => Instruction::Ld(Operand::Register(Register::A), Operand::HighC),
+        0xF3 => Instruction::Di,
+        0xF8 => Instruction::LdHlSpOffset(fetch() as i8),
+        0xF9 => Instruction::LdSpHl,
+        0xFA => Instruction::Ld(Operand::Register(Register::A), Operand::Absolute(fetch_word())),
+        0xFB => Instruction::Ei,
+        0x40..=0x7F => Instruction::Ld(operand_from_bits(opcode >> 3), operand_from_bits(opcode)),
+        0x80..=0xBF => alu(opcode, operand_from_bits(opcode)),
+        _ => Instruction::Illegal(opcode),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Decodes `bytes`, checking that exactly the instruction's bytes are used.
+    fn decode_bytes(bytes: &[u8]) -> Instruction {
+        let mut operands = bytes[1..].iter();
+        let instruction = decode(bytes[0], || *operands.next().expect("decoder read past the instruction"));
+        assert_eq!(operands.len(), 0, "{:02X?} left operands unread", bytes);
+        instruction
+    }
+
+    #[test]
+    fn test_decode_loads() {
+        let a = Operand::Register(Register::A);
+        let cases = [
+            (vec![0x41], Instruction::Ld(Operand::Register(Register::B), Operand::Register(Register::C))),
+            (vec![0x7E], Instruction::Ld(a, Operand::Indirect(RegisterPair::Hl))),
+            (vec![0x70], Instruction::Ld(Operand::Indirect(RegisterPair::Hl), Operand::Register(Register::B))),
+            (vec![0x36, 0x12], Instruction::Ld(Operand::Indirect(RegisterPair::Hl), Operand::Immediate(0x12))),
+            (vec![0x1A], Instruction::Ld(a, Operand::Indirect(RegisterPair::De))),
+            (vec![0x22], Instruction::Ld(Operand::HlIncrement, a)),
+            (vec![0x3A], Instruction::Ld(a, Operand::HlDecrement)),
+            (vec![0xEA, 0x34, 0x12], Instruction::Ld(Operand::Absolute(0x1234), a)),
+            (vec![0xF0, 0x44], Instruction::Ld(a, Operand::High(0x44))),
+            (vec![0xE2], Instruction::Ld(Operand::HighC, a)),
+            (vec![0x31, 0xFE, 0xFF], Instruction::Ld16(RegisterPair::Sp, 0xFFFE)),
+            (vec![0x08, 0x00, 0xC1], Instruction::LdAbsoluteSp(0xC100)),
+            (vec![0xF8, 0xFE], Instruction::LdHlSpOffset(-2)),
+            (vec![0xF9], Instruction::LdSpHl),
+            (vec![0xF5], Instruction::Push(RegisterPair::Af)),
+            (vec![0xC1], Instruction::Pop(RegisterPair::Bc)),
+        ];
+
+        for (bytes, expected) in cases {
+            assert_eq!(decode_bytes(&bytes), expected, "{:02X?}", bytes);
+        }
+    }
+
+    #[test]
+    fn test_decode_arithmetic() {
+        let cases = [
+            (vec![0x80], Instruction::Add(Operand::Register(Register::B))),
+            (vec![0x8E], Instruction::Adc(Operand::Indirect(RegisterPair::Hl))),
+            (vec![0x97], Instruction::Sub(Operand::Register(Register::A))),
+            (vec![0xDE, 0x01], Instruction::Sbc(Operand::Immediate(0x01))),
+            (vec![0xA4], Instruction::And(Operand::Register(Register::H))),
+            (vec![0xEE, 0xFF], Instruction::Xor(Operand::Immediate(0xFF))),
+            (vec![0xB5], Instruction::Or(Operand::Register(Register::L))),
+            (vec![0xFE, 0x90], Instruction::Cp(Operand::Immediate(0x90))),
+            (vec![0x34], Instruction::Inc(Operand::Indirect(RegisterPair::Hl))),
+            (vec![0x0D], Instruction::Dec(Operand::Register(Register::C))),
+            (vec![0x13], Instruction::Inc16(RegisterPair::De)),
+            (vec![0x3B], Instruction::Dec16(RegisterPair::Sp)),
+            (vec![0x29], Instruction::AddHl(RegisterPair::Hl)),
+            (vec![0xE8, 0x80], Instruction::AddSp(-128)),
+            (vec![0x27], Instruction::Daa),
+        ];
+
+        for (bytes, expected) in cases {
+            assert_eq!(decode_bytes(&bytes), expected, "{:02X?}", bytes);
+        }
+    }
+
+    #[test]
+    fn test_decode_control_flow() {
+        let cases = [
+            (vec![0xC3, 0x50, 0x01], Instruction::Jp(None, 0x0150)),
+            (vec![0xDA, 0x00, 0x40], Instruction::Jp(Some(Condition::Carry), 0x4000)),
+            (vec![0xE9], Instruction::JpHl),
+            (vec![0x18, 0xFE], Instruction::Jr(None, -2)),
+            (vec![0x20, 0x05], Instruction::Jr(Some(Condition::NotZero), 5)),
+            (vec![0xCC, 0x34, 0x12], Instruction::Call(Some(Condition::Zero), 0x1234)),
+            (vec![0xCD, 0x34, 0x12], Instruction::Call(None, 0x1234)),
+            (vec![0xD0], Instruction::Ret(Some(Condition::NotCarry))),
+            (vec![0xC9], Instruction::Ret(None)),
+            (vec![0xD9], Instruction::Reti),
+            (vec![0xEF], Instruction::Rst(0x28)),
+            (vec![0x10, 0x00], Instruction::Stop),
+            (vec![0x76], Instruction::Halt),
+            (vec![0xFB], Instruction::Ei),
+        ];
+
+        for (bytes, expected) in cases {
+            assert_eq!(decode_bytes(&bytes), expected, "{:02X?}", bytes);
+        }
+    }
+
+    #[test]
+    fn test_decode_cb() {
+        assert_eq!(decode_cb(0x00), (CbOperation::Rlc, Operand::Register(Register::B)));
+        assert_eq!(decode_cb(0x1E), (CbOperation::Rr, Operand::Indirect(RegisterPair::Hl)));
+        assert_eq!(decode_cb(0x37), (CbOperation::Swap, Operand::Register(Register::A)));
+        assert_eq!(decode_cb(0x3F), (CbOperation::Srl, Operand::Register(Register::A)));
+        assert_eq!(decode_cb(0x7C), (CbOperation::Bit(7), Operand::Register(Register::H)));
+        assert_eq!(decode_cb(0x86), (CbOperation::Res(0), Operand::Indirect(RegisterPair::Hl)));
+        assert_eq!(decode_cb(0xD9), (CbOperation::Set(3), Operand::Register(Register::C)));
+        assert_eq!(decode_cb(0xFF), (CbOperation::Set(7), Operand::Register(Register::A)));
+
+        assert_eq!(decode_bytes(&[0xCB, 0x7E]), Instruction::Cb(CbOperation::Bit(7), Operand::Indirect(RegisterPair::Hl)));
+    }
+
+    #[test]
+    fn test_decode_illegal_opcodes() {
+        for opcode in 0..=0xFF {
+            let instruction = decode(opcode, || 0);
+            assert_eq!(instruction == Instruction::Illegal(opcode), ILLEGAL_OPCODES.contains(&opcode), "opcode {:02X}", opcode);
+        }
+    }
+}
@@ -0,0 +1,115 @@
+/// The eight DMG buttons. Directions and actions share the low nibble of P1,
+/// the row being selected by writing bits 4 and 5.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Button {
+    Right,
+    Left,
+    Up,
+    Down,
+    A,
+    B,
+    Select,
+    Start,
+}
+
+impl Button {
+    /// Bit of the button in `Joypad::pressed`: directions in the low nibble,
+    /// actions in the high nibble, each in P1 line order.
+    fn mask(self) -> u8 {
+        match self {
+            Button::Right => 0x01,
+            Button::Left => 0x02,
+            Button::Up => 0x04,
+            Button::Down => 0x08,
+            Button::A => 0x10,
+            Button::B => 0x20,
+            Button::Select => 0x40,
+            Button::Start => 0x80,
+        }
+    }
+}
+
+const SELECT_DIRECTIONS: u8 = 0x10;
+const SELECT_ACTIONS: u8 = 0x20;
+
+/// The P1/JOYP register at 0xFF00.
+pub struct Joypad {
+    pressed: u8,
+    select: u8,
+}
+
+impl Joypad {
+    pub fn new() -> Joypad {
+        Joypad {
+            pressed: 0,
+            select: SELECT_DIRECTIONS | SELECT_ACTIONS,
+        }
+    }
+
+    /// Presses `button`, returning whether this pulled a P1 line low. That is
+    /// the condition for both the joypad interrupt and leaving STOP.
+    pub fn press(&mut self, button: Button) -> bool {
+        let newly_pressed = self.pressed & button.mask() == 0;
+        self.pressed |= button.mask();
+        newly_pressed
+    }
+
+    pub fn release(&mut self, button: Button) {
+        self.pressed &= !button.mask();
+    }
+
+    /// Lines are active low, and bits 6-7 are unused and read as 1.
+    pub fn read(&self) -> u8 {
+        let mut lines = 0;
+        if self.select & SELECT_DIRECTIONS == 0 {
+            lines |= self.pressed & 0x0F;
+        }
+        if self.select & SELECT_ACTIONS == 0 {
+            lines |= self.pressed >> 4;
+        }
+        0xC0 | self.select | (!lines & 0x0F)
+    }
+
+    /// Only the row select bits are writable.
+    pub fn write(&mut self, value: u8) {
+        self.select = value & (SELECT_DIRECTIONS | SELECT_ACTIONS);
+    }
+}
+
+impl Default for Joypad {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_selected_row() {
+        let mut joypad = Joypad::new();
+        joypad.press(Button::Down);
+        joypad.press(Button::A);
+        assert_eq!(joypad.read(), 0xFF);
+
+        joypad.write(0x20); // select directions
+        assert_eq!(joypad.read(), 0xE7);
+
+        joypad.write(0x10); // select actions
+        assert_eq!(joypad.read(), 0xDE);
+
+        joypad.release(Button::A);
+        assert_eq!(joypad.read(), 0xDF);
+    }
+
+    #[test]
+    fn test_press_reports_new_presses_only() {
+        let mut joypad = Joypad::new();
+        assert!(joypad.press(Button::Start));
+        assert!(!joypad.press(Button::Start));
+
+        joypad.release(Button::Start);
+        assert!(joypad.press(Button::Start));
+    }
+}
@@ -0,0 +1,8 @@
+pub mod cartridge;
+pub mod emulator;
+pub mod cpu;
+pub mod instructions;
+pub mod joypad;
+pub mod memory;
+pub mod ppu;
+pub mod timer;
@@ -1,7 +1,17 @@
-pub mod emulator;
-pub mod cpu;
-pub mod instructions;
+use game_boy_dmg_emulator::emulator::Emulator;
 
 fn main() {
-    let mut emulator = emulator::Emulator::new();
+    let mut emulator = Emulator::new();
+    if let Some(rom) = std::env::args().nth(1) {
+        if let Err(error) = emulator.load_rom(&rom) {
+            eprintln!("{}", error);
+            return;
+        }
+        loop {
+            if let Err(error) = emulator.step() {
+                eprintln!("{}", error);
+                break;
+            }
+        }
+    }
 }
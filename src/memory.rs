@@ -0,0 +1,617 @@
+pub mod io;
+
+use crate::cartridge::{Cartridge, RomOnly};
+use crate::ppu::{FrameSink, Mode, Ppu};
+use io::{Io, BOOT, DMA};
+
+/// The areas of the address space, each decoded separately by the bus.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MemoryRegion {
+    /// 0x0000-0x7FFF, cartridge ROM and bank controller registers.
+    Rom,
+    /// 0x8000-0x9FFF
+    Vram,
+    /// 0xA000-0xBFFF, cartridge RAM.
+    ExternalRam,
+    /// 0xC000-0xDFFF
+    Wram,
+    /// 0xE000-0xFDFF, a mirror of 0xC000-0xDDFF.
+    Echo,
+    /// 0xFE00-0xFE9F
+    Oam,
+    /// 0xFEA0-0xFEFF
+    Unusable,
+    /// 0xFF00-0xFF7F
+    Io,
+    /// 0xFF80-0xFFFE
+    Hram,
+    /// 0xFFFF
+    InterruptEnable,
+}
+
+/// The region `address` falls in.
+pub fn region(address: u16) -> MemoryRegion {
+    match address {
+        0x0000..=0x7FFF => MemoryRegion::Rom,
+        0x8000..=0x9FFF => MemoryRegion::Vram,
+        0xA000..=0xBFFF => MemoryRegion::ExternalRam,
+        0xC000..=0xDFFF => MemoryRegion::Wram,
+        0xE000..=0xFDFF => MemoryRegion::Echo,
+        0xFE00..=0xFE9F => MemoryRegion::Oam,
+        0xFEA0..=0xFEFF => MemoryRegion::Unusable,
+        0xFF00..=0xFF7F => MemoryRegion::Io,
+        0xFF80..=0xFFFE => MemoryRegion::Hram,
+        0xFFFF => MemoryRegion::InterruptEnable,
+    }
+}
+
+/// What reads return where nothing drives the bus: unmapped I/O, missing
+/// cartridge RAM, and memory the CPU is locked out of.
+pub const OPEN_BUS: u8 = 0xFF;
+
+/// What the DMG reads from 0xFEA0-0xFEFF while OAM is open to the CPU,
+/// leaving aside the OAM corruption quirk, which is not emulated. Writes
+/// there are ignored.
+const UNUSABLE_READ: u8 = 0x00;
+
+/// An OAM DMA transfer copies 160 bytes, one per machine cycle.
+const DMA_CYCLES: u16 = 160 * 4;
+
+pub struct MemoryBus {
+    vram: [u8; 0x2000],
+    wram: [u8; 0x2000],
+    oam: [u8; 0xA0],
+    hram: [u8; 0x7F],
+    interrupt_enable: u8,
+    cartridge: Box<dyn Cartridge>,
+    pub(crate) io: Io,
+    /// T-cycles left in the running OAM DMA transfer.
+    dma_cycles: u16,
+    boot_rom: Option<Box<[u8; 0x100]>>,
+    /// Whether the boot ROM still overlays 0x0000-0x00FF.
+    boot_rom_mapped: bool,
+    /// Whether VRAM and OAM are closed to the CPU while the PPU uses them.
+    ppu_access_restricted: bool,
+    /// Told whenever the cartridge switches its rumble motor on or off.
+    rumble_callback: Option<Box<dyn FnMut(bool)>>,
+}
+
+impl MemoryBus {
+    pub fn new() -> MemoryBus {
+        MemoryBus {
+            vram: [0; 0x2000],
+            wram: [0; 0x2000],
+            oam: [0; 0xA0],
+            hram: [0; 0x7F],
+            interrupt_enable: 0x00,
+            cartridge: Box::new(RomOnly::with_ram(vec![0; 0x8000])),
+            io: Io::new(),
+            dma_cycles: 0,
+            boot_rom: None,
+            boot_rom_mapped: false,
+            ppu_access_restricted: true,
+            rumble_callback: None,
+        }
+    }
+
+    /// Swaps in a new cartridge. Until then a blank one with 32 KiB of ROM
+    /// and 8 KiB of RAM is inserted.
+    pub fn load_cartridge(&mut self, cartridge: Box<dyn Cartridge>) {
+        self.cartridge = cartridge;
+    }
+
+    pub fn cartridge(&self) -> &dyn Cartridge {
+        &*self.cartridge
+    }
+
+    pub fn cartridge_mut(&mut self) -> &mut dyn Cartridge {
+        &mut *self.cartridge
+    }
+
+    pub fn ppu(&self) -> &Ppu {
+        &self.io.ppu
+    }
+
+    /// VRAM as it is, whatever the PPU is doing.
+    pub fn vram(&self) -> &[u8; 0x2000] {
+        &self.vram
+    }
+
+    /// OAM as it is, with any DMA transfer already copied in.
+    pub fn oam(&self) -> &[u8; 0xA0] {
+        &self.oam
+    }
+
+    pub fn set_frame_sink(&mut self, sink: Option<FrameSink>) {
+        self.io.ppu.set_frame_sink(sink);
+    }
+
+    /// Calls `callback` with the new motor state each time the cartridge
+    /// turns its rumble motor on or off. It carries over to later cartridges.
+    pub fn set_rumble_callback(&mut self, callback: Box<dyn FnMut(bool)>) {
+        self.rumble_callback = Some(callback);
+    }
+
+    fn write_cartridge_rom(&mut self, address: u16, value: u8) {
+        let rumble = self.cartridge.rumble();
+        self.cartridge.write_rom(address, value);
+        if self.cartridge.rumble() != rumble {
+            if let Some(callback) = self.rumble_callback.as_mut() {
+                callback(!rumble);
+            }
+        }
+    }
+
+    /// Pokes bytes straight into the backing storage, bypassing the bus.
+    /// ROM addresses patch the ROM image into a new `RomOnly` cartridge with
+    /// RAM, which takes over the old one's RAM.
+    #[cfg(test)]
+    pub fn set_range(&mut self, start: usize, len: usize, values: &[u8]) {
+        if start < 0x8000 {
+            let mut rom = self.cartridge.rom().to_vec();
+            rom.resize(rom.len().max(start + len), 0);
+            rom[start..(start + len)].copy_from_slice(values);
+            let mut cartridge = RomOnly::with_ram(rom);
+            for address in 0xA000..=0xBFFF {
+                cartridge.write_ram(address, self.cartridge.read_ram(address));
+            }
+            self.cartridge = Box::new(cartridge);
+        } else {
+            for (offset, &value) in values[..len].iter().enumerate() {
+                self.write_mapped((start + offset) as u16, value);
+            }
+        }
+    }
+
+    /// Lets the CPU reach VRAM and OAM in every PPU mode when off, for
+    /// debugging.
+    pub fn set_ppu_access_restricted(&mut self, restricted: bool) {
+        self.ppu_access_restricted = restricted;
+    }
+
+    /// The PPU holds VRAM while drawing, and OAM, along with the unusable
+    /// area after it, from the OAM scan on.
+    fn ppu_blocks(&self, address: u16) -> bool {
+        if !self.ppu_access_restricted || !self.io.ppu.lcd_enabled() {
+            return false;
+        }
+        match region(address) {
+            MemoryRegion::Vram => self.io.ppu.mode() == Mode::Drawing,
+            MemoryRegion::Oam | MemoryRegion::Unusable => matches!(self.io.ppu.mode(), Mode::OamScan | Mode::Drawing),
+            _ => false,
+        }
+    }
+
+    /// While an OAM DMA transfer runs, the CPU only reaches HRAM and the
+    /// registers; everything else reads as open bus. So does memory the PPU
+    /// holds.
+    pub fn read_byte(&self, address: u16) -> u8 {
+        if self.dma_blocks(address) || self.ppu_blocks(address) {
+            return OPEN_BUS;
+        }
+        self.read_mapped(address)
+    }
+
+    fn read_mapped(&self, address: u16) -> u8 {
+        match region(address) {
+            MemoryRegion::Rom if self.boot_rom_mapped && address < 0x100 => self.boot_rom.as_ref().map_or(OPEN_BUS, |boot_rom| boot_rom[address as usize]),
+            MemoryRegion::Rom => self.cartridge.read_rom(address),
+            MemoryRegion::ExternalRam => self.cartridge.read_ram(address),
+            MemoryRegion::Vram => self.vram[(address - 0x8000) as usize],
+            MemoryRegion::Wram => self.wram[(address - 0xC000) as usize],
+            MemoryRegion::Echo => self.wram[(address - 0xE000) as usize],
+            MemoryRegion::Oam => self.oam[(address - 0xFE00) as usize],
+            MemoryRegion::Unusable => UNUSABLE_READ,
+            MemoryRegion::Io => self.io.read(address),
+            MemoryRegion::Hram => self.hram[(address - 0xFF80) as usize],
+            MemoryRegion::InterruptEnable => self.interrupt_enable,
+        }
+    }
+
+    pub fn write_byte(&mut self, address: u16, value: u8) {
+        if self.dma_blocks(address) || self.ppu_blocks(address) {
+            return;
+        }
+        self.write_mapped(address, value);
+    }
+
+    fn write_mapped(&mut self, address: u16, value: u8) {
+        match region(address) {
+            MemoryRegion::Rom => self.write_cartridge_rom(address, value),
+            MemoryRegion::ExternalRam => self.cartridge.write_ram(address, value),
+            MemoryRegion::Vram => self.vram[(address - 0x8000) as usize] = value,
+            MemoryRegion::Wram => self.wram[(address - 0xC000) as usize] = value,
+            MemoryRegion::Echo => self.wram[(address - 0xE000) as usize] = value,
+            MemoryRegion::Oam => self.oam[(address - 0xFE00) as usize] = value,
+            MemoryRegion::Unusable => {},
+            MemoryRegion::Io if address == BOOT => if value != 0 { self.boot_rom_mapped = false },
+            MemoryRegion::Io if address == DMA => { self.io.write(address, value); self.start_dma(value) },
+            MemoryRegion::Io => self.io.write(address, value),
+            MemoryRegion::Hram => self.hram[(address - 0xFF80) as usize] = value,
+            MemoryRegion::InterruptEnable => self.interrupt_enable = value,
+        }
+    }
+
+    /// Overlays the 256-byte boot ROM on the cartridge until the boot ROM
+    /// unmaps itself through 0xFF50. Only reads see it.
+    pub fn load_boot_rom(&mut self, boot_rom: &[u8; 0x100]) {
+        self.boot_rom = Some(Box::new(*boot_rom));
+        self.boot_rom_mapped = true;
+    }
+
+    pub fn boot_rom_mapped(&self) -> bool {
+        self.boot_rom_mapped
+    }
+
+    /// Copies XX00-XX9F into OAM at once, then keeps the bus restricted for
+    /// as long as the transfer takes on hardware.
+    fn start_dma(&mut self, page: u8) {
+        let source = (page as u16) << 8;
+        for offset in 0..0xA0 {
+            self.oam[offset as usize] = self.read_mapped(source + offset);
+        }
+        self.dma_cycles = DMA_CYCLES;
+    }
+
+    pub fn dma_active(&self) -> bool {
+        self.dma_cycles > 0
+    }
+
+    fn dma_blocks(&self, address: u16) -> bool {
+        self.dma_active() && !matches!(region(address), MemoryRegion::Io | MemoryRegion::Hram | MemoryRegion::InterruptEnable)
+    }
+
+    /// Advances the bus by the T-cycles the CPU just spent.
+    pub fn tick(&mut self, cycles: u8) {
+        self.dma_cycles = self.dma_cycles.saturating_sub(cycles as u16);
+        self.cartridge.tick(cycles);
+        self.io.tick(cycles, &self.vram, &self.oam);
+    }
+
+    /// Reads a little-endian word.
+    pub fn read_word(&self, address: u16) -> u16 {
+        self.read_byte(address) as u16 | ((self.read_byte(address.wrapping_add(1)) as u16) << 8)
+    }
+
+    /// Writes a little-endian word.
+    pub fn write_word(&mut self, address: u16, value: u16) {
+        self.write_byte(address, value as u8);
+        self.write_byte(address.wrapping_add(1), (value >> 8) as u8);
+    }
+
+    /// Puts the I/O registers and IE in their post-boot state.
+    pub fn reset_io(&mut self) {
+        self.io.reset_post_boot();
+        self.interrupt_enable = 0x00;
+    }
+
+    /// Clears everything but the cartridge and the frame sink and rumble
+    /// callback, mapping the boot ROM back in if there is one.
+    pub fn reset(&mut self) {
+        self.boot_rom_mapped = self.boot_rom.is_some();
+        self.vram.fill(0);
+        self.wram.fill(0);
+        self.oam.fill(0);
+        self.hram.fill(0);
+        self.interrupt_enable = 0x00;
+        let frame_sink = self.io.ppu.take_frame_sink();
+        self.io = Io::new();
+        self.io.ppu.set_frame_sink(frame_sink);
+        self.dma_cycles = 0;
+    }
+}
+
+impl Default for MemoryBus {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cartridge::from_rom;
+    use io::{IF, LCDC, LY, LYC, STAT};
+
+    #[test]
+    fn test_words_are_little_endian() {
+        let mut bus = MemoryBus::new();
+        bus.write_word(0xC000, 0x1234);
+        assert_eq!(bus.read_byte(0xC000), 0x34);
+        assert_eq!(bus.read_byte(0xC001), 0x12);
+
+        bus.write_byte(0xFF80, 0xCD);
+        bus.write_byte(0xFF81, 0xAB);
+        assert_eq!(bus.read_word(0xFF80), 0xABCD);
+    }
+
+    #[test]
+    fn test_rom_writes_leave_rom_untouched() {
+        let mut bus = MemoryBus::new();
+        bus.set_range(0x0000, 4, &[0x31, 0xFE, 0xFF, 0x00]);
+        for address in [0x0000, 0x0002, 0x2000, 0x4000, 0x7FFF] {
+            bus.write_byte(address, 0x01);
+        }
+        bus.write_word(0x0000, 0xBEEF);
+
+        assert_eq!(bus.read_word(0x0000), 0xFE31);
+        assert_eq!(bus.read_byte(0x0002), 0xFF);
+        assert_eq!(bus.read_byte(0x2000), 0x00);
+        assert_eq!(bus.read_byte(0x7FFF), 0x00);
+
+        bus.write_byte(0x8000, 0x01);
+        assert_eq!(bus.read_byte(0x8000), 0x01);
+    }
+
+    #[test]
+    fn test_echo_ram_mirrors_wram() {
+        let mut bus = MemoryBus::new();
+        for (wram, echo) in [(0xC000, 0xE000), (0xD123, 0xF123), (0xDDFF, 0xFDFF)] {
+            bus.write_byte(wram, 0x5A);
+            assert_eq!(bus.read_byte(echo), 0x5A, "{:04X}", echo);
+
+            bus.write_byte(echo, 0xA5);
+            assert_eq!(bus.read_byte(wram), 0xA5, "{:04X}", wram);
+        }
+
+        // 0xDE00-0xDFFF have no mirror, the echo stopping short of OAM.
+        bus.write_byte(0xDE00, 0x11);
+        assert_eq!(bus.read_byte(0xFE00), 0x00);
+    }
+
+    #[test]
+    fn test_unusable_region_ignores_writes() {
+        let mut bus = MemoryBus::new();
+        bus.write_byte(0xFE9F, 0x55);
+        bus.write_byte(0xFEA0, 0xAA);
+        bus.write_byte(0xFEFF, 0xAA);
+
+        assert_eq!(bus.read_byte(0xFEA0), UNUSABLE_READ);
+        assert_eq!(bus.read_byte(0xFEFF), UNUSABLE_READ);
+        assert_eq!(bus.read_byte(0xFE9F), 0x55);
+    }
+
+    #[test]
+    fn test_oam_dma_from_wram() {
+        let mut bus = MemoryBus::new();
+        for offset in 0..0xA0 {
+            bus.write_byte(0xC000 + offset, offset as u8 ^ 0x5A);
+        }
+        bus.write_byte(0xFF80, 0x77);
+
+        bus.write_byte(0xFF46, 0xC0);
+        assert!(bus.dma_active());
+        assert_eq!(bus.read_byte(0xFF46), 0xC0);
+        assert_eq!(bus.read_byte(0xC050), 0xFF);
+        assert_eq!(bus.read_byte(0xFF80), 0x77);
+        bus.write_byte(0xC050, 0x00);
+
+        for _ in 0..159 {
+            bus.tick(4);
+        }
+        assert!(bus.dma_active());
+        bus.tick(4);
+        assert!(!bus.dma_active());
+
+        for offset in 0..0xA0 {
+            assert_eq!(bus.read_byte(0xFE00 + offset), offset as u8 ^ 0x5A, "OAM {:02X}", offset);
+        }
+        assert_eq!(bus.read_byte(0xC050), 0x50 ^ 0x5A);
+    }
+
+    #[test]
+    fn test_oam_dma_from_rom() {
+        let mut bus = MemoryBus::new();
+        let sprites: Vec<u8> = (0..0xA0).map(|i| i as u8).collect();
+        bus.set_range(0x1200, 0xA0, &sprites);
+
+        bus.write_byte(0xFF46, 0x12);
+        bus.tick(255);
+        bus.tick(255);
+        bus.tick(130);
+        assert_eq!(bus.oam[..], sprites[..]);
+    }
+
+    #[test]
+    fn test_boot_rom_overlay_until_unmapped() {
+        let mut bus = MemoryBus::new();
+        bus.set_range(0x0000, 2, &[0xC3, 0x50]);
+        bus.set_range(0x0100, 1, &[0x00]);
+        let mut boot_rom = [0; 0x100];
+        boot_rom[0] = 0x31;
+        boot_rom[0xFF] = 0x50;
+        bus.load_boot_rom(&boot_rom);
+
+        assert_eq!(bus.read_byte(0x0000), 0x31);
+        assert_eq!(bus.read_byte(0x00FF), 0x50);
+        assert_eq!(bus.read_byte(0x0100), 0x00);
+
+        bus.write_byte(0xFF50, 0x00);
+        assert!(bus.boot_rom_mapped());
+        bus.write_byte(0xFF50, 0x01);
+        assert!(!bus.boot_rom_mapped());
+        assert_eq!(bus.read_byte(0x0000), 0xC3);
+        assert_eq!(bus.read_byte(0x0001), 0x50);
+
+        // Unmapping is permanent until the next reset.
+        bus.write_byte(0xFF50, 0x00);
+        assert_eq!(bus.read_byte(0x0000), 0xC3);
+        bus.reset();
+        assert_eq!(bus.read_byte(0x0000), 0x31);
+    }
+
+    #[test]
+    fn test_ppu_modes_block_vram_and_oam() {
+        let mut bus = MemoryBus::new();
+        bus.write_byte(0x8000, 0x11);
+        bus.write_byte(0xFE00, 0x22);
+        bus.write_byte(LCDC, 0x80);
+
+        bus.io.ppu.force_mode(Mode::Drawing);
+        bus.write_byte(0x8000, 0x33);
+        bus.write_byte(0xFE00, 0x33);
+        assert_eq!(bus.read_byte(0x8000), 0xFF);
+        assert_eq!(bus.read_byte(0xFE00), 0xFF);
+
+        bus.io.ppu.force_mode(Mode::OamScan);
+        assert_eq!(bus.read_byte(0x8000), 0x11);
+        assert_eq!(bus.read_byte(0xFE00), 0xFF);
+
+        bus.io.ppu.force_mode(Mode::HBlank);
+        assert_eq!(bus.read_byte(0xFE00), 0x22);
+        bus.write_byte(0x8000, 0x44);
+        assert_eq!(bus.read_byte(0x8000), 0x44);
+
+        // With the display off, or the restriction lifted, nothing is held.
+        bus.io.ppu.force_mode(Mode::Drawing);
+        bus.write_byte(LCDC, 0x00);
+        assert_eq!(bus.read_byte(0x8000), 0x44);
+        bus.write_byte(LCDC, 0x80);
+        bus.set_ppu_access_restricted(false);
+        bus.write_byte(0x8000, 0x55);
+        assert_eq!(bus.read_byte(0x8000), 0x55);
+    }
+
+    #[test]
+    fn test_stat_follows_the_ppu() {
+        let mut bus = MemoryBus::new();
+        bus.write_byte(LCDC, 0x80);
+        bus.write_byte(STAT, 0xFF);
+        let mut modes = Vec::new();
+        for _ in 0..456 / 4 {
+            let mode = bus.read_byte(STAT) & 0x03;
+            if modes.last() != Some(&mode) {
+                modes.push(mode);
+            }
+            bus.tick(4);
+        }
+        assert_eq!(modes, [2, 3, 0]);
+        assert_eq!(bus.read_byte(STAT) & 0xF8, 0xF8);
+    }
+
+    #[test]
+    fn test_lyc_interrupt_sets_if() {
+        let mut bus = MemoryBus::new();
+        bus.write_byte(LYC, 40);
+        bus.write_byte(STAT, 0x40);
+        bus.write_byte(LCDC, 0x80);
+        let mut lines = Vec::new();
+        for _ in 0..70224 / 4 {
+            bus.tick(4);
+            if bus.read_byte(IF) & 0x02 != 0 {
+                lines.push(bus.read_byte(LY));
+                bus.write_byte(IF, 0x00);
+            }
+        }
+        assert_eq!(lines, [40]);
+    }
+
+    #[test]
+    fn test_lcd_off_frees_vram_and_oam() {
+        let mut bus = MemoryBus::new();
+        bus.write_byte(STAT, 0x78);
+        bus.write_byte(LCDC, 0x00);
+        // A line's worth of dots, through where mode 3 would be.
+        for dot in 0..456 / 4 {
+            bus.write_byte(0x8000 + dot, dot as u8);
+            bus.write_byte(0xFE00 + dot % 0xA0, dot as u8);
+            assert_eq!(bus.read_byte(0x8000 + dot), dot as u8);
+            assert_eq!(bus.read_byte(0xFE00 + dot % 0xA0), dot as u8);
+            bus.tick(4);
+        }
+        for _ in 0..70224 / 4 {
+            bus.tick(4);
+        }
+        assert_eq!(bus.read_byte(IF) & 0x1F, 0x00);
+        assert_eq!(bus.read_byte(LY), 0);
+    }
+
+    #[test]
+    fn test_external_ram_needs_enabling() {
+        for cartridge_type in [0x03, 0x06, 0x13, 0x1B] {
+            let mut rom = vec![0; 0x8000];
+            rom[0x0147] = cartridge_type;
+            rom[0x0149] = 0x02;
+            let mut bus = MemoryBus::new();
+            bus.load_cartridge(from_rom(rom));
+            let label = format!("type {:02X}", cartridge_type);
+
+            bus.write_byte(0x0000, 0x0A);
+            bus.write_byte(0xA000, 0xF5);
+            bus.write_byte(0x0000, 0x00);
+            bus.write_byte(0xA000, 0xFA);
+            assert_eq!(bus.read_byte(0xA000), 0xFF, "{}", label);
+
+            bus.write_byte(0x0000, 0x0A);
+            assert_eq!(bus.read_byte(0xA000), 0xF5, "{}", label);
+        }
+    }
+
+    #[test]
+    fn test_unmapped_reads_are_open_bus() {
+        let mut bus = MemoryBus::new();
+        bus.load_cartridge(from_rom(vec![0x42; 0x8000]));
+        bus.write_byte(0xFE9F, 0x11);
+        bus.write_byte(LCDC, 0x80);
+        bus.io.ppu.force_mode(Mode::HBlank);
+        bus.write_byte(0xFF05, 0x22); // TIMA
+        bus.write_byte(0xFF03, 0x33);
+        bus.write_byte(0xFF7F, 0x33);
+        bus.write_byte(0xA000, 0x33);
+
+        let cases = [
+            (0x7FFF, 0x42, "ROM"),
+            (0xA000, OPEN_BUS, "cartridge without RAM"),
+            (0xFE9F, 0x11, "OAM"),
+            (0xFEA0, UNUSABLE_READ, "unusable area"),
+            (0xFF05, 0x22, "TIMA"),
+            (0xFF03, OPEN_BUS, "unmapped register"),
+            (0xFF7F, OPEN_BUS, "unmapped register"),
+        ];
+        for (address, expected, label) in cases {
+            assert_eq!(bus.read_byte(address), expected, "{} at {:04X}", label, address);
+        }
+
+        bus.io.ppu.force_mode(Mode::OamScan);
+        assert_eq!(bus.read_byte(0xFEA0), OPEN_BUS);
+    }
+
+    #[test]
+    fn test_region_boundaries() {
+        let cases = [
+            (0x0000, MemoryRegion::Rom),
+            (0x7FFF, MemoryRegion::Rom),
+            (0x8000, MemoryRegion::Vram),
+            (0x9FFF, MemoryRegion::Vram),
+            (0xA000, MemoryRegion::ExternalRam),
+            (0xBFFF, MemoryRegion::ExternalRam),
+            (0xC000, MemoryRegion::Wram),
+            (0xDFFF, MemoryRegion::Wram),
+            (0xE000, MemoryRegion::Echo),
+            (0xFDFF, MemoryRegion::Echo),
+            (0xFE00, MemoryRegion::Oam),
+            (0xFE9F, MemoryRegion::Oam),
+            (0xFEA0, MemoryRegion::Unusable),
+            (0xFEFF, MemoryRegion::Unusable),
+            (0xFF00, MemoryRegion::Io),
+            (0xFF7F, MemoryRegion::Io),
+            (0xFF80, MemoryRegion::Hram),
+            (0xFFFE, MemoryRegion::Hram),
+            (0xFFFF, MemoryRegion::InterruptEnable),
+        ];
+        for (address, expected) in cases {
+            assert_eq!(region(address), expected, "{:04X}", address);
+        }
+    }
+
+    #[test]
+    fn test_regions_have_separate_storage() {
+        let mut bus = MemoryBus::new();
+        for address in [0x8000, 0x9FFF, 0xC000, 0xDFFF, 0xFE00, 0xFE9F, 0xFF80, 0xFFFE, 0xFFFF] {
+            bus.write_byte(address, address as u8 ^ 0xA5);
+        }
+        for address in [0x8000, 0x9FFF, 0xC000, 0xDFFF, 0xFE00, 0xFE9F, 0xFF80, 0xFFFE, 0xFFFF] {
+            assert_eq!(bus.read_byte(address), address as u8 ^ 0xA5, "{:04X}", address);
+        }
+    }
+}
@@ -0,0 +1,199 @@
+use super::OPEN_BUS;
+use crate::joypad::Joypad;
+use crate::ppu::Ppu;
+use crate::timer::Timer;
+
+pub const P1: u16 = 0xFF00;
+pub const SB: u16 = 0xFF01;
+pub const SC: u16 = 0xFF02;
+pub const DIV: u16 = 0xFF04;
+pub const TIMA: u16 = 0xFF05;
+pub const TMA: u16 = 0xFF06;
+pub const TAC: u16 = 0xFF07;
+pub const IF: u16 = 0xFF0F;
+pub const LCDC: u16 = 0xFF40;
+pub const STAT: u16 = 0xFF41;
+pub const SCY: u16 = 0xFF42;
+pub const SCX: u16 = 0xFF43;
+pub const LY: u16 = 0xFF44;
+pub const LYC: u16 = 0xFF45;
+pub const DMA: u16 = 0xFF46;
+pub const BGP: u16 = 0xFF47;
+pub const OBP0: u16 = 0xFF48;
+pub const OBP1: u16 = 0xFF49;
+pub const WY: u16 = 0xFF4A;
+pub const WX: u16 = 0xFF4B;
+/// Writing a nonzero value unmaps the boot ROM.
+pub const BOOT: u16 = 0xFF50;
+
+/// The I/O registers as the DMG boot ROM leaves them. Registers missing
+/// here, including the undefined OBP0 and OBP1, are left cleared.
+pub(crate) const POST_BOOT_IO: [(u16, u8); 39] = [
+    (0xFF00, 0xCF), (0xFF01, 0x00), (0xFF02, 0x7E), (0xFF04, 0xAB),
+    (0xFF05, 0x00), (0xFF06, 0x00), (0xFF07, 0xF8), (0xFF0F, 0xE1),
+    (0xFF10, 0x80), (0xFF11, 0xBF), (0xFF12, 0xF3), (0xFF13, 0xFF),
+    (0xFF14, 0xBF), (0xFF16, 0x3F), (0xFF17, 0x00), (0xFF18, 0xFF),
+    (0xFF19, 0xBF), (0xFF1A, 0x7F), (0xFF1B, 0xFF), (0xFF1C, 0x9F),
+    (0xFF1D, 0xFF), (0xFF1E, 0xBF), (0xFF20, 0xFF), (0xFF21, 0x00),
+    (0xFF22, 0x00), (0xFF23, 0xBF), (0xFF24, 0x77), (0xFF25, 0xF3),
+    (0xFF26, 0xF1), (0xFF40, 0x91), (0xFF41, 0x85), (0xFF42, 0x00),
+    (0xFF43, 0x00), (0xFF44, 0x00), (0xFF45, 0x00), (0xFF46, 0xFF),
+    (0xFF47, 0xFC), (0xFF4A, 0x00), (0xFF4B, 0x00),
+];
+
+/// Whether a hardware register sits at `address`. The sound registers have
+/// a few holes, and nothing past WX exists on the DMG.
+fn is_mapped(address: u16) -> bool {
+    matches!(address,
+        P1 | SB | SC | DIV | TIMA | TMA | TAC | IF
+        | 0xFF10..=0xFF14 | 0xFF16..=0xFF1E | 0xFF20..=0xFF26 | 0xFF30..=0xFF3F
+        | LCDC..=WX)
+}
+
+/// The hardware registers at 0xFF00-0xFF7F. Registers without behavior of
+/// their own yet are plain storage, for their subsystem to take over.
+pub struct Io {
+    registers: [u8; 0x80],
+    pub(crate) joypad: Joypad,
+    pub(crate) timer: Timer,
+    pub(crate) ppu: Ppu,
+}
+
+impl Io {
+    pub fn new() -> Io {
+        Io {
+            registers: [0; 0x80],
+            joypad: Joypad::new(),
+            timer: Timer::new(),
+            ppu: Ppu::new(),
+        }
+    }
+
+    fn register(&self, address: u16) -> u8 {
+        self.registers[(address - 0xFF00) as usize]
+    }
+
+    fn set_register(&mut self, address: u16, value: u8) {
+        self.registers[(address - 0xFF00) as usize] = value;
+    }
+
+    /// Unused bits read back as 1.
+    pub fn read(&self, address: u16) -> u8 {
+        match address {
+            P1 => self.joypad.read(),
+            SC => self.register(SC) | 0x7E,
+            DIV..=TAC => self.timer.read(address),
+            IF => self.register(IF) | 0xE0,
+            LCDC..=WX if address != DMA => self.ppu.read(address),
+            _ if is_mapped(address) => self.register(address),
+            _ => OPEN_BUS,
+        }
+    }
+
+    pub fn write(&mut self, address: u16, value: u8) {
+        match address {
+            P1 => self.joypad.write(value),
+            DIV..=TAC => self.timer.write(address, value),
+            LCDC..=WX if address != DMA => self.ppu.write(address, value),
+            _ if is_mapped(address) => self.set_register(address, value),
+            _ => {},
+        }
+    }
+
+    /// Advances the devices behind the registers by the T-cycles the CPU
+    /// just spent, flagging the interrupts they request in IF.
+    pub fn tick(&mut self, cycles: u8, vram: &[u8; 0x2000], oam: &[u8; 0xA0]) {
+        let requests = self.timer.tick(cycles) | self.ppu.tick(cycles, vram, oam);
+        self.set_register(IF, self.register(IF) | requests);
+    }
+
+    /// Loads the post-boot values as is, bypassing the write handlers.
+    pub fn reset_post_boot(&mut self) {
+        for (address, value) in POST_BOOT_IO {
+            match address {
+                P1 => self.joypad.write(value),
+                DIV..=TAC => self.timer.load_register(address, value),
+                LCDC..=WX if address != DMA => self.ppu.load_register(address, value),
+                _ => self.set_register(address, value),
+            }
+        }
+    }
+}
+
+impl Default for Io {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unmapped_reads_return_ff() {
+        let mut io = Io::new();
+        for address in [0xFF03, 0xFF08, 0xFF15, 0xFF1F, 0xFF27, 0xFF4C, 0xFF7F] {
+            io.write(address, 0x12);
+            assert_eq!(io.read(address), 0xFF, "{:04X}", address);
+        }
+    }
+
+    #[test]
+    fn test_write_handlers_replace_storage() {
+        let mut io = Io::new();
+        io.timer.load_register(DIV, 0xAB);
+        io.write(DIV, 0x42);
+        assert_eq!(io.read(DIV), 0x00);
+
+        io.write(P1, 0x00);
+        assert_eq!(io.read(P1), 0xCF);
+
+        io.write(LY, 0x90);
+        assert_eq!(io.read(LY), 0x00);
+
+        io.ppu.load_register(STAT, 0x02);
+        io.write(STAT, 0xFF);
+        assert_eq!(io.read(STAT), 0xFA);
+
+        io.write(BGP, 0xE4);
+        assert_eq!(io.read(BGP), 0xE4);
+        io.write(OBP0, 0x1B);
+        assert_eq!(io.read(OBP0), 0x1B);
+        io.write(OBP1, 0x9C);
+        assert_eq!(io.read(OBP1), 0x9C);
+    }
+
+    #[test]
+    fn test_div_counts_ticks() {
+        let mut io = Io::new();
+        io.reset_post_boot();
+        assert_eq!(io.read(DIV), 0xAB);
+        for _ in 0..64 {
+            io.tick(4, &[0; 0x2000], &[0; 0xA0]);
+        }
+        assert_eq!(io.read(DIV), 0xAC);
+    }
+
+    #[test]
+    fn test_timer_overflow_sets_if() {
+        let mut io = Io::new();
+        io.write(TIMA, 0xFF);
+        io.write(TMA, 0x80);
+        io.write(TAC, 0x05);
+        io.tick(16, &[0; 0x2000], &[0; 0xA0]);
+        assert_eq!(io.read(IF), 0xE0);
+        io.tick(4, &[0; 0x2000], &[0; 0xA0]);
+        assert_eq!(io.read(TIMA), 0x80);
+        assert_eq!(io.read(IF), 0xE4);
+    }
+
+    #[test]
+    fn test_unused_bits_read_as_one() {
+        let io = Io::new();
+        assert_eq!(io.read(SC), 0x7E);
+        assert_eq!(io.read(TAC), 0xF8);
+        assert_eq!(io.read(IF), 0xE0);
+        assert_eq!(io.read(STAT), 0x80);
+    }
+}
@@ -0,0 +1,1680 @@
+use std::collections::VecDeque;
+
+use crate::memory::io::{BGP, LCDC, LY, LYC, OBP0, OBP1, SCX, SCY, STAT, WX, WY};
+
+/// Every line takes the same time, whatever is drawn on it.
+pub const DOTS_PER_LINE: u16 = 456;
+/// 144 visible lines, then 10 of VBlank.
+pub const LINES_PER_FRAME: u8 = 154;
+pub const DOTS_PER_FRAME: u32 = DOTS_PER_LINE as u32 * LINES_PER_FRAME as u32;
+/// The first line of VBlank.
+pub const VBLANK_LINE: u8 = 144;
+
+pub const SCREEN_WIDTH: usize = 160;
+pub const SCREEN_HEIGHT: usize = 144;
+
+/// The size of `Ppu::debug_render_tiles` images: the 384 tiles of VRAM, 16
+/// to a row.
+pub const TILE_SHEET_WIDTH: usize = 16 * 8;
+pub const TILE_SHEET_HEIGHT: usize = 24 * 8;
+/// The width and height of the picture a tile map makes.
+pub const TILE_MAP_SIZE: usize = 256;
+
+/// The IF bit for the VBlank interrupt.
+pub const VBLANK_INTERRUPT: u8 = 0x01;
+/// The IF bit for the STAT interrupt.
+pub const STAT_INTERRUPT: u8 = 0x02;
+
+const OAM_SCAN_DOTS: u16 = 80;
+/// The OAM scan stops after finding this many sprites on a line.
+const MAX_SPRITES_PER_LINE: usize = 10;
+/// A background or window fetch has its row of pixels after this many dots.
+const FETCH_DOTS: u8 = 6;
+/// Fetching a sprite's row stalls the pixel output this long, on top of
+/// waiting for the background fetch to get far enough.
+const SPRITE_FETCH_DOTS: u8 = 6;
+
+/// What the PPU is busy with, as reported in the low bits of STAT.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    HBlank = 0,
+    VBlank = 1,
+    OamScan = 2,
+    Drawing = 3,
+}
+
+/// The 2-bit color numbers of a row of 8 pixels, leftmost first, from the
+/// two bytes a tile stores it in: the low bits, then the high bits.
+pub(crate) fn decode_row(low: u8, high: u8) -> [u8; 8] {
+    let mut colors = [0; 8];
+    for (pixel, color) in colors.iter_mut().enumerate() {
+        let bit = 7 - pixel;
+        *color = (high >> bit & 0x01) << 1 | (low >> bit & 0x01);
+    }
+    colors
+}
+
+/// An OAM entry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Sprite {
+    y: u8,
+    x: u8,
+    tile: u8,
+    /// Bit 7 BG priority, bit 6 Y flip, bit 5 X flip, bit 4 OBP1.
+    attributes: u8,
+}
+
+impl Sprite {
+    fn from_oam(entry: &[u8]) -> Sprite {
+        Sprite {
+            y: entry[0],
+            x: entry[1],
+            tile: entry[2],
+            attributes: entry[3],
+        }
+    }
+
+    /// Attribute bit 7: background colors 1-3 are drawn over the sprite.
+    fn behind_background(&self) -> bool {
+        self.attributes & 0x80 != 0
+    }
+
+    fn y_flip(&self) -> bool {
+        self.attributes & 0x40 != 0
+    }
+
+    fn x_flip(&self) -> bool {
+        self.attributes & 0x20 != 0
+    }
+}
+
+/// One of the two tile maps in VRAM.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TileMap {
+    Map9800,
+    Map9C00,
+}
+
+impl TileMap {
+    /// Where the map is in VRAM.
+    fn offset(self) -> usize {
+        match self {
+            TileMap::Map9800 => 0x1800,
+            TileMap::Map9C00 => 0x1C00,
+        }
+    }
+}
+
+/// A completed frame, as handed to a frame sink.
+pub struct Frame<'a> {
+    /// Shades 0-3, one per pixel, row by row.
+    pub pixels: &'a [u8; SCREEN_WIDTH * SCREEN_HEIGHT],
+    /// Counts from 1 at the first frame.
+    pub number: u64,
+}
+
+/// Called with each frame as it is completed.
+pub type FrameSink = Box<dyn FnMut(&Frame)>;
+
+/// A sprite pixel waiting to be mixed with the background.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct SpritePixel {
+    color: u8,
+    /// Whether OBP1 shades it rather than OBP0.
+    obp1: bool,
+    behind_background: bool,
+}
+
+/// Mode 3 on the current line. A fetcher fetches 8 background or window
+/// pixels at a time into a FIFO that shifts one out per dot, and fetching
+/// a sprite stalls the shifting. Fine scroll, the window and sprites all
+/// make the line take longer to draw.
+struct PixelFifo {
+    /// Color numbers waiting to be shifted out, the next at the front.
+    background: VecDeque<u8>,
+    /// Sprite pixels by screen column, the first sprite fetched over a
+    /// pixel keeping it. This stands in for the sprite FIFO, which lines up
+    /// with the screen rather than with the background.
+    sprites: [Option<SpritePixel>; SCREEN_WIDTH],
+    /// Dots into the current fetch, up to `FETCH_DOTS`.
+    fetch_dots: u8,
+    /// The row fetched, waiting for the FIFO to empty.
+    fetched: [u8; 8],
+    /// The tile to fetch next, counted from SCX or the left of the window.
+    fetch_column: u8,
+    /// The first fetch of a line is thrown away.
+    warmed_up: bool,
+    /// Pixels still to drop rather than shift out: SCX % 8 at the start of
+    /// the line, or the part of the window left of the screen.
+    discard: u8,
+    /// Whether the fetcher has switched to the window.
+    window: bool,
+    /// The index in the line's sprites of the next one to fetch.
+    next_sprite: usize,
+    /// Dots left fetching a sprite.
+    sprite_dots: u8,
+    /// Pixels shifted out to the screen.
+    x: usize,
+}
+
+impl PixelFifo {
+    fn new(scx: u8) -> PixelFifo {
+        PixelFifo {
+            background: VecDeque::with_capacity(8),
+            sprites: [None; SCREEN_WIDTH],
+            fetch_dots: 0,
+            fetched: [0; 8],
+            fetch_column: 0,
+            warmed_up: false,
+            discard: scx % 8,
+            window: false,
+            next_sprite: 0,
+            sprite_dots: 0,
+            x: 0,
+        }
+    }
+
+    /// Starts fetching the window from its first tile, dropping what the
+    /// FIFO holds of the background.
+    fn start_window(&mut self, wx: u8) {
+        self.background.clear();
+        self.fetch_dots = 0;
+        self.fetch_column = 0;
+        self.discard = 7u8.saturating_sub(wx);
+        self.window = true;
+    }
+}
+
+/// An OAM entry decoded, for debuggers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SpriteEntry {
+    /// The line below the sprite's top, plus 16.
+    pub y: u8,
+    /// The column right of the sprite's left edge, plus 8.
+    pub x: u8,
+    pub tile: u8,
+    /// 0 for OBP0, 1 for OBP1.
+    pub palette: u8,
+    pub x_flip: bool,
+    pub y_flip: bool,
+    /// Background colors 1-3 are drawn over the sprite.
+    pub behind_background: bool,
+    /// 8 or 16, as LCDC had it when the entry was decoded.
+    pub height: u8,
+}
+
+impl SpriteEntry {
+    /// Whether any of the sprite is on screen on line `line`.
+    pub fn visible_on_line(&self, line: u8) -> bool {
+        let line = line as u16 + 16;
+        (self.y as u16..self.y as u16 + self.height as u16).contains(&line) && (1..168).contains(&self.x)
+    }
+}
+
+/// The shade `palette` gives color number `color`.
+fn shade(palette: u8, color: u8) -> u8 {
+    palette >> (color * 2) & 0x03
+}
+
+impl Mode {
+    fn from_bits(bits: u8) -> Mode {
+        match bits & 0x03 {
+            0 => Mode::HBlank,
+            1 => Mode::VBlank,
+            2 => Mode::OamScan,
+            _ => Mode::Drawing,
+        }
+    }
+}
+
+/// The picture processing unit and its registers at 0xFF40-0xFF4B, apart
+/// from DMA.
+pub struct Ppu {
+    lcdc: u8,
+    /// The interrupt source enables in bits 3-6 of STAT.
+    stat: u8,
+    scy: u8,
+    scx: u8,
+    ly: u8,
+    lyc: u8,
+    bgp: u8,
+    obp0: u8,
+    obp1: u8,
+    wy: u8,
+    wx: u8,
+    mode: Mode,
+    /// Whether LY matched LYC when they were last compared.
+    coincidence: bool,
+    /// The STAT interrupt line: the OR of every enabled source whose
+    /// condition holds. Only its rising edges request the interrupt, so one
+    /// source going high while another already holds the line is missed.
+    stat_line: bool,
+    /// Dots into the current line.
+    dot: u16,
+    /// The window row to draw next. It counts the lines the window was
+    /// drawn on this frame rather than following LY, so a window hidden for
+    /// a few lines picks up where it left off.
+    window_line: u8,
+    /// Interrupts requested and not yet handed to the bus, as IF bits.
+    interrupts: u8,
+    /// What the OAM scan found on the current line, in drawing priority
+    /// order.
+    line_sprites: Vec<Sprite>,
+    fifo: PixelFifo,
+    /// Shades 0-3, one per pixel, row by row.
+    framebuffer: Box<[u8; SCREEN_WIDTH * SCREEN_HEIGHT]>,
+    /// The last frame drawn in full, copied out of `framebuffer` at the
+    /// start of VBlank so that it is never seen half drawn.
+    frame: Box<[u8; SCREEN_WIDTH * SCREEN_HEIGHT]>,
+    frame_count: u64,
+    frame_sink: Option<FrameSink>,
+}
+
+impl Ppu {
+    pub fn new() -> Ppu {
+        Ppu {
+            lcdc: 0,
+            stat: 0,
+            scy: 0,
+            scx: 0,
+            ly: 0,
+            lyc: 0,
+            bgp: 0,
+            obp0: 0,
+            obp1: 0,
+            wy: 0,
+            wx: 0,
+            mode: Mode::HBlank,
+            coincidence: false,
+            stat_line: false,
+            dot: 0,
+            window_line: 0,
+            interrupts: 0,
+            line_sprites: Vec::with_capacity(MAX_SPRITES_PER_LINE),
+            fifo: PixelFifo::new(0),
+            framebuffer: Box::new([0; SCREEN_WIDTH * SCREEN_HEIGHT]),
+            frame: Box::new([0; SCREEN_WIDTH * SCREEN_HEIGHT]),
+            frame_count: 0,
+            frame_sink: None,
+        }
+    }
+
+    pub fn lcd_enabled(&self) -> bool {
+        self.lcdc & 0x80 != 0
+    }
+
+    pub fn mode(&self) -> Mode {
+        self.mode
+    }
+
+    pub fn ly(&self) -> u8 {
+        self.ly
+    }
+
+    /// The frame being drawn, complete down to the line before LY.
+    pub fn framebuffer(&self) -> &[u8; SCREEN_WIDTH * SCREEN_HEIGHT] {
+        &self.framebuffer
+    }
+
+    /// The last complete frame, as shades 0-3 row by row.
+    pub fn frame(&self) -> &[u8; SCREEN_WIDTH * SCREEN_HEIGHT] {
+        &self.frame
+    }
+
+    /// How many frames have been completed, for telling when `frame` has
+    /// changed.
+    pub fn frame_count(&self) -> u64 {
+        self.frame_count
+    }
+
+    /// Has `sink` called at the start of each VBlank with the frame just
+    /// completed, replacing any sink set before. `None` removes it.
+    pub fn set_frame_sink(&mut self, sink: Option<FrameSink>) {
+        self.frame_sink = sink;
+    }
+
+    /// Removes the frame sink, handing it back.
+    pub fn take_frame_sink(&mut self) -> Option<FrameSink> {
+        self.frame_sink.take()
+    }
+
+    /// Bit 7 of STAT is unused and reads as 1.
+    pub fn read(&self, address: u16) -> u8 {
+        match address {
+            LCDC => self.lcdc,
+            STAT => 0x80 | self.stat | (self.coincidence as u8) << 2 | self.mode as u8,
+            SCY => self.scy,
+            SCX => self.scx,
+            LY => self.ly,
+            LYC => self.lyc,
+            BGP => self.bgp,
+            OBP0 => self.obp0,
+            OBP1 => self.obp1,
+            WY => self.wy,
+            WX => self.wx,
+            _ => 0xFF,
+        }
+    }
+
+    pub fn write(&mut self, address: u16, value: u8) {
+        match address {
+            LCDC => self.write_lcdc(value),
+            // The mode and coincidence bits are read-only.
+            STAT => {
+                self.stat = value & 0x78;
+                self.update_stat_line();
+            }
+            LY => {},
+            // A game can make LY match by moving LYC to it.
+            LYC => {
+                self.lyc = value;
+                self.compare_lyc();
+            }
+            _ => self.load_register(address, value),
+        }
+    }
+
+    /// Switching the display on starts a frame from the top; switching it
+    /// off stops the PPU on line 0 in HBlank and blanks the screen. Real
+    /// hardware can be damaged by switching off outside VBlank, but games
+    /// do it anyway, so it is allowed at any time. The other bits take
+    /// effect from the next line drawn.
+    ///
+    /// Hardware does not show the first frame after switching on; here it
+    /// is drawn like any other.
+    fn write_lcdc(&mut self, value: u8) {
+        let was_enabled = self.lcd_enabled();
+        self.lcdc = value;
+        if was_enabled != self.lcd_enabled() {
+            self.ly = 0;
+            self.dot = 0;
+            self.window_line = 0;
+            self.mode = if self.lcd_enabled() { Mode::OamScan } else { Mode::HBlank };
+            if self.lcd_enabled() {
+                self.compare_lyc();
+            } else {
+                self.stat_line = false;
+                self.framebuffer.fill(0);
+                self.frame.fill(0);
+            }
+        }
+    }
+
+    /// Sets a register as is, read-only bits included, bypassing the write
+    /// handlers.
+    pub(crate) fn load_register(&mut self, address: u16, value: u8) {
+        match address {
+            LCDC => self.lcdc = value,
+            STAT => {
+                self.stat = value & 0x78;
+                self.coincidence = value & 0x04 != 0;
+                self.mode = Mode::from_bits(value);
+            }
+            SCY => self.scy = value,
+            SCX => self.scx = value,
+            LY => self.ly = value,
+            LYC => self.lyc = value,
+            BGP => self.bgp = value,
+            OBP0 => self.obp0 = value,
+            OBP1 => self.obp1 = value,
+            WY => self.wy = value,
+            WX => self.wx = value,
+            _ => {},
+        }
+    }
+
+    /// Advances by `cycles` dots, one per T-cycle, returning the interrupts
+    /// this requested as IF bits. Nothing moves while the display is off.
+    pub fn tick(&mut self, cycles: u8, vram: &[u8; 0x2000], oam: &[u8; 0xA0]) -> u8 {
+        if self.lcd_enabled() {
+            for _ in 0..cycles {
+                self.step_dot(vram, oam);
+            }
+        }
+        std::mem::take(&mut self.interrupts)
+    }
+
+    /// Compares LY with LYC. Runs whenever either changes.
+    fn compare_lyc(&mut self) {
+        self.coincidence = self.ly == self.lyc;
+        self.update_stat_line();
+    }
+
+    /// Recomputes the STAT line from its four sources, requesting the STAT
+    /// interrupt if it went high: STAT bit 3 for HBlank, 4 for VBlank, 5 for
+    /// OAM scan and 6 for LY matching LYC. The line stays low with the
+    /// display off.
+    fn update_stat_line(&mut self) {
+        let source = match self.mode {
+            Mode::HBlank => 0x08,
+            Mode::VBlank => 0x10,
+            Mode::OamScan => 0x20,
+            Mode::Drawing => 0x00,
+        };
+        let line = self.lcd_enabled()
+            && (self.stat & source != 0 || self.coincidence && self.stat & 0x40 != 0);
+        if line && !self.stat_line {
+            self.interrupts |= STAT_INTERRUPT;
+        }
+        self.stat_line = line;
+    }
+
+    /// Switches to `mode`, which may raise the STAT line. Entering VBlank
+    /// also requests the VBlank interrupt, whatever STAT, and completes the
+    /// frame.
+    fn enter_mode(&mut self, mode: Mode) {
+        if mode == Mode::VBlank {
+            self.interrupts |= VBLANK_INTERRUPT;
+            *self.frame = *self.framebuffer;
+            self.frame_count += 1;
+            if let Some(sink) = self.frame_sink.as_mut() {
+                sink(&Frame { pixels: &self.frame, number: self.frame_count });
+            }
+        }
+        self.mode = mode;
+        self.update_stat_line();
+    }
+
+    fn step_dot(&mut self, vram: &[u8; 0x2000], oam: &[u8; 0xA0]) {
+        self.dot += 1;
+        if self.dot == DOTS_PER_LINE {
+            self.dot = 0;
+            self.ly = (self.ly + 1) % LINES_PER_FRAME;
+            if self.ly == 0 {
+                self.window_line = 0;
+            }
+            self.compare_lyc();
+        }
+        // Mode 3 lasts until the last pixel is out, however long it takes.
+        let mode = match self.mode {
+            _ if self.ly >= VBLANK_LINE => Mode::VBlank,
+            Mode::Drawing => if self.draw_dot(vram) { Mode::HBlank } else { Mode::Drawing },
+            _ if self.dot < OAM_SCAN_DOTS => Mode::OamScan,
+            Mode::OamScan => Mode::Drawing,
+            mode => mode,
+        };
+        if mode != self.mode {
+            if mode == Mode::Drawing {
+                self.scan_oam(oam);
+                self.fifo = PixelFifo::new(self.scx);
+            }
+            self.enter_mode(mode);
+        }
+    }
+
+    /// The VRAM offset of the 16 bytes of `tile`. With LCDC bit 4 set tiles
+    /// 0-255 start at 0x8000; without it tiles -128-127 are centered on
+    /// 0x9000.
+    fn tile_offset(&self, tile: u8) -> usize {
+        if self.lcdc & 0x10 != 0 {
+            tile as usize * 16
+        } else {
+            (0x1000 + (tile as i8) as isize * 16) as usize
+        }
+    }
+
+    /// The color numbers of the 8 pixels of tile column `column` of the
+    /// 256x256 picture a tile map makes, on its line `y`. The map starts at
+    /// VRAM offset `map`.
+    fn map_row(&self, vram: &[u8; 0x2000], map: usize, column: u8, y: u8) -> [u8; 8] {
+        let tile = vram[map + (y as usize / 8) * 32 + (column & 31) as usize];
+        let row = self.tile_offset(tile) + (y as usize % 8) * 2;
+        decode_row(vram[row], vram[row + 1])
+    }
+
+    /// Whether the window covers part of line LY: it is drawn from line WY
+    /// down and from column WX - 7 rightwards. Without the background there
+    /// is no window either.
+    fn window_on_line(&self) -> bool {
+        self.lcdc & 0x21 == 0x21 && self.ly >= self.wy && self.wx <= 166
+    }
+
+    /// Runs mode 3 for a dot, returning whether the line is finished. The
+    /// registers are read as the pixels are fetched and shifted out, so
+    /// changes mid-line show from where they were made.
+    fn draw_dot(&mut self, vram: &[u8; 0x2000]) -> bool {
+        if self.fifo.sprite_dots > 0 {
+            self.fifo.sprite_dots -= 1;
+            if self.fifo.sprite_dots == 0 {
+                self.fetch_sprite(vram);
+            }
+            return false;
+        }
+        if self.fifo.x >= SCREEN_WIDTH {
+            return true;
+        }
+        if !self.fifo.window && self.fifo.discard == 0 && self.window_on_line() && self.fifo.x + 7 >= self.wx as usize {
+            self.fifo.start_window(self.wx);
+        }
+        // A sprite starting here waits for the background fetch to be
+        // nearly done, then has its own fetch.
+        while !self.fifo.background.is_empty() {
+            let Some(sprite) = self.line_sprites.get(self.fifo.next_sprite) else { break };
+            if sprite.x as usize > self.fifo.x + 8 {
+                break;
+            }
+            if self.lcdc & 0x02 == 0 {
+                self.fifo.next_sprite += 1;
+                continue;
+            }
+            if self.fifo.fetch_dots < FETCH_DOTS - 1 {
+                self.fetch_dot(vram);
+            } else {
+                self.fifo.sprite_dots = SPRITE_FETCH_DOTS - 1;
+            }
+            return false;
+        }
+        if let Some(color) = self.fifo.background.pop_front() {
+            if self.fifo.discard > 0 {
+                self.fifo.discard -= 1;
+            } else {
+                self.shift_out(color);
+            }
+        }
+        self.fetch_dot(vram);
+        if self.fifo.x == SCREEN_WIDTH {
+            if self.fifo.window {
+                self.window_line += 1;
+            }
+            return true;
+        }
+        false
+    }
+
+    /// Runs the background fetcher for a dot. Once it has a row it pushes
+    /// it as soon as the FIFO is empty, then starts on the next.
+    fn fetch_dot(&mut self, vram: &[u8; 0x2000]) {
+        if self.fifo.fetch_dots < FETCH_DOTS {
+            self.fifo.fetch_dots += 1;
+            if self.fifo.fetch_dots == FETCH_DOTS {
+                self.fifo.fetched = if self.fifo.window {
+                    let map = if self.lcdc & 0x40 != 0 { 0x1C00 } else { 0x1800 };
+                    self.map_row(vram, map, self.fifo.fetch_column, self.window_line)
+                } else {
+                    let map = if self.lcdc & 0x08 != 0 { 0x1C00 } else { 0x1800 };
+                    let column = (self.scx / 8).wrapping_add(self.fifo.fetch_column);
+                    self.map_row(vram, map, column, self.ly.wrapping_add(self.scy))
+                };
+            }
+        }
+        if self.fifo.fetch_dots == FETCH_DOTS && self.fifo.background.is_empty() {
+            if self.fifo.warmed_up {
+                self.fifo.background.extend(self.fifo.fetched);
+                self.fifo.fetch_column = self.fifo.fetch_column.wrapping_add(1);
+            }
+            self.fifo.warmed_up = true;
+            self.fifo.fetch_dots = 0;
+        }
+    }
+
+    /// Puts the next pixel on the screen: the sprite one if there is one in
+    /// front of background color `color`, the background one otherwise.
+    /// With LCDC bit 0 clear the background is blank and counts as color 0.
+    fn shift_out(&mut self, color: u8) {
+        let (color, mut shade_) = if self.lcdc & 0x01 != 0 { (color, shade(self.bgp, color)) } else { (0, 0) };
+        if let Some(sprite) = self.fifo.sprites[self.fifo.x] {
+            if self.lcdc & 0x02 != 0 && (!sprite.behind_background || color == 0) {
+                shade_ = shade(if sprite.obp1 { self.obp1 } else { self.obp0 }, sprite.color);
+            }
+        }
+        self.framebuffer[self.ly as usize * SCREEN_WIDTH + self.fifo.x] = shade_;
+        self.fifo.x += 1;
+    }
+
+    /// Sprites are 8x16 with LCDC bit 2 set, 8x8 otherwise.
+    fn sprite_height(&self) -> u8 {
+        if self.lcdc & 0x04 != 0 { 16 } else { 8 }
+    }
+
+    /// Picks the first 10 sprites in OAM that overlap line LY, whatever
+    /// their X, and orders them for drawing: the smaller X wins, then the
+    /// earlier entry.
+    fn scan_oam(&mut self, oam: &[u8; 0xA0]) {
+        let height = self.sprite_height();
+        // Y is offset by 16 so that sprites can be partly off the top edge.
+        let line = self.ly + 16;
+        self.line_sprites.clear();
+        self.line_sprites.extend(
+            oam.chunks_exact(4)
+                .map(Sprite::from_oam)
+                .filter(|sprite| line >= sprite.y && line < sprite.y.saturating_add(height))
+                .take(MAX_SPRITES_PER_LINE),
+        );
+        self.line_sprites.sort_by_key(|sprite| sprite.x);
+    }
+
+    /// Fetches the row of the next sprite on the line, keeping its pixels
+    /// that are not transparent and not already taken by an earlier sprite.
+    fn fetch_sprite(&mut self, vram: &[u8; 0x2000]) {
+        let sprite = self.line_sprites[self.fifo.next_sprite];
+        self.fifo.next_sprite += 1;
+        let height = self.sprite_height();
+        let mut row = self.ly + 16 - sprite.y;
+        // Found as an 8x16 sprite, but 8x8 ones were selected since.
+        if row >= height {
+            return;
+        }
+        if sprite.y_flip() {
+            row = height - 1 - row;
+        }
+        // 8x16 sprites take the even tile, then the odd one below it.
+        let tile = if height == 16 { sprite.tile & 0xFE } else { sprite.tile };
+        let offset = tile as usize * 16 + row as usize * 2;
+        let colors = decode_row(vram[offset], vram[offset + 1]);
+        for column in 0..8 {
+            // X is offset by 8 so that sprites can be partly off the left
+            // edge.
+            let x = sprite.x as i16 - 8 + column as i16;
+            if !(0..SCREEN_WIDTH as i16).contains(&x) || self.fifo.sprites[x as usize].is_some() {
+                continue;
+            }
+            let color = colors[if sprite.x_flip() { 7 - column } else { column }];
+            // Color 0 is transparent.
+            if color != 0 {
+                self.fifo.sprites[x as usize] = Some(SpritePixel {
+                    color,
+                    obp1: sprite.attributes & 0x10 != 0,
+                    behind_background: sprite.behind_background(),
+                });
+            }
+        }
+    }
+
+    /// Draws every tile in `vram` into `buffer`, 16 to a row in the order
+    /// they are stored, as color numbers 0-3 with no palette applied.
+    ///
+    /// # Panics
+    ///
+    /// If `buffer` is not `TILE_SHEET_WIDTH * TILE_SHEET_HEIGHT` long.
+    pub fn debug_render_tiles(&self, vram: &[u8; 0x2000], buffer: &mut [u8]) {
+        assert_eq!(buffer.len(), TILE_SHEET_WIDTH * TILE_SHEET_HEIGHT, "a tile sheet is 128x192");
+        for (tile, data) in vram[..0x1800].chunks_exact(16).enumerate() {
+            let (left, top) = (tile % 16 * 8, tile / 16 * 8);
+            for (row, bytes) in data.chunks_exact(2).enumerate() {
+                let start = (top + row) * TILE_SHEET_WIDTH + left;
+                buffer[start..start + 8].copy_from_slice(&decode_row(bytes[0], bytes[1]));
+            }
+        }
+    }
+
+    /// Draws the whole 256x256 picture tile map `map` makes into `buffer`,
+    /// as shades, with the tile data LCDC selects and BGP. Returns the top
+    /// left corner of the part SCX and SCY put on screen, which wraps
+    /// around the right and bottom edges.
+    ///
+    /// # Panics
+    ///
+    /// If `buffer` is not `TILE_MAP_SIZE * TILE_MAP_SIZE` long.
+    pub fn debug_render_tilemap(&self, vram: &[u8; 0x2000], map: TileMap, buffer: &mut [u8]) -> (u8, u8) {
+        assert_eq!(buffer.len(), TILE_MAP_SIZE * TILE_MAP_SIZE, "a tile map picture is 256x256");
+        for (y, line) in buffer.chunks_exact_mut(TILE_MAP_SIZE).enumerate() {
+            for (column, pixels) in line.chunks_exact_mut(8).enumerate() {
+                let colors = self.map_row(vram, map.offset(), column as u8, y as u8);
+                for (pixel, color) in pixels.iter_mut().zip(colors) {
+                    *pixel = shade(self.bgp, color);
+                }
+            }
+        }
+        (self.scx, self.scy)
+    }
+
+    /// The 40 entries in `oam`, decoded the way the OAM scan reads them.
+    pub fn debug_sprites(&self, oam: &[u8; 0xA0]) -> [SpriteEntry; 40] {
+        let height = self.sprite_height();
+        std::array::from_fn(|entry| {
+            let sprite = Sprite::from_oam(&oam[entry * 4..entry * 4 + 4]);
+            SpriteEntry {
+                y: sprite.y,
+                x: sprite.x,
+                tile: sprite.tile,
+                palette: sprite.attributes >> 4 & 0x01,
+                x_flip: sprite.x_flip(),
+                y_flip: sprite.y_flip(),
+                behind_background: sprite.behind_background(),
+                height,
+            }
+        })
+    }
+
+    #[cfg(test)]
+    pub(crate) fn force_mode(&mut self, mode: Mode) {
+        self.mode = mode;
+    }
+}
+
+impl Default for Ppu {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Mode 3 at its shortest, with no scroll, window or sprites.
+    const DRAWING_DOTS: u16 = 172;
+
+    /// Runs the PPU from the top of a frame to the top of the next.
+    fn run_frame(ppu: &mut Ppu, vram: &[u8; 0x2000], oam: &[u8; 0xA0]) {
+        for _ in 0..DOTS_PER_FRAME / 4 {
+            ppu.tick(4, vram, oam);
+        }
+    }
+
+    /// Runs a frame like `run_frame`, returning the line and dot of each
+    /// request of `interrupt`.
+    fn interrupt_requests(ppu: &mut Ppu, interrupt: u8) -> Vec<(u8, u16)> {
+        let mut requests = Vec::new();
+        for _ in 0..DOTS_PER_FRAME {
+            if ppu.tick(1, &[0; 0x2000], &[0; 0xA0]) & interrupt != 0 {
+                requests.push((ppu.ly, ppu.dot));
+            }
+        }
+        requests
+    }
+
+    #[test]
+    fn test_decode_row() {
+        assert_eq!(decode_row(0xF0, 0xCC), [3, 3, 1, 1, 2, 2, 0, 0]);
+        assert_eq!(decode_row(0x81, 0x01), [1, 0, 0, 0, 0, 0, 0, 3]);
+    }
+
+    #[test]
+    fn test_render_background() {
+        let mut vram = [0; 0x2000];
+        // Tile 1 at 0x8010, every row colors 3 3 1 1 2 2 0 0.
+        for row in 0..8 {
+            vram[0x0010 + row * 2] = 0xF0;
+            vram[0x0011 + row * 2] = 0xCC;
+        }
+        vram[0x1800] = 0x01; // top left of the 0x9800 map
+        vram[0x1800 + 32 + 2] = 0x01; // second tile row, third column
+
+        let mut ppu = Ppu::new();
+        ppu.write(BGP, 0x1B); // inverted: shade 3 - color
+        ppu.write(LCDC, 0x91);
+        run_frame(&mut ppu, &vram, &[0; 0xA0]);
+
+        let mut expected = [3; SCREEN_WIDTH * SCREEN_HEIGHT];
+        for y in 0..8 {
+            expected[y * SCREEN_WIDTH..y * SCREEN_WIDTH + 8].copy_from_slice(&[0, 0, 2, 2, 1, 1, 3, 3]);
+            let start = (y + 8) * SCREEN_WIDTH + 16;
+            expected[start..start + 8].copy_from_slice(&[0, 0, 2, 2, 1, 1, 3, 3]);
+        }
+        assert_eq!(ppu.framebuffer()[..], expected[..]);
+
+        ppu.write(LCDC, 0x90);
+        run_frame(&mut ppu, &vram, &[0; 0xA0]);
+        assert!(ppu.framebuffer().iter().all(|&shade| shade == 0));
+    }
+
+    #[test]
+    fn test_palette_changes_between_frames() {
+        let mut vram = [0; 0x2000];
+        // Tile 0 fills the map, every row colors 3 3 1 1 2 2 0 0.
+        for row in 0..8 {
+            vram[row * 2] = 0xF0;
+            vram[row * 2 + 1] = 0xCC;
+        }
+        let mut ppu = Ppu::new();
+        ppu.write(BGP, 0xE4);
+        ppu.write(LCDC, 0x91);
+        run_frame(&mut ppu, &vram, &[0; 0xA0]);
+        assert!(ppu.framebuffer().chunks_exact(8).all(|pixels| pixels == [3, 3, 1, 1, 2, 2, 0, 0]));
+
+        ppu.write(BGP, 0x1B);
+        assert_eq!(ppu.read(BGP), 0x1B);
+        run_frame(&mut ppu, &vram, &[0; 0xA0]);
+        assert!(ppu.framebuffer().chunks_exact(8).all(|pixels| pixels == [0, 0, 2, 2, 1, 1, 3, 3]));
+    }
+
+    #[test]
+    fn test_palette_change_mid_frame() {
+        let mut vram = [0; 0x2000];
+        vram[..16].fill(0xFF); // tile 0, all color 3
+        let mut ppu = Ppu::new();
+        ppu.write(BGP, 0xC0);
+        ppu.write(LCDC, 0x91);
+        while ppu.ly() < 72 {
+            ppu.tick(4, &vram, &[0; 0xA0]);
+        }
+        ppu.write(BGP, 0x40);
+        while ppu.ly() != 0 {
+            ppu.tick(4, &vram, &[0; 0xA0]);
+        }
+
+        let (top, bottom) = ppu.framebuffer().split_at(72 * SCREEN_WIDTH);
+        assert!(top.iter().all(|&shade| shade == 3));
+        assert!(bottom.iter().all(|&shade| shade == 1));
+    }
+
+    #[test]
+    fn test_palette_change_mid_line() {
+        let mut vram = [0; 0x2000];
+        vram[..16].fill(0xFF); // tile 0, all color 3
+        let mut ppu = Ppu::new();
+        ppu.write(BGP, 0xC0);
+        ppu.write(LCDC, 0x91);
+        // Pixel 0 comes out on the 13th dot of mode 3, then one a dot.
+        ppu.tick(OAM_SCAN_DOTS as u8 + 12 + 80, &vram, &[0; 0xA0]);
+        ppu.write(BGP, 0x40);
+        ppu.tick(200, &vram, &[0; 0xA0]);
+
+        let line = &ppu.framebuffer()[..SCREEN_WIDTH];
+        assert_eq!(line[..80], [3; 80]);
+        assert_eq!(line[80..], [1; 80]);
+    }
+
+    #[test]
+    fn test_sprite_color_0_ignores_palette() {
+        let mut vram = [0; 0x2000];
+        vram[0x0010] = 0x0F; // tile 1, row 0: colors 0 0 0 0 1 1 1 1
+        let mut oam = [0; 0xA0];
+        oam[0..4].copy_from_slice(&[16, 8, 0x01, 0x00]);
+
+        let mut ppu = Ppu::new();
+        ppu.write(OBP0, 0xFF); // every color to shade 3
+        ppu.write(LCDC, 0x82);
+        run_frame(&mut ppu, &vram, &oam);
+        assert_eq!(ppu.read(OBP0), 0xFF);
+        assert_eq!(ppu.framebuffer()[..8], [0, 0, 0, 0, 3, 3, 3, 3]);
+    }
+
+    /// VRAM with tile 1 all color 3 and tile 2 all color 1, and an empty
+    /// map of tile 0.
+    fn scroll_vram() -> [u8; 0x2000] {
+        let mut vram = [0; 0x2000];
+        vram[0x0010..0x0020].fill(0xFF);
+        for row in 0..8 {
+            vram[0x0020 + row * 2] = 0xFF;
+        }
+        vram
+    }
+
+    #[test]
+    fn test_fine_scroll_x() {
+        let mut vram = scroll_vram();
+        vram[0x1800] = 0x01;
+        let mut ppu = Ppu::new();
+        ppu.write(BGP, 0xE4);
+        ppu.write(LCDC, 0x91);
+
+        // The first tile is entered 3 pixels in, leaving a 5 pixel sliver.
+        ppu.write(SCX, 3);
+        run_frame(&mut ppu, &vram, &[0; 0xA0]);
+        assert_eq!(ppu.framebuffer()[..8], [3, 3, 3, 3, 3, 0, 0, 0]);
+
+        // The last column of the map comes first, then the map wraps.
+        ppu.write(SCX, 255);
+        run_frame(&mut ppu, &vram, &[0; 0xA0]);
+        assert_eq!(ppu.framebuffer()[..10], [0, 3, 3, 3, 3, 3, 3, 3, 3, 0]);
+    }
+
+    #[test]
+    fn test_scroll_y_wraps() {
+        let mut vram = scroll_vram();
+        vram[0x1800] = 0x01; // map rows 0-7
+        vram[0x1800 + 17 * 32] = 0x02; // map rows 136-143
+        vram[0x1800 + 31 * 32] = 0x02; // map rows 248-255
+        let mut ppu = Ppu::new();
+        ppu.write(BGP, 0xE4);
+        ppu.write(SCY, 250);
+        ppu.write(LCDC, 0x91);
+        run_frame(&mut ppu, &vram, &[0; 0xA0]);
+
+        let column: Vec<u8> = ppu.framebuffer().chunks_exact(SCREEN_WIDTH).map(|line| line[0]).collect();
+        assert_eq!(column[..6], [1; 6]);
+        assert_eq!(column[6..14], [3; 8]);
+        assert_eq!(column[14..142], [0; 128]);
+        assert_eq!(column[142..], [1, 1]);
+    }
+
+    #[test]
+    fn test_scroll_between_frames() {
+        let mut vram = scroll_vram();
+        vram[0x1801] = 0x01;
+        let mut ppu = Ppu::new();
+        ppu.write(BGP, 0xE4);
+        ppu.write(LCDC, 0x91);
+        run_frame(&mut ppu, &vram, &[0; 0xA0]);
+        assert_eq!(ppu.framebuffer()[..16], [0, 0, 0, 0, 0, 0, 0, 0, 3, 3, 3, 3, 3, 3, 3, 3]);
+
+        ppu.write(SCX, 4);
+        run_frame(&mut ppu, &vram, &[0; 0xA0]);
+        assert_eq!(ppu.framebuffer()[..16], [0, 0, 0, 0, 3, 3, 3, 3, 3, 3, 3, 3, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn test_signed_tile_data_and_second_map() {
+        let mut vram = [0; 0x2000];
+        // Tile 0 in the 0x8800 area sits at 0x9000, tile 0x80 at 0x8800.
+        vram[0x1000] = 0xFF;
+        vram[0x0800] = 0x00;
+        vram[0x0801] = 0xFF;
+        vram[0x1C00] = 0x80;
+
+        let mut ppu = Ppu::new();
+        ppu.write(BGP, 0xE4);
+        ppu.write(LCDC, 0x89);
+        run_frame(&mut ppu, &vram, &[0; 0xA0]);
+
+        let framebuffer = ppu.framebuffer();
+        assert_eq!(framebuffer[0..8], [2; 8]);
+        assert_eq!(framebuffer[8..16], [1; 8]);
+        assert_eq!(framebuffer[SCREEN_WIDTH], 0);
+    }
+
+    #[test]
+    fn test_window_covers_bottom_third() {
+        let mut vram = [0; 0x2000];
+        // Tile 1: the left half color 3, the right half color 0.
+        for row in 0..8 {
+            vram[0x0010 + row * 2] = 0xF0;
+            vram[0x0011 + row * 2] = 0xF0;
+        }
+        vram[0x1C00..0x2000].fill(0x01);
+
+        let mut ppu = Ppu::new();
+        ppu.write(BGP, 0xE4);
+        ppu.write(WY, 96);
+        ppu.write(WX, 7);
+        ppu.write(LCDC, 0xF1);
+        run_frame(&mut ppu, &vram, &[0; 0xA0]);
+
+        let mut expected = [0; SCREEN_WIDTH * SCREEN_HEIGHT];
+        for (pixel, shade) in expected.iter_mut().enumerate().skip(96 * SCREEN_WIDTH) {
+            *shade = if pixel % 8 < 4 { 3 } else { 0 };
+        }
+        assert_eq!(ppu.framebuffer()[..], expected[..]);
+
+        // WX under 7 shifts the window left, into its first tile.
+        ppu.write(WX, 3);
+        run_frame(&mut ppu, &vram, &[0; 0xA0]);
+        assert_eq!(ppu.framebuffer()[96 * SCREEN_WIDTH..96 * SCREEN_WIDTH + 8], [0, 0, 0, 0, 3, 3, 3, 3]);
+
+        // The window starts part of the way across, and not at all past 166.
+        ppu.write(WX, 87);
+        run_frame(&mut ppu, &vram, &[0; 0xA0]);
+        assert_eq!(ppu.framebuffer()[96 * SCREEN_WIDTH + 76..96 * SCREEN_WIDTH + 84], [0, 0, 0, 0, 3, 3, 3, 3]);
+        ppu.write(WX, 167);
+        run_frame(&mut ppu, &vram, &[0; 0xA0]);
+        assert!(ppu.framebuffer().iter().all(|&shade| shade == 0));
+    }
+
+    #[test]
+    fn test_window_enabled_mid_frame() {
+        let mut vram = [0; 0x2000];
+        vram[0x0010..0x0020].fill(0xFF); // tile 1, all color 3
+        vram[0x1C00..0x2000].fill(0x01);
+
+        let mut ppu = Ppu::new();
+        ppu.write(BGP, 0xE4);
+        ppu.write(WX, 7);
+        ppu.write(LCDC, 0xD1);
+        for _ in 0..DOTS_PER_LINE as u32 * 50 / 4 {
+            ppu.tick(4, &vram, &[0; 0xA0]);
+        }
+        ppu.write(LCDC, 0xF1);
+        for _ in 0..DOTS_PER_LINE as u32 * 104 / 4 {
+            ppu.tick(4, &vram, &[0; 0xA0]);
+        }
+
+        assert_eq!(ppu.framebuffer()[49 * SCREEN_WIDTH], 0);
+        assert_eq!(ppu.framebuffer()[50 * SCREEN_WIDTH], 3);
+        assert_eq!(ppu.framebuffer()[143 * SCREEN_WIDTH + 159], 3);
+    }
+
+    #[test]
+    fn test_window_resumes_after_hidden_lines() {
+        let mut vram = [0; 0x2000];
+        // Every row of tiles 1-127 differs, and the window map uses them in
+        // turn. The background is all blank tile 0.
+        for (offset, byte) in vram[0x0010..0x0800].iter_mut().enumerate() {
+            *byte = (offset * 37 % 251) as u8;
+        }
+        for (entry, tile) in vram[0x1C00..0x2000].iter_mut().enumerate() {
+            *tile = 1 + (entry % 127) as u8;
+        }
+        let mut ppu = Ppu::new();
+        ppu.write(BGP, 0xE4);
+        ppu.write(WX, 7);
+        ppu.write(LCDC, 0xF1);
+        run_frame(&mut ppu, &vram, &[0; 0xA0]);
+        let shown = *ppu.framebuffer();
+
+        // Hide the window for lines 80-100 from the LYC interrupt.
+        ppu.write(LYC, 80);
+        ppu.write(STAT, 0x40);
+        for _ in 0..DOTS_PER_FRAME {
+            if ppu.tick(1, &vram, &[0; 0xA0]) & STAT_INTERRUPT != 0 {
+                if ppu.ly() == 80 {
+                    ppu.write(LCDC, 0xD1);
+                    ppu.write(LYC, 101);
+                } else {
+                    ppu.write(LCDC, 0xF1);
+                }
+            }
+        }
+
+        let line = |n: usize| n * SCREEN_WIDTH..(n + 1) * SCREEN_WIDTH;
+        let hidden = ppu.framebuffer();
+        assert_eq!(hidden[..80 * SCREEN_WIDTH], shown[..80 * SCREEN_WIDTH]);
+        assert!(hidden[80 * SCREEN_WIDTH..101 * SCREEN_WIDTH].iter().all(|&shade| shade == 0));
+        for n in 101..SCREEN_HEIGHT {
+            assert_eq!(hidden[line(n)], shown[line(n - 21)], "line {}", n);
+        }
+    }
+
+    #[test]
+    fn test_render_flipped_tall_sprite_at_edge() {
+        let mut vram = [0; 0x2000];
+        vram[0x0020] = 0x80; // tile 2, row 0: color 1 on the left
+        vram[0x003E] = 0xFF; // tile 3, row 7: colors 1 1 1 1 3 3 3 3
+        vram[0x003F] = 0x0F;
+        let mut oam = [0; 0xA0];
+        // Tile 3 rounds down to 2, with Y and X flipped and OBP1.
+        oam[0..4].copy_from_slice(&[16 + 10, 4, 0x03, 0x70]);
+        // Parked off screen, a solid tile, in each direction.
+        vram[0x0040..0x0060].fill(0xFF);
+        for (entry, (y, x)) in [(0, 50), (160, 50), (50, 0), (50, 168)].into_iter().enumerate() {
+            oam[4 + entry * 4..8 + entry * 4].copy_from_slice(&[y, x, 0x04, 0x00]);
+        }
+
+        let mut ppu = Ppu::new();
+        ppu.write(OBP0, 0xE4);
+        ppu.write(OBP1, 0x9C); // color 1 to shade 3, color 3 to shade 2
+        ppu.write(LCDC, 0x86);
+        run_frame(&mut ppu, &vram, &oam);
+
+        let mut expected = [0; SCREEN_WIDTH * SCREEN_HEIGHT];
+        expected[10 * SCREEN_WIDTH..10 * SCREEN_WIDTH + 4].copy_from_slice(&[3, 3, 3, 3]);
+        expected[25 * SCREEN_WIDTH + 3] = 3;
+        assert_eq!(ppu.framebuffer()[..], expected[..]);
+
+        // Sprites are off with LCDC bit 1 clear.
+        ppu.write(LCDC, 0x84);
+        run_frame(&mut ppu, &vram, &oam);
+        assert!(ppu.framebuffer().iter().all(|&shade| shade == 0));
+    }
+
+    #[test]
+    fn test_ten_sprites_per_line() {
+        let mut vram = [0; 0x2000];
+        vram[0x0010..0x0020].fill(0xFF); // tile 1, all color 3
+        let mut oam = [0; 0xA0];
+        for entry in 0..12 {
+            oam[entry * 4..entry * 4 + 4].copy_from_slice(&[16, 8 + entry as u8 * 8, 0x01, 0x00]);
+        }
+        // Off-screen sprites on the line still count towards the 10.
+        oam[0..4].copy_from_slice(&[16, 0, 0x01, 0x00]);
+
+        let mut ppu = Ppu::new();
+        ppu.write(OBP0, 0xE4);
+        ppu.write(LCDC, 0x82);
+        run_frame(&mut ppu, &vram, &oam);
+
+        let line = &ppu.framebuffer()[..SCREEN_WIDTH];
+        assert_eq!(line[..8], [0; 8]);
+        assert_eq!(line[8..80], [3; 72]);
+        assert_eq!(line[80..96], [0; 16]);
+    }
+
+    #[test]
+    fn test_sprite_priority() {
+        let mut vram = [0; 0x2000];
+        vram[0x0010] = 0xFF; // tile 1, row 0: color 1
+        vram[0x0021] = 0xFF; // tile 2, row 0: color 2
+        let mut oam = [0; 0xA0];
+        // The later entry wins where they overlap, having the smaller X.
+        oam[0..4].copy_from_slice(&[16, 20, 0x01, 0x00]);
+        oam[4..8].copy_from_slice(&[16, 16, 0x02, 0x00]);
+        // With the same X, the earlier entry wins.
+        oam[8..12].copy_from_slice(&[17, 40, 0x01, 0x00]);
+        oam[12..16].copy_from_slice(&[17, 40, 0x02, 0x00]);
+
+        let mut ppu = Ppu::new();
+        ppu.write(OBP0, 0xE4);
+        ppu.write(LCDC, 0x82);
+        run_frame(&mut ppu, &vram, &oam);
+
+        let framebuffer = ppu.framebuffer();
+        assert_eq!(framebuffer[8..20], [2, 2, 2, 2, 2, 2, 2, 2, 1, 1, 1, 1]);
+        assert_eq!(framebuffer[SCREEN_WIDTH + 32..SCREEN_WIDTH + 40], [1; 8]);
+    }
+
+    #[test]
+    fn test_sprite_behind_background() {
+        let mut vram = [0; 0x2000];
+        vram[0x0010..0x0020].fill(0xFF); // tile 1, all color 3
+        // Tile 2 alternates colors 0 and 2 on every row.
+        for row in 0..8 {
+            vram[0x0020 + row * 2 + 1] = 0x55;
+        }
+        vram[0x1800] = 0x02;
+        vram[0x1820] = 0x02;
+        let mut oam = [0; 0xA0];
+        oam[0..4].copy_from_slice(&[16, 8, 0x01, 0x80]);
+        // An earlier sprite behind the background still hides a later one
+        // in front of it.
+        oam[4..8].copy_from_slice(&[24, 8, 0x01, 0x80]);
+        oam[8..12].copy_from_slice(&[24, 8, 0x01, 0x00]);
+
+        let mut ppu = Ppu::new();
+        ppu.write(BGP, 0xE4);
+        ppu.write(OBP0, 0xE4);
+        ppu.write(LCDC, 0x93);
+        run_frame(&mut ppu, &vram, &oam);
+        assert_eq!(ppu.framebuffer()[..8], [3, 2, 3, 2, 3, 2, 3, 2]);
+        assert_eq!(ppu.framebuffer()[8 * SCREEN_WIDTH..8 * SCREEN_WIDTH + 8], [3, 2, 3, 2, 3, 2, 3, 2]);
+
+        // With the background off, sprites behind it show over white.
+        ppu.write(LCDC, 0x92);
+        run_frame(&mut ppu, &vram, &oam);
+        assert_eq!(ppu.framebuffer()[..8], [3; 8]);
+    }
+
+    #[test]
+    fn test_lcdc_sprite_enable() {
+        let mut vram = [0; 0x2000];
+        vram[0x0010..0x0020].fill(0xFF); // tile 1, all color 3
+        let mut oam = [0; 0xA0];
+        oam[0..4].copy_from_slice(&[16, 8, 0x01, 0x00]);
+        let mut ppu = Ppu::new();
+        ppu.write(OBP0, 0xE4);
+        ppu.write(LCDC, 0x82);
+        run_frame(&mut ppu, &vram, &oam);
+        assert_eq!(ppu.framebuffer()[..8], [3; 8]);
+
+        ppu.write(LCDC, 0x80);
+        run_frame(&mut ppu, &vram, &oam);
+        assert!(ppu.framebuffer().iter().all(|&shade| shade == 0));
+    }
+
+    #[test]
+    fn test_lcdc_background_map() {
+        let mut vram = [0; 0x2000];
+        vram[0x0010..0x0020].fill(0xFF); // tile 1, all color 3
+        vram[0x1C00] = 0x01; // only the 0x9C00 map uses it
+        let mut ppu = Ppu::new();
+        ppu.write(BGP, 0xE4);
+        ppu.write(LCDC, 0x91);
+        run_frame(&mut ppu, &vram, &[0; 0xA0]);
+        assert_eq!(ppu.framebuffer()[..8], [0; 8]);
+
+        ppu.write(LCDC, 0x99);
+        run_frame(&mut ppu, &vram, &[0; 0xA0]);
+        assert_eq!(ppu.framebuffer()[..9], [3, 3, 3, 3, 3, 3, 3, 3, 0]);
+    }
+
+    #[test]
+    fn test_lcd_disable_and_enable() {
+        let mut vram = [0; 0x2000];
+        vram[..16].fill(0xFF); // tile 0, all color 3
+        let mut ppu = Ppu::new();
+        ppu.write(BGP, 0xE4);
+        ppu.write(LCDC, 0x91);
+        run_frame(&mut ppu, &vram, &[0; 0xA0]);
+        for _ in 0..DOTS_PER_LINE * 50 / 4 {
+            ppu.tick(4, &vram, &[0; 0xA0]);
+        }
+        assert_eq!(ppu.read(LY), 50);
+
+        // Mid-frame, outside VBlank.
+        ppu.write(LCDC, 0x11);
+        assert_eq!((ppu.read(LY), ppu.mode()), (0, Mode::HBlank));
+        assert!(ppu.framebuffer().iter().all(|&shade| shade == 0));
+
+        ppu.write(LCDC, 0x91);
+        assert_eq!((ppu.read(LY), ppu.mode()), (0, Mode::OamScan));
+        for _ in 0..DOTS_PER_LINE / 4 {
+            ppu.tick(4, &vram, &[0; 0xA0]);
+        }
+        assert_eq!(ppu.read(LY), 1);
+        assert_eq!(ppu.framebuffer()[..SCREEN_WIDTH], [3; SCREEN_WIDTH]);
+    }
+
+    #[test]
+    fn test_sprite_size_change_after_scan() {
+        let mut vram = [0; 0x2000];
+        vram[..0x20].fill(0xFF); // tiles 0 and 1, all color 3
+        let mut oam = [0; 0xA0];
+        oam[0..4].copy_from_slice(&[8, 8, 0x00, 0x40]);
+        let mut ppu = Ppu::new();
+        ppu.write(OBP0, 0xE4);
+        ppu.write(LCDC, 0x86);
+        // Line 2 is row 10 of the 8x16 sprite, past the end of an 8x8 one.
+        for _ in 0..(DOTS_PER_LINE * 2 + OAM_SCAN_DOTS + 4) / 4 {
+            ppu.tick(4, &vram, &oam);
+        }
+        ppu.write(LCDC, 0x82);
+        ppu.tick(DRAWING_DOTS as u8, &vram, &oam);
+        assert_eq!(ppu.framebuffer()[2 * SCREEN_WIDTH..2 * SCREEN_WIDTH + 8], [0; 8]);
+    }
+
+    #[test]
+    fn test_stat_register() {
+        let mut ppu = Ppu::new();
+        let vram = [0; 0x2000];
+        ppu.write(LYC, 1);
+        ppu.write(LCDC, 0x80);
+
+        // Only the interrupt enables take the written value.
+        ppu.write(STAT, 0xFF);
+        assert_eq!(ppu.read(STAT), 0xFA);
+        ppu.tick(OAM_SCAN_DOTS as u8, &vram, &[0; 0xA0]);
+        assert_eq!(ppu.read(STAT), 0xFB);
+        ppu.tick(DRAWING_DOTS as u8, &vram, &[0; 0xA0]);
+        assert_eq!(ppu.read(STAT), 0xF8);
+        ppu.tick(204, &vram, &[0; 0xA0]);
+        assert_eq!(ppu.read(STAT), 0xFE);
+
+        ppu.write(STAT, 0x00);
+        assert_eq!(ppu.read(STAT), 0x86);
+        for value in 0..=0xFF {
+            ppu.write(STAT, value);
+            assert_eq!(ppu.read(STAT), 0x80 | value & 0x78 | 0x06, "wrote {:#04X}", value);
+        }
+    }
+
+    #[test]
+    fn test_lyc_interrupt() {
+        let mut ppu = Ppu::new();
+        ppu.write(LYC, 40);
+        ppu.write(STAT, 0x40);
+        ppu.write(LCDC, 0x80);
+        assert_eq!(interrupt_requests(&mut ppu, STAT_INTERRUPT), [(40, 0)]);
+    }
+
+    #[test]
+    fn test_mode_interrupts() {
+        let mut ppu = Ppu::new();
+        ppu.write(LCDC, 0x80);
+        // Enabling the source of the current mode raises the line at once.
+        ppu.write(STAT, 0x20);
+        assert_eq!(ppu.tick(0, &[0; 0x2000], &[0; 0xA0]), STAT_INTERRUPT);
+        let requests = interrupt_requests(&mut ppu, STAT_INTERRUPT);
+        assert_eq!(requests.len(), 144);
+        assert!(requests.iter().all(|&(_, dot)| dot == 0));
+
+        ppu.write(STAT, 0x08);
+        let requests = interrupt_requests(&mut ppu, STAT_INTERRUPT);
+        assert_eq!(requests.len(), 144);
+        assert!(requests.iter().all(|&(_, dot)| dot == OAM_SCAN_DOTS + DRAWING_DOTS));
+
+        ppu.write(STAT, 0x10);
+        assert_eq!(interrupt_requests(&mut ppu, STAT_INTERRUPT), [(144, 0)]);
+
+        ppu.write(STAT, 0x00);
+        assert_eq!(interrupt_requests(&mut ppu, STAT_INTERRUPT), []);
+    }
+
+    #[test]
+    fn test_lyc_write_compares() {
+        let mut ppu = Ppu::new();
+        let vram = [0; 0x2000];
+        ppu.write(LYC, 5);
+        ppu.write(STAT, 0x40);
+        ppu.write(LCDC, 0x80);
+        for _ in 0..(DOTS_PER_LINE * 3 + 100) / 4 {
+            ppu.tick(4, &vram, &[0; 0xA0]);
+        }
+        assert_eq!(ppu.tick(0, &vram, &[0; 0xA0]), 0);
+        assert_eq!(ppu.read(STAT) & 0x04, 0);
+
+        // Mid-line, on line 3.
+        ppu.write(LYC, 3);
+        assert_eq!(ppu.read(STAT) & 0x04, 0x04);
+        assert_eq!(ppu.tick(0, &vram, &[0; 0xA0]), STAT_INTERRUPT);
+
+        // Still matching: no new request.
+        ppu.write(LYC, 3);
+        assert_eq!(ppu.tick(0, &vram, &[0; 0xA0]), 0);
+
+        ppu.write(LYC, 4);
+        assert_eq!(ppu.read(STAT) & 0x04, 0);
+        assert_eq!(ppu.tick(0, &vram, &[0; 0xA0]), 0);
+    }
+
+    /// Counts the STAT requests over line 10, writing LYC the first time the
+    /// PPU reaches each mode in `writes`.
+    fn line_10_stat_requests(writes: &[(Mode, u8)]) -> usize {
+        let mut ppu = Ppu::new();
+        let vram = [0; 0x2000];
+        ppu.write(LYC, 100);
+        ppu.write(STAT, 0x48);
+        ppu.write(LCDC, 0x80);
+        while ppu.ly != 10 {
+            ppu.tick(1, &vram, &[0; 0xA0]);
+        }
+        let mut writes = writes.iter().peekable();
+        let mut requests = 0;
+        while ppu.ly == 10 {
+            if let Some(&&(mode, lyc)) = writes.peek() {
+                if ppu.mode == mode {
+                    ppu.write(LYC, lyc);
+                    writes.next();
+                }
+            }
+            if ppu.tick(1, &vram, &[0; 0xA0]) & STAT_INTERRUPT != 0 {
+                requests += 1;
+            }
+        }
+        requests
+    }
+
+    #[test]
+    fn test_stat_line_blocking() {
+        // LY matching LYC in HBlank finds the line already high.
+        assert_eq!(line_10_stat_requests(&[(Mode::HBlank, 10)]), 1);
+        // A match that is over by HBlank lets the line fall in between.
+        assert_eq!(line_10_stat_requests(&[(Mode::OamScan, 10), (Mode::Drawing, 100)]), 2);
+        // One that lasts into HBlank keeps it high.
+        assert_eq!(line_10_stat_requests(&[(Mode::OamScan, 10)]), 1);
+
+        // LY comes to match LYC while the previous line is still in HBlank,
+        // and the match holds the line through HBlank on line 11: the LYC
+        // request and that HBlank one are both lost.
+        let mut ppu = Ppu::new();
+        ppu.write(LYC, 11);
+        ppu.write(STAT, 0x48);
+        ppu.write(LCDC, 0x80);
+        let requests = interrupt_requests(&mut ppu, STAT_INTERRUPT);
+        assert_eq!(requests.len(), 143);
+        assert!(requests.iter().all(|&(ly, _)| ly != 11));
+    }
+
+    #[test]
+    fn test_coincidence_on_enable() {
+        let mut ppu = Ppu::new();
+        ppu.write(STAT, 0x40);
+        ppu.write(LCDC, 0x80);
+        assert_eq!(ppu.read(STAT) & 0x04, 0x04);
+        assert_eq!(ppu.tick(0, &[0; 0x2000], &[0; 0xA0]), STAT_INTERRUPT);
+    }
+
+    #[test]
+    fn test_lcd_off_requests_nothing() {
+        let mut ppu = Ppu::new();
+        ppu.write(STAT, 0x78);
+        ppu.write(LYC, 1);
+        assert_eq!(interrupt_requests(&mut ppu, 0xFF), []);
+        ppu.write(LYC, 0);
+        assert_eq!(interrupt_requests(&mut ppu, 0xFF), []);
+        assert_eq!(ppu.read(STAT) & 0x03, 0);
+        assert_eq!(ppu.read(LY), 0);
+    }
+
+    #[test]
+    fn test_vblank_interrupt() {
+        let mut ppu = Ppu::new();
+        ppu.write(LCDC, 0x80);
+        assert_eq!(interrupt_requests(&mut ppu, VBLANK_INTERRUPT), [(144, 0)]);
+        assert_eq!(interrupt_requests(&mut ppu, VBLANK_INTERRUPT), [(144, 0)]);
+    }
+
+    /// Switches the display on and returns how long mode 3 lasts on line 0.
+    fn drawing_dots(ppu: &mut Ppu, oam: &[u8; 0xA0]) -> u16 {
+        ppu.write(LCDC, ppu.lcdc | 0x80);
+        let mut dots = 0;
+        while ppu.mode() != Mode::HBlank {
+            ppu.tick(1, &[0; 0x2000], oam);
+            if ppu.mode() == Mode::Drawing {
+                dots += 1;
+            }
+        }
+        ppu.write(LCDC, ppu.lcdc & 0x7F);
+        dots
+    }
+
+    #[test]
+    fn test_drawing_length() {
+        let mut ppu = Ppu::new();
+        ppu.write(LCDC, 0x03);
+        assert_eq!(drawing_dots(&mut ppu, &[0; 0xA0]), DRAWING_DOTS);
+
+        // The pixels SCX % 8 drops from the first tile each take a dot.
+        ppu.write(SCX, 5);
+        assert_eq!(drawing_dots(&mut ppu, &[0; 0xA0]), DRAWING_DOTS + 5);
+        ppu.write(SCX, 0);
+
+        // Fetching the window from its first tile.
+        ppu.write(WX, 87);
+        ppu.write(LCDC, 0x23);
+        assert_eq!(drawing_dots(&mut ppu, &[0; 0xA0]), DRAWING_DOTS + 6);
+
+        // Each sprite takes 6 dots, and the fetcher must get 5 dots into a
+        // tile first: a whole 5 on the first sprite of an aligned tile.
+        ppu.write(LCDC, 0x03);
+        let mut oam = [0; 0xA0];
+        for entry in 0..10 {
+            oam[entry * 4..entry * 4 + 2].copy_from_slice(&[16, 8 + entry as u8 * 16]);
+        }
+        assert_eq!(drawing_dots(&mut ppu, &oam), DRAWING_DOTS + 10 * 11);
+        for entry in 0..10 {
+            oam[entry * 4 + 1] = 8;
+        }
+        assert_eq!(drawing_dots(&mut ppu, &oam), DRAWING_DOTS + 11 + 9 * 6);
+        for entry in 0..10 {
+            oam[entry * 4 + 1] = 8 + 5 + entry as u8 * 16;
+        }
+        assert_eq!(drawing_dots(&mut ppu, &oam), DRAWING_DOTS + 10 * 6);
+
+        // Sprites switched off cost nothing.
+        ppu.write(LCDC, 0x01);
+        assert_eq!(drawing_dots(&mut ppu, &oam), DRAWING_DOTS);
+    }
+
+    #[test]
+    fn test_frame_is_double_buffered() {
+        let mut vram = [0; 0x2000];
+        vram[..16].fill(0xFF); // tile 0, all color 3
+        let mut ppu = Ppu::new();
+        ppu.write(BGP, 0xE4);
+        ppu.write(LCDC, 0x91);
+        run_frame(&mut ppu, &vram, &[0; 0xA0]);
+        assert_eq!(ppu.frame_count(), 1);
+        assert!(ppu.frame().iter().all(|&shade| shade == 3));
+
+        // Halfway down the next frame only the one being drawn has changed.
+        ppu.write(BGP, 0x00);
+        for _ in 0..DOTS_PER_LINE * 72 / 4 {
+            ppu.tick(4, &vram, &[0; 0xA0]);
+        }
+        assert_eq!(ppu.framebuffer()[0], 0);
+        assert!(ppu.frame().iter().all(|&shade| shade == 3));
+        for _ in 0..DOTS_PER_LINE * 72 / 4 {
+            ppu.tick(4, &vram, &[0; 0xA0]);
+        }
+        assert_eq!(ppu.frame_count(), 2);
+        assert!(ppu.frame().iter().all(|&shade| shade == 0));
+    }
+
+    #[test]
+    fn test_frame_sink() {
+        use std::cell::RefCell;
+        use std::rc::Rc;
+
+        let mut vram = [0; 0x2000];
+        vram[..16].fill(0xFF); // tile 0, all color 3
+        let frames = Rc::new(RefCell::new(Vec::new()));
+        let sink_frames = Rc::clone(&frames);
+        let mut ppu = Ppu::new();
+        ppu.set_frame_sink(Some(Box::new(move |frame: &Frame| {
+            sink_frames.borrow_mut().push((frame.number, frame.pixels.iter().all(|&shade| shade == 3)));
+        })));
+        ppu.write(BGP, 0xE4);
+        ppu.write(LCDC, 0x91);
+        run_frame(&mut ppu, &vram, &[0; 0xA0]);
+        run_frame(&mut ppu, &vram, &[0; 0xA0]);
+        assert_eq!(*frames.borrow(), [(1, true), (2, true)]);
+
+        ppu.set_frame_sink(None);
+        run_frame(&mut ppu, &vram, &[0; 0xA0]);
+        assert_eq!(frames.borrow().len(), 2);
+        assert_eq!(Rc::strong_count(&frames), 1);
+    }
+
+    #[test]
+    fn test_debug_render_tiles() {
+        let mut vram = [0; 0x2000];
+        vram[0x0000..0x0002].copy_from_slice(&[0xF0, 0xCC]); // tile 0, row 0
+        vram[0x011E..0x0120].copy_from_slice(&[0x81, 0x01]); // tile 17, row 7
+        vram[0x17F0..0x1800].fill(0xFF); // tile 383
+        vram[0x1800..].fill(0xFF); // the maps are not tiles
+
+        let mut sheet = vec![0; TILE_SHEET_WIDTH * TILE_SHEET_HEIGHT];
+        Ppu::new().debug_render_tiles(&vram, &mut sheet);
+        assert_eq!(sheet[..8], [3, 3, 1, 1, 2, 2, 0, 0]);
+        let start = 15 * TILE_SHEET_WIDTH + 8;
+        assert_eq!(sheet[start..start + 8], [1, 0, 0, 0, 0, 0, 0, 3]);
+        let (rest, last_tile_row) = sheet.split_at(TILE_SHEET_WIDTH * (TILE_SHEET_HEIGHT - 8));
+        assert!(last_tile_row.chunks_exact(TILE_SHEET_WIDTH).all(|row| row[120..] == [3; 8] && row[..120] == [0; 120]));
+        assert_eq!(rest.iter().filter(|&&color| color != 0).count(), 8);
+    }
+
+    #[test]
+    fn test_debug_render_tilemap() {
+        let mut vram = [0; 0x2000];
+        // Tiles 1-3 all colors 1-3.
+        for row in 0..8 {
+            vram[0x0010 + row * 2] = 0xFF;
+            vram[0x0021 + row * 2] = 0xFF;
+        }
+        vram[0x0030..0x0040].fill(0xFF);
+        for (entry, tile) in vram[0x1C00..0x2000].iter_mut().enumerate() {
+            *tile = entry as u8;
+        }
+        let mut ppu = Ppu::new();
+        ppu.write(BGP, 0xE4);
+        ppu.write(SCX, 200);
+        ppu.write(SCY, 120);
+        let mut picture = vec![0; TILE_MAP_SIZE * TILE_MAP_SIZE];
+
+        // Tiles -128-127 from 0x9000, where there is nothing.
+        assert_eq!(ppu.debug_render_tilemap(&vram, TileMap::Map9C00, &mut picture), (200, 120));
+        assert!(picture.iter().all(|&shade| shade == 0));
+
+        ppu.write(LCDC, 0x10);
+        ppu.debug_render_tilemap(&vram, TileMap::Map9C00, &mut picture);
+        assert_eq!(picture[..32], [0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 1, 1, 1, 1, 2, 2, 2, 2, 2, 2, 2, 2, 3, 3, 3, 3, 3, 3, 3, 3]);
+        assert_eq!(picture[7 * TILE_MAP_SIZE + 31], 3);
+        assert!(picture[32..TILE_MAP_SIZE].iter().all(|&shade| shade == 0));
+        // Map rows 8 on, entries 256 on, start over from tile 0.
+        assert_eq!(picture[64 * TILE_MAP_SIZE..64 * TILE_MAP_SIZE + 32], picture[..32]);
+
+        ppu.write(BGP, 0x1B);
+        ppu.debug_render_tilemap(&vram, TileMap::Map9800, &mut picture);
+        assert!(picture.iter().all(|&shade| shade == 3));
+        assert_eq!(ppu.read(BGP), 0x1B);
+    }
+
+    #[test]
+    fn test_debug_sprites() {
+        let mut oam = [0; 0xA0];
+        oam[0..4].copy_from_slice(&[16, 8, 0x12, 0xF0]);
+        oam[4..8].copy_from_slice(&[40, 100, 0x35, 0x20]);
+        oam[8..12].copy_from_slice(&[8, 0, 0x01, 0x40]); // off the top and left
+        oam[156..160].copy_from_slice(&[160, 168, 0xFF, 0x10]); // off the bottom and right
+        let mut ppu = Ppu::new();
+        let sprites = ppu.debug_sprites(&oam);
+
+        assert_eq!(sprites[0], SpriteEntry { y: 16, x: 8, tile: 0x12, palette: 1, x_flip: true, y_flip: true, behind_background: true, height: 8 });
+        assert_eq!(sprites[1], SpriteEntry { y: 40, x: 100, tile: 0x35, palette: 0, x_flip: true, y_flip: false, behind_background: false, height: 8 });
+        assert_eq!((sprites[2].y_flip, sprites[2].x_flip, sprites[2].palette), (true, false, 0));
+        assert_eq!((sprites[39].tile, sprites[39].palette), (0xFF, 1));
+        assert_eq!(sprites[20], SpriteEntry { y: 0, x: 0, tile: 0, palette: 0, x_flip: false, y_flip: false, behind_background: false, height: 8 });
+
+        assert!(sprites[0].visible_on_line(0) && sprites[0].visible_on_line(7) && !sprites[0].visible_on_line(8));
+        assert!(!sprites[1].visible_on_line(23) && sprites[1].visible_on_line(24));
+        assert!(!(0..=255).any(|line| sprites[2].visible_on_line(line)));
+        assert!(!(0..=255).any(|line| sprites[39].visible_on_line(line)));
+
+        ppu.write(LCDC, 0x04);
+        let sprites = ppu.debug_sprites(&oam);
+        assert_eq!(sprites[0].height, 16);
+        assert!(sprites[0].visible_on_line(15) && !sprites[0].visible_on_line(16));
+    }
+
+    #[test]
+    fn test_hblank_interrupt_waits_for_drawing() {
+        let mut oam = [0; 0xA0];
+        for entry in 0..10 {
+            oam[entry * 4..entry * 4 + 2].copy_from_slice(&[16, 8 + entry as u8 * 16]);
+        }
+        // (OAM, SCX, HBlank start)
+        let cases = [
+            ([0; 0xA0], 0, OAM_SCAN_DOTS + DRAWING_DOTS),
+            ([0; 0xA0], 3, OAM_SCAN_DOTS + DRAWING_DOTS + 3),
+            (oam, 0, OAM_SCAN_DOTS + DRAWING_DOTS + 10 * 11),
+        ];
+        for (oam, scx, start) in cases {
+            let mut ppu = Ppu::new();
+            ppu.write(SCX, scx);
+            ppu.write(STAT, 0x08);
+            ppu.write(LCDC, 0x83);
+            let mut dot = 0;
+            while ppu.tick(1, &[0; 0x2000], &oam) & STAT_INTERRUPT == 0 {
+                dot += 1;
+            }
+            assert_eq!(dot + 1, start, "SCX {}", scx);
+        }
+    }
+
+    #[test]
+    fn test_frame_timing() {
+        let mut ppu = Ppu::new();
+        ppu.write(LCDC, 0x80);
+        assert_eq!((ppu.ly(), ppu.mode()), (0, Mode::OamScan));
+
+        let mut line_modes = vec![vec![ppu.mode()]];
+        for _ in 0..DOTS_PER_FRAME {
+            let ly = ppu.ly();
+            ppu.tick(1, &[0; 0x2000], &[0; 0xA0]);
+            if ppu.ly() != ly {
+                line_modes.push(Vec::new());
+            }
+            let modes = line_modes.last_mut().unwrap();
+            if modes.last() != Some(&ppu.mode()) {
+                modes.push(ppu.mode());
+            }
+        }
+
+        assert_eq!((ppu.ly(), ppu.mode()), (0, Mode::OamScan));
+        assert_eq!(line_modes.len(), LINES_PER_FRAME as usize + 1);
+        for (line, modes) in line_modes[..LINES_PER_FRAME as usize].iter().enumerate() {
+            let expected = if line < VBLANK_LINE as usize {
+                vec![Mode::OamScan, Mode::Drawing, Mode::HBlank]
+            } else {
+                vec![Mode::VBlank]
+            };
+            assert_eq!(*modes, expected, "line {}", line);
+        }
+    }
+
+    #[test]
+    fn test_mode_boundaries_and_registers() {
+        let mut ppu = Ppu::new();
+        let vram = [0; 0x2000];
+        ppu.write(LCDC, 0x80);
+        ppu.tick(OAM_SCAN_DOTS as u8 - 1, &vram, &[0; 0xA0]);
+        assert_eq!(ppu.read(STAT) & 0x03, 2);
+        ppu.tick(1, &vram, &[0; 0xA0]);
+        assert_eq!(ppu.read(STAT) & 0x03, 3);
+        ppu.tick(DRAWING_DOTS as u8, &vram, &[0; 0xA0]);
+        assert_eq!(ppu.read(STAT) & 0x03, 0);
+
+        for _ in 0..(DOTS_PER_LINE * 143 + 204) / 4 {
+            ppu.tick(4, &vram, &[0; 0xA0]);
+        }
+        assert_eq!(ppu.read(LY), 144);
+        assert_eq!(ppu.read(STAT) & 0x03, 1);
+
+        // LY is read-only, and nothing runs with the display off.
+        ppu.write(LY, 0x12);
+        assert_eq!(ppu.read(LY), 144);
+        ppu.write(LCDC, 0x00);
+        ppu.tick(200, &vram, &[0; 0xA0]);
+        assert_eq!((ppu.read(LY), ppu.read(STAT) & 0x03), (0, 0));
+    }
+}
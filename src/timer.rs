@@ -0,0 +1,338 @@
+use crate::memory::io::{DIV, TAC, TIMA, TMA};
+
+/// The IF bit the timer requests when TIMA overflows.
+pub const TIMER_INTERRUPT: u8 = 0x04;
+
+/// How far TIMA is through reloading from TMA after an overflow, with the
+/// T-cycles left in each step.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Reload {
+    Idle,
+    /// TIMA reads 0 for the cycle after the overflow. Writing it now
+    /// cancels the reload, interrupt included.
+    Delayed(u8),
+    /// The cycle TMA is copied in. TIMA ignores writes, while TMA writes go
+    /// through to TIMA as well.
+    Reloading(u8),
+}
+
+/// The timer registers at 0xFF04-0xFF07, all driven by one 16-bit counter
+/// that counts T-cycles.
+pub struct Timer {
+    /// DIV is its upper byte, so it steps every 256 T-cycles: 16384 Hz.
+    counter: u16,
+    tima: u8,
+    tma: u8,
+    /// Bit 2 enables TIMA, bits 0-1 select its rate.
+    tac: u8,
+    reload: Reload,
+    /// Interrupts requested and not yet handed to the bus, as IF bits.
+    interrupts: u8,
+}
+
+impl Timer {
+    pub fn new() -> Timer {
+        Timer {
+            counter: 0,
+            tima: 0,
+            tma: 0,
+            tac: 0,
+            reload: Reload::Idle,
+            interrupts: 0,
+        }
+    }
+
+    /// Unused TAC bits read back as 1.
+    pub fn read(&self, address: u16) -> u8 {
+        match address {
+            DIV => (self.counter >> 8) as u8,
+            TIMA => self.tima,
+            TMA => self.tma,
+            TAC => 0xF8 | self.tac,
+            _ => 0xFF,
+        }
+    }
+
+    pub fn write(&mut self, address: u16, value: u8) {
+        match address {
+            // Any write resets the whole counter, not just the byte seen.
+            DIV => self.set_counter(0),
+            TAC => {
+                let input = self.input();
+                self.tac = value & 0x07;
+                self.step_on_fall(input);
+            }
+            TIMA => match self.reload {
+                Reload::Idle => self.tima = value,
+                Reload::Delayed(_) => {
+                    self.tima = value;
+                    self.reload = Reload::Idle;
+                }
+                Reload::Reloading(_) => {},
+            },
+            TMA => {
+                self.tma = value;
+                if let Reload::Reloading(_) = self.reload {
+                    self.tima = value;
+                }
+            }
+            _ => self.load_register(address, value),
+        }
+    }
+
+    /// Sets a register as is, bypassing the write handlers.
+    pub(crate) fn load_register(&mut self, address: u16, value: u8) {
+        match address {
+            DIV => self.counter = (value as u16) << 8,
+            TIMA => self.tima = value,
+            TMA => self.tma = value,
+            TAC => self.tac = value & 0x07,
+            _ => {},
+        }
+    }
+
+    /// Advances the counter by `cycles` T-cycles, returning the interrupts
+    /// requested meanwhile as IF bits.
+    pub fn tick(&mut self, cycles: u8) -> u8 {
+        for _ in 0..cycles {
+            self.reload = match self.reload {
+                Reload::Delayed(1) => {
+                    self.tima = self.tma;
+                    self.interrupts |= TIMER_INTERRUPT;
+                    Reload::Reloading(4)
+                }
+                Reload::Delayed(left) => Reload::Delayed(left - 1),
+                Reload::Reloading(1) | Reload::Idle => Reload::Idle,
+                Reload::Reloading(left) => Reload::Reloading(left - 1),
+            };
+            self.set_counter(self.counter.wrapping_add(1));
+        }
+        std::mem::take(&mut self.interrupts)
+    }
+
+    /// The counter bit TAC selects, ANDed with the enable bit. TIMA steps
+    /// when this falls: every 1024, 16, 64 or 256 T-cycles for rates 0-3.
+    fn input(&self) -> bool {
+        let bit = match self.tac & 0x03 {
+            0 => 9,
+            1 => 3,
+            2 => 5,
+            _ => 7,
+        };
+        self.tac & 0x04 != 0 && self.counter & (1 << bit) != 0
+    }
+
+    fn set_counter(&mut self, counter: u16) {
+        let input = self.input();
+        self.counter = counter;
+        self.step_on_fall(input);
+    }
+
+    /// Steps TIMA if the input was high and no longer is. Besides the counter
+    /// moving on, resetting DIV or changing TAC can make it fall. An overflow
+    /// leaves TIMA at 0, the reload and interrupt coming 4 T-cycles later.
+    fn step_on_fall(&mut self, input: bool) {
+        if !input || self.input() {
+            return;
+        }
+        let (tima, overflow) = self.tima.overflowing_add(1);
+        self.tima = tima;
+        if overflow {
+            self.reload = Reload::Delayed(4);
+        }
+    }
+}
+
+impl Default for Timer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Ticks 4 cycles at a time, as the CPU does, and returns the interrupts
+    /// requested.
+    fn tick_cycles(timer: &mut Timer, cycles: u32) -> u8 {
+        let mut requests = 0;
+        for _ in 0..cycles / 4 {
+            requests |= timer.tick(4);
+        }
+        requests
+    }
+
+    #[test]
+    fn test_div_period() {
+        let mut timer = Timer::new();
+        tick_cycles(&mut timer, 252);
+        timer.tick(3);
+        assert_eq!(timer.read(DIV), 0);
+        timer.tick(1);
+        assert_eq!(timer.read(DIV), 1);
+
+        tick_cycles(&mut timer, 256 * 254);
+        assert_eq!(timer.read(DIV), 0xFF);
+        tick_cycles(&mut timer, 256);
+        assert_eq!(timer.read(DIV), 0x00);
+    }
+
+    #[test]
+    fn test_div_write_resets_counter() {
+        let mut timer = Timer::new();
+        tick_cycles(&mut timer, 256 * 3 + 200);
+        assert_eq!(timer.read(DIV), 3);
+
+        timer.write(DIV, 0x42);
+        assert_eq!(timer.read(DIV), 0);
+        // The 200 cycles into the period are lost too.
+        tick_cycles(&mut timer, 252);
+        timer.tick(3);
+        assert_eq!(timer.read(DIV), 0);
+        timer.tick(1);
+        assert_eq!(timer.read(DIV), 1);
+    }
+
+    #[test]
+    fn test_tima_periods() {
+        let cases = [(0x04, 1024), (0x05, 16), (0x06, 64), (0x07, 256)];
+        for (tac, period) in cases {
+            let mut timer = Timer::new();
+            timer.write(TAC, tac);
+            tick_cycles(&mut timer, period - 4);
+            timer.tick(3);
+            assert_eq!(timer.read(TIMA), 0, "TAC {:02X}", tac);
+            timer.tick(1);
+            assert_eq!(timer.read(TIMA), 1, "TAC {:02X}", tac);
+            tick_cycles(&mut timer, period * 9);
+            assert_eq!(timer.read(TIMA), 10, "TAC {:02X}", tac);
+        }
+    }
+
+    #[test]
+    fn test_tima_overflow_reloads_tma() {
+        let mut timer = Timer::new();
+        timer.write(TMA, 0xF0);
+        timer.write(TIMA, 0xFE);
+        timer.write(TAC, 0x05);
+        assert_eq!(tick_cycles(&mut timer, 16), 0);
+        assert_eq!(timer.read(TIMA), 0xFF);
+
+        assert_eq!(tick_cycles(&mut timer, 16), 0);
+        assert_eq!(timer.read(TIMA), 0x00);
+        assert_eq!(timer.tick(4), TIMER_INTERRUPT);
+        assert_eq!(timer.read(TIMA), 0xF0);
+        assert_eq!(tick_cycles(&mut timer, 12), 0);
+        assert_eq!(timer.read(TIMA), 0xF1);
+    }
+
+    #[test]
+    fn test_disabled_timer_stops() {
+        let mut timer = Timer::new();
+        timer.write(TAC, 0x01);
+        assert_eq!(tick_cycles(&mut timer, 4096), 0);
+        assert_eq!(timer.read(TIMA), 0);
+        assert_eq!(timer.read(TAC), 0xF9);
+
+        timer.write(TAC, 0x05);
+        tick_cycles(&mut timer, 32);
+        timer.write(TAC, 0x01);
+        tick_cycles(&mut timer, 4096);
+        assert_eq!(timer.read(TIMA), 2);
+    }
+
+    #[test]
+    fn test_div_write_can_step_tima() {
+        let mut timer = Timer::new();
+        timer.write(TAC, 0x05);
+        tick_cycles(&mut timer, 8);
+        // Bit 3 of the counter is set: resetting it is a falling edge.
+        timer.write(DIV, 0);
+        assert_eq!(timer.read(TIMA), 1);
+        tick_cycles(&mut timer, 4);
+        timer.write(DIV, 0);
+        assert_eq!(timer.read(TIMA), 1);
+    }
+
+    /// A timer at rate 1 whose TIMA overflowed on the last cycle ticked,
+    /// with TMA set to 0x23.
+    fn overflowed_timer() -> Timer {
+        let mut timer = Timer::new();
+        timer.write(TAC, 0x05);
+        timer.write(TIMA, 0xFF);
+        timer.write(TMA, 0x23);
+        assert_eq!(tick_cycles(&mut timer, 16), 0);
+        timer
+    }
+
+    // After Mooneye's timer/tima_reload.
+    #[test]
+    fn test_tima_reload() {
+        let mut timer = overflowed_timer();
+        for cycle in 0..3 {
+            assert_eq!(timer.read(TIMA), 0x00, "cycle {}", cycle);
+            assert_eq!(timer.tick(1), 0, "cycle {}", cycle);
+        }
+        assert_eq!(timer.read(TIMA), 0x00);
+        assert_eq!(timer.tick(1), TIMER_INTERRUPT);
+        assert_eq!(timer.read(TIMA), 0x23);
+
+        // Counting goes on at the same rate.
+        assert_eq!(tick_cycles(&mut timer, 12), 0);
+        assert_eq!(timer.read(TIMA), 0x24);
+    }
+
+    // After Mooneye's timer/tima_write_reloading.
+    #[test]
+    fn test_tima_write_reloading() {
+        // Written while it reads 0, TIMA keeps the value and the reload is
+        // off, interrupt and all.
+        let mut timer = overflowed_timer();
+        timer.tick(2);
+        timer.write(TIMA, 0x55);
+        assert_eq!(tick_cycles(&mut timer, 8), 0);
+        assert_eq!(timer.read(TIMA), 0x55);
+
+        // Written on the reload cycle, TMA wins.
+        let mut timer = overflowed_timer();
+        assert_eq!(timer.tick(4), TIMER_INTERRUPT);
+        timer.write(TIMA, 0x55);
+        assert_eq!(timer.read(TIMA), 0x23);
+        timer.tick(3);
+        timer.write(TIMA, 0x55);
+        assert_eq!(timer.read(TIMA), 0x23);
+
+        // Past it, writes land again.
+        timer.tick(1);
+        timer.write(TIMA, 0x55);
+        assert_eq!(timer.read(TIMA), 0x55);
+    }
+
+    // After Mooneye's timer/tma_write_reloading.
+    #[test]
+    fn test_tma_write_reloading() {
+        // Written before the reload, TMA is what gets loaded.
+        let mut timer = overflowed_timer();
+        timer.tick(2);
+        timer.write(TMA, 0x66);
+        assert_eq!(timer.read(TIMA), 0x00);
+        assert_eq!(timer.tick(2), TIMER_INTERRUPT);
+        assert_eq!(timer.read(TIMA), 0x66);
+
+        // Written on the reload cycle, it goes through to TIMA.
+        let mut timer = overflowed_timer();
+        timer.tick(4);
+        timer.write(TMA, 0x66);
+        assert_eq!(timer.read(TIMA), 0x66);
+        timer.tick(3);
+        timer.write(TMA, 0x77);
+        assert_eq!(timer.read(TIMA), 0x77);
+
+        // Past it, TIMA is left alone.
+        timer.tick(1);
+        timer.write(TMA, 0x88);
+        assert_eq!(timer.read(TIMA), 0x77);
+        assert_eq!(timer.read(TMA), 0x88);
+    }
+}
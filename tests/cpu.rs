@@ -0,0 +1,579 @@
+use std::cell::RefCell;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::rc::Rc;
+
+use game_boy_dmg_emulator::cpu::Cpu;
+use game_boy_dmg_emulator::cartridge::{self, RomError};
+use game_boy_dmg_emulator::emulator::{Config, Emulator, LogoCheck, Palette};
+
+/// A 32 KiB ROM holding `program` at `address`.
+fn rom_with_program(address: usize, program: &[u8]) -> Vec<u8> {
+    let mut rom = vec![0; 0x8000];
+    rom[address..address + program.len()].copy_from_slice(program);
+    rom
+}
+
+#[test]
+fn test_program_through_public_api() {
+    let mut cpu = Cpu::new();
+    let program = [
+        0x31, 0xFE, 0xFF, // LD SP, 0xFFFE
+        0x01, 0x34, 0x12, // LD BC, 0x1234
+        0x21, 0x00, 0xC0, // LD HL, 0xC000
+        0x3E, 0x0F, // LD A, 0x0F
+        0x3C, // INC A
+        0x77, // LD (HL), A
+        0xC5, // PUSH BC
+        0xD1, // POP DE
+    ];
+    cpu.load_rom(rom_with_program(0x0000, &program)).unwrap();
+
+    let cycles: u32 = (0..8).map(|_| cpu.step().unwrap() as u32).sum();
+    assert_eq!(cycles, 12 + 12 + 12 + 8 + 4 + 8 + 16 + 12);
+    assert_eq!(cpu.pc(), program.len() as u16);
+    assert_eq!(cpu.sp(), 0xFFFE);
+    assert_eq!(cpu.reg_a(), 0x10);
+    assert_eq!(cpu.reg_f(), 0x20);
+    assert_eq!(cpu.reg_bc(), 0x1234);
+    assert_eq!(cpu.reg_de(), 0x1234);
+    assert_eq!((cpu.reg_h(), cpu.reg_l()), (0xC0, 0x00));
+    assert_eq!(cpu.read_mem(0xC000), 0x10);
+}
+
+#[test]
+fn test_emulator_starts_at_cartridge_entry_point() {
+    let path = std::env::temp_dir().join("entry_point.gb");
+    std::fs::write(&path, rom_with_program(0x0100, &[0x04])).unwrap(); // INC B
+
+    let mut emulator = Emulator::new();
+    emulator.load_rom(path.to_str().unwrap()).unwrap();
+    assert_eq!(emulator.cpu().pc(), 0x0100);
+    assert_eq!(emulator.cpu().reg_af(), 0x01B0);
+
+    assert_eq!(emulator.step(), Ok(4));
+    assert_eq!(emulator.cpu().reg_b(), 0x01);
+    assert_eq!(emulator.read_mem(0x0100), 0x04);
+
+    emulator.write_mem(0xC000, 0x5A);
+    assert_eq!(emulator.read_mem(0xC000), 0x5A);
+}
+
+#[test]
+fn test_rumble_callback_sees_motor_edges() {
+    let program = [
+        0x3E, 0x08, // LD A, 0x08
+        0xEA, 0x00, 0x40, // LD (0x4000), A
+        0xEA, 0x00, 0x40, // LD (0x4000), A
+        0x3E, 0x00, // LD A, 0x00
+        0xEA, 0x00, 0x40, // LD (0x4000), A
+    ];
+    let mut rom = rom_with_program(0x0000, &program);
+    rom[0x0147] = 0x1C; // MBC5+RUMBLE
+
+    let edges = Rc::new(RefCell::new(Vec::new()));
+    let mut cpu = Cpu::new();
+    let seen = edges.clone();
+    cpu.set_rumble_callback(Box::new(move |on| seen.borrow_mut().push(on)));
+    cpu.load_rom(rom).unwrap();
+    for _ in 0..5 {
+        cpu.step().unwrap();
+    }
+    assert_eq!(*edges.borrow(), vec![true, false]);
+}
+
+#[test]
+fn test_battery_ram_round_trips_through_save_file() {
+    let rom_path = std::env::temp_dir().join("battery_save.gb");
+    let save_path = rom_path.with_extension("sav");
+    let _ = std::fs::remove_file(&save_path);
+    let mut rom = vec![0; 0x8000];
+    rom[0x0147] = 0x03; // MBC1+RAM+BATTERY
+    rom[0x0149] = 0x03; // 32 KiB
+    std::fs::write(&rom_path, &rom).unwrap();
+
+    let mut emulator = Emulator::new();
+    emulator.load_rom(rom_path.to_str().unwrap()).unwrap();
+    assert_eq!(emulator.header().cartridge_type, 0x03);
+    assert_eq!(emulator.header().ram_size_bytes(), 0x8000);
+    emulator.write_mem(0x0000, 0x0A); // enable RAM
+    emulator.write_mem(0x6000, 0x01); // RAM banking mode
+    emulator.write_mem(0x4000, 0x02);
+    emulator.write_mem(0xA123, 0x5A);
+    emulator.flush_ram().unwrap();
+    assert_eq!(std::fs::read(&save_path).unwrap().len(), 0x8000);
+    drop(emulator);
+
+    let mut emulator = Emulator::new();
+    emulator.load_rom(rom_path.to_str().unwrap()).unwrap();
+    emulator.write_mem(0x0000, 0x0A);
+    emulator.write_mem(0x6000, 0x01);
+    assert_eq!(emulator.read_mem(0xA123), 0x00);
+    emulator.write_mem(0x4000, 0x02);
+    assert_eq!(emulator.read_mem(0xA123), 0x5A);
+
+    // A short save is padded out to the RAM size.
+    std::fs::write(&save_path, [0x11]).unwrap();
+    emulator.load_ram(&save_path).unwrap();
+    emulator.write_mem(0x4000, 0x00);
+    assert_eq!(emulator.read_mem(0xA000), 0x11);
+    assert_eq!(emulator.read_mem(0xA001), 0x00);
+}
+
+#[test]
+fn test_battery_without_ram_writes_no_save() {
+    let rom_path = std::env::temp_dir().join("battery_no_ram.gb");
+    let save_path = rom_path.with_extension("sav");
+    std::fs::write(&save_path, [0; 48]).unwrap();
+    let mut rom = vec![0; 0x8000];
+    rom[0x0147] = 0x0F; // MBC3+TIMER+BATTERY
+    std::fs::write(&rom_path, &rom).unwrap();
+
+    // There is no RAM to fill from, or write back to, a clock-only save.
+    let mut emulator = Emulator::new();
+    emulator.load_rom(rom_path.to_str().unwrap()).unwrap();
+    std::fs::remove_file(&save_path).unwrap();
+    emulator.flush_ram().unwrap();
+    emulator.save_ram(&save_path).unwrap();
+    drop(emulator);
+    assert!(!save_path.exists());
+}
+
+#[test]
+fn test_cartridge_without_battery_writes_no_save() {
+    let rom_path = std::env::temp_dir().join("no_battery.gb");
+    let save_path = rom_path.with_extension("sav");
+    let _ = std::fs::remove_file(&save_path);
+    let mut rom = vec![0; 0x8000];
+    rom[0x0147] = 0x02; // MBC1+RAM
+    rom[0x0149] = 0x02;
+    std::fs::write(&rom_path, &rom).unwrap();
+
+    let mut emulator = Emulator::new();
+    emulator.load_rom(rom_path.to_str().unwrap()).unwrap();
+    emulator.write_mem(0x0000, 0x0A);
+    emulator.write_mem(0xA000, 0x5A);
+    emulator.flush_ram().unwrap();
+    emulator.save_ram(&save_path).unwrap();
+    drop(emulator);
+    assert!(!save_path.exists());
+}
+
+#[test]
+fn test_strict_mode_refuses_bad_header_checksum() {
+    let path = std::env::temp_dir().join("bad_checksum.gb");
+    let mut rom = vec![0; 0x8000];
+    rom[0x0134..0x0138].copy_from_slice(b"GOOD");
+    rom[0x014D] = cartridge::header_checksum(&rom);
+    std::fs::write(&path, &rom).unwrap();
+
+    let mut emulator = Emulator::with_config(Config { strict_header_checksum: true, ..Config::default() });
+    assert_eq!(emulator.load_rom(path.to_str().unwrap()), Ok(()));
+    assert!(emulator.header().header_checksum_ok);
+
+    rom[0x0134] ^= 0x01; // "FOOD"
+    std::fs::write(&path, &rom).unwrap();
+    let expected = rom[0x014D];
+    let error = RomError::BadHeaderChecksum { expected, computed: cartridge::header_checksum(&rom) };
+    assert_eq!(emulator.load_rom(path.to_str().unwrap()), Err(error));
+
+    // Without strict mode the game still runs.
+    let mut emulator = Emulator::new();
+    assert_eq!(emulator.load_rom(path.to_str().unwrap()), Ok(()));
+    assert!(!emulator.header().header_checksum_ok);
+}
+
+#[test]
+fn test_logo_verification() {
+    let path = std::env::temp_dir().join("logo.gb");
+    let mut rom = vec![0; 0x8000];
+    rom[0x0104..0x0134].copy_from_slice(&cartridge::NINTENDO_LOGO);
+    std::fs::write(&path, &rom).unwrap();
+
+    let strict = Config { verify_logo: LogoCheck::Strict, ..Config::default() };
+    let mut emulator = Emulator::with_config(strict.clone());
+    assert_eq!(emulator.load_rom(path.to_str().unwrap()), Ok(()));
+    assert!(emulator.header().logo_ok);
+
+    rom[0x0110] ^= 0x01;
+    std::fs::write(&path, &rom).unwrap();
+    let mut emulator = Emulator::with_config(strict);
+    assert_eq!(emulator.load_rom(path.to_str().unwrap()), Err(RomError::BadLogo));
+
+    let mut emulator = Emulator::with_config(Config { verify_logo: LogoCheck::Warn, ..Config::default() });
+    assert_eq!(emulator.load_rom(path.to_str().unwrap()), Ok(()));
+    assert!(!emulator.header().logo_ok);
+    assert_eq!(emulator.cpu().pc(), 0x0100);
+}
+
+#[test]
+fn test_load_rom_checks_size() {
+    let mut cpu = Cpu::new();
+    assert_eq!(cpu.load_rom(vec![0; 0x8000]), Ok(()));
+
+    let mut rom = vec![0; 0x100000];
+    rom[0x0147] = 0x01; // MBC1
+    rom[0xFC000] = 0x3E;
+    assert_eq!(cpu.load_rom(rom), Ok(()));
+    cpu.write_mem(0x2000, 0x1F);
+    cpu.write_mem(0x4000, 0x01); // bank 0x3F, the last one
+    assert_eq!(cpu.read_mem(0x4000), 0x3E);
+
+    let path = std::env::temp_dir().join("seven_bytes.gb");
+    std::fs::write(&path, [0; 7]).unwrap();
+    let mut emulator = Emulator::new();
+    let error = emulator.load_rom(path.to_str().unwrap()).unwrap_err();
+    assert_eq!(error, RomError::BadSize { size: 7 });
+    assert!(error.to_string().contains("7 bytes"));
+}
+
+#[test]
+fn test_load_rom_reports_bad_path() {
+    let path = std::env::temp_dir().join("no_such_dir").join("missing.gb");
+    let mut emulator = Emulator::new();
+    let error = emulator.load_rom(path.to_str().unwrap()).unwrap_err();
+    assert_eq!(error, RomError::Io { path: path.clone(), kind: std::io::ErrorKind::NotFound });
+    assert!(error.to_string().contains("missing.gb"));
+}
+
+#[test]
+fn test_headless_run_from_rom_bytes() {
+    let program = [
+        0x21, 0x00, 0xC0, // LD HL, 0xC000
+        0x06, 0x00, // LD B, 0x00
+        0x04, // loop: INC B
+        0x78, // LD A, B
+        0x22, // LD (HL+), A
+        0x7C, // LD A, H
+        0xFE, 0xC1, // CP 0xC1
+        0x20, 0xF8, // JR NZ, loop
+        0x18, 0xFE, // JR -2
+    ];
+    let mut rom = rom_with_program(0x0150, &program);
+    rom[0x0100..0x0104].copy_from_slice(&[0x00, 0xC3, 0x50, 0x01]); // NOP ; JP 0x0150
+    rom[0x0104..0x0134].copy_from_slice(&cartridge::NINTENDO_LOGO);
+    rom[0x0134..0x013C].copy_from_slice(b"HEADLESS");
+    rom[0x014D] = cartridge::header_checksum(&rom);
+
+    let config = Config { strict_header_checksum: true, verify_logo: LogoCheck::Strict, ..Config::default() };
+    let mut emulator = Emulator::with_config(config);
+    assert_eq!(emulator.load_rom_bytes(&rom), Ok(()));
+    assert_eq!(emulator.header().title, "HEADLESS");
+    for _ in 0..3000 {
+        emulator.step().unwrap();
+    }
+
+    assert_eq!(emulator.cpu().pc(), 0x015D);
+    assert_eq!(emulator.read_mem(0xC000), 0x01);
+    assert_eq!(emulator.read_mem(0xC0FE), 0xFF);
+    assert_eq!(emulator.read_mem(0xC0FF), 0x00);
+    assert_eq!(emulator.cpu().reg_hl(), 0xC100);
+}
+
+fn rom_hash(cpu: &Cpu) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    cpu.cartridge().rom().hash(&mut hasher);
+    hasher.finish()
+}
+
+#[test]
+fn test_banking_never_writes_the_rom_image() {
+    let program = [
+        0x21, 0x00, 0xC0, // LD HL, 0xC000
+        0x06, 0x01, // LD B, 0x01
+        0x78, // loop: LD A, B
+        0xEA, 0x00, 0x20, // LD (0x2000), A
+        0xFA, 0x00, 0x40, // LD A, (0x4000)
+        0x22, // LD (HL+), A
+        0x04, // INC B
+        0x78, // LD A, B
+        0xFE, 0x08, // CP 0x08
+        0x20, 0xF2, // JR NZ, loop
+        0x3E, 0xFF, // LD A, 0xFF
+        0xEA, 0x00, 0x00, // LD (0x0000), A
+        0xEA, 0x00, 0x60, // LD (0x6000), A
+        0xEA, 0xFF, 0x7F, // LD (0x7FFF), A
+    ];
+    for cartridge_type in [0x00, 0x01] {
+        let mut rom = vec![0; 0x20000];
+        rom[..program.len()].copy_from_slice(&program);
+        for bank in 1..8 {
+            rom[bank * 0x4000] = 0x80 | bank as u8;
+        }
+        rom[0x0147] = cartridge_type;
+
+        let mut cpu = Cpu::new();
+        cpu.load_rom(rom).unwrap();
+        let before = rom_hash(&cpu);
+        for _ in 0..2 + 7 * 8 + 4 {
+            cpu.step().unwrap();
+        }
+        assert_eq!(cpu.pc(), program.len() as u16);
+        assert_eq!(rom_hash(&cpu), before, "type {:02X}", cartridge_type);
+
+        let banks: Vec<u8> = (0xC000..0xC007).map(|address| cpu.read_mem(address)).collect();
+        if cartridge_type == 0x01 {
+            assert_eq!(banks, [0x81, 0x82, 0x83, 0x84, 0x85, 0x86, 0x87]);
+        } else {
+            assert_eq!(banks, [0x81; 7]);
+        }
+    }
+}
+
+#[test]
+fn test_frames_through_public_api() {
+    let mut emulator = Emulator::with_config(Config { unrestricted_vram_oam: true, ..Config::default() });
+    emulator.load_rom_bytes(&rom_with_program(0x0100, &[0x18, 0xFE])).unwrap(); // JR -2
+    // Tile 0, which fills the map, all color 3. BGP is 0xFC after boot.
+    for address in 0x8000..0x8010 {
+        emulator.write_mem(address, 0xFF);
+    }
+    emulator.set_palette(Palette([0x000000, 0x000000, 0x000000, 0x112233]));
+
+    let mut frames = 0;
+    while frames < 2 {
+        let count = emulator.frame_count();
+        emulator.step().unwrap();
+        if emulator.frame_count() != count {
+            frames += 1;
+        }
+    }
+    assert_eq!(emulator.frame_count(), 2);
+    assert!(emulator.frame().iter().all(|&shade| shade == 3));
+    let mut rgba = vec![0; 160 * 144 * 4];
+    emulator.frame_rgba(&mut rgba);
+    assert!(rgba.chunks_exact(4).all(|pixel| pixel == [0x11, 0x22, 0x33, 0xFF]));
+}
+
+#[test]
+fn test_loading_a_second_rom_restarts() {
+    let mut emulator = Emulator::new();
+    let program = [
+        0x3E, 0x42, // LD A, 0x42
+        0xEA, 0x00, 0xC0, // LD (0xC000), A
+        0xEA, 0xFF, 0xFF, // LD (IE), A
+        0x18, 0xFE, // JR -2
+    ];
+    emulator.load_rom_bytes(&rom_with_program(0x0100, &program)).unwrap();
+    for _ in 0..4 {
+        emulator.step().unwrap();
+    }
+    assert_eq!(emulator.read_mem(0xC000), 0x42);
+    assert_eq!(emulator.read_mem(0xFFFF), 0x42);
+    assert_eq!(emulator.cpu().pc(), 0x0108);
+
+    emulator.load_rom_bytes(&rom_with_program(0x0100, &[0x18, 0xFE])).unwrap(); // JR -2
+    assert_eq!(emulator.cpu().pc(), 0x0100);
+    assert_eq!(emulator.read_mem(0xC000), 0x00);
+    assert_eq!(emulator.read_mem(0xFFFF), 0x00);
+}
+
+#[test]
+fn test_homebrew_rom_draws_its_tile() {
+    let mut emulator = Emulator::new();
+    emulator.load_rom_bytes(include_bytes!("data/hello.gb")).unwrap();
+    while emulator.frame_count() < 3 {
+        emulator.step().unwrap();
+    }
+    // The smiley's top row, in the top left tile; BGP is 0xFC after boot.
+    assert_eq!(emulator.frame()[..8], [0, 0, 3, 3, 3, 3, 0, 0]);
+    assert_eq!(emulator.frame()[160 * 2..160 * 2 + 8], [3, 0, 3, 0, 0, 3, 0, 3]);
+    assert!(emulator.frame()[8..160].iter().all(|&shade| shade == 0));
+}
+
+#[test]
+fn test_frame_ppm_holds_the_stripes() {
+    let mut emulator = Emulator::with_config(Config { unrestricted_vram_oam: true, ..Config::default() });
+    emulator.load_rom_bytes(&rom_with_program(0x0100, &[0x18, 0xFE])).unwrap(); // JR -2
+    emulator.write_mem(0xFF47, 0xE4); // BGP: each color its own shade
+    // Tile 0, which fills the map: stripes of colors 0-3 two pixels wide,
+    // reversed in the bottom half.
+    for row in 0..8 {
+        let (low, high) = if row < 4 { (0x33, 0x0F) } else { (0xCC, 0xF0) };
+        emulator.write_mem(0x8000 + row * 2, low);
+        emulator.write_mem(0x8001 + row * 2, high);
+    }
+    while emulator.frame_count() < 2 {
+        emulator.step().unwrap();
+    }
+
+    // Built from the pattern above and the grayscale levels, not the PPU.
+    let gray = [[0xFF; 3], [0xAA; 3], [0x55; 3], [0x00; 3]];
+    let mut expected = b"P6\n160 144\n255\n".to_vec();
+    for y in 0..144 {
+        let stripes = if y % 8 < 4 { [0, 0, 1, 1, 2, 2, 3, 3] } else { [3, 3, 2, 2, 1, 1, 0, 0] };
+        for x in 0..160 {
+            expected.extend_from_slice(&gray[stripes[x % 8]]);
+        }
+    }
+    let ppm = emulator.frame_ppm();
+    assert_eq!(ppm.len(), expected.len());
+    if let Some(offset) = ppm.iter().zip(&expected).position(|(byte, expected)| byte != expected) {
+        panic!("PPM differs at byte {}", offset);
+    }
+}
+
+#[test]
+fn test_frame_sink_over_one_second() {
+    let mut emulator = Emulator::new();
+    emulator.load_rom_bytes(&rom_with_program(0x0100, &[0x18, 0xFE])).unwrap(); // JR -2
+    let numbers = Rc::new(RefCell::new(Vec::new()));
+    let sink_numbers = Rc::clone(&numbers);
+    emulator.set_frame_sink(Box::new(move |frame| sink_numbers.borrow_mut().push(frame.number)));
+
+    let mut cycles = 0u32;
+    while cycles < 4_194_304 {
+        cycles += emulator.step().unwrap() as u32;
+    }
+    // The first VBlank comes 144 lines in, then one every 59.7th of a
+    // second.
+    assert_eq!(*numbers.borrow(), (1..=59).collect::<Vec<u64>>());
+
+    emulator.remove_frame_sink();
+    emulator.step().unwrap();
+    assert_eq!(Rc::strong_count(&numbers), 1);
+}
+
+#[test]
+fn test_frame_sink_survives_reset() {
+    let mut emulator = Emulator::new();
+    let numbers = Rc::new(RefCell::new(Vec::new()));
+    let sink_numbers = Rc::clone(&numbers);
+    emulator.set_frame_sink(Box::new(move |frame| sink_numbers.borrow_mut().push(frame.number)));
+    // Loading restarts the machine too.
+    emulator.load_rom_bytes(&rom_with_program(0x0100, &[0x18, 0xFE])).unwrap(); // JR -2
+    for _ in 0..100 {
+        emulator.step().unwrap();
+    }
+
+    emulator.reset();
+    while emulator.frame_count() == 0 {
+        emulator.step().unwrap();
+    }
+    assert_eq!(*numbers.borrow(), [1]);
+}
+
+#[test]
+fn test_palette_changes_only_the_colors() {
+    let config = Config { unrestricted_vram_oam: true, palette: Palette::DMG_GREEN, ..Config::default() };
+    let mut emulator = Emulator::with_config(config);
+    emulator.load_rom_bytes(&rom_with_program(0x0100, &[0x18, 0xFE])).unwrap(); // JR -2
+    // Tile 0's rows go through colors 3 3 2 2 1 1 0 0, with BGP mapping
+    // each color to the same shade.
+    for address in (0x8000..0x8010).step_by(2) {
+        emulator.write_mem(address, 0xCC);
+        emulator.write_mem(address + 1, 0xF0);
+    }
+    emulator.write_mem(0xFF47, 0xE4);
+    while emulator.frame_count() == 0 {
+        emulator.step().unwrap();
+    }
+
+    let frame = *emulator.frame();
+    let mut green = vec![0; 160 * 144 * 4];
+    emulator.frame_rgba(&mut green);
+    emulator.set_palette(Palette::POCKET);
+    let mut pocket = vec![0; 160 * 144 * 4];
+    emulator.frame_rgba(&mut pocket);
+
+    assert_eq!(*emulator.frame(), frame);
+    assert_eq!(frame[..8], [3, 3, 2, 2, 1, 1, 0, 0]);
+    assert_eq!(green[..4], [0x0F, 0x38, 0x0F, 0xFF]);
+    assert_eq!(pocket[..4], [0x1F, 0x1F, 0x1F, 0xFF]);
+    for ((green, pocket), &shade) in green.chunks_exact(4).zip(pocket.chunks_exact(4)).zip(frame.iter()) {
+        assert_eq!(green, Palette::DMG_GREEN.rgba(shade));
+        assert_eq!(pocket, Palette::POCKET.rgba(shade));
+    }
+}
+
+#[test]
+fn test_debug_sprites_see_oam_dma() {
+    let mut emulator = Emulator::new();
+    emulator.load_rom_bytes(&rom_with_program(0x0100, &[0x18, 0xFE])).unwrap(); // JR -2
+    for (offset, &byte) in [32, 20, 0x07, 0x30].iter().enumerate() {
+        emulator.write_mem(0xC004 + offset as u16, byte);
+    }
+    emulator.write_mem(0xFF46, 0xC0);
+
+    let sprite = emulator.debug_sprites()[1];
+    assert_eq!((sprite.y, sprite.x, sprite.tile, sprite.palette, sprite.x_flip), (32, 20, 0x07, 1, true));
+    assert!(sprite.visible_on_line(16));
+}
+
+#[test]
+fn test_scroll_change_from_lyc_handler() {
+    let program = [
+        0x3E, 72, // LD A, 72
+        0xE0, 0x45, // LDH (LYC), A
+        0x3E, 0x40, // LD A, 0x40
+        0xE0, 0x41, // LDH (STAT), A: the LYC source
+        0x3E, 0x02, // LD A, 0x02
+        0xE0, 0xFF, // LDH (IE), A: STAT
+        0xFB, // EI
+        0x18, 0xFE, // JR -2
+    ];
+    let mut rom = rom_with_program(0x0100, &program);
+    rom[0x0048..0x004D].copy_from_slice(&[0x3E, 0x04, 0xE0, 0x43, 0xD9]); // LD A, 4 ; LDH (SCX), A ; RETI
+
+    let mut emulator = Emulator::with_config(Config { unrestricted_vram_oam: true, ..Config::default() });
+    emulator.load_rom_bytes(&rom).unwrap();
+    // Tile 1, all color 3, down the second column of the map.
+    for address in 0x8010..0x8020 {
+        emulator.write_mem(address, 0xFF);
+    }
+    for row in 0..32 {
+        emulator.write_mem(0x9801 + row * 32, 0x01);
+    }
+    while emulator.frame_count() == 0 {
+        emulator.step().unwrap();
+    }
+
+    for (line, pixels) in emulator.frame().chunks_exact(160).enumerate() {
+        let column = if line < 72 { 8 } else { 4 };
+        assert_eq!(pixels[column..column + 8], [3; 8], "line {}", line);
+        assert_eq!(pixels.iter().filter(|&&shade| shade != 0).count(), 8, "line {}", line);
+    }
+}
+
+/// Runs a program that counts its turns round a STAT polling loop while
+/// line 1 is in mode 3, with `oam` in OAM, and returns the count.
+fn mode_3_polls(oam: &[u8; 0xA0]) -> u8 {
+    let program = [
+        0x3E, 0x97, // LD A, 0x97
+        0xE0, 0x40, // LDH (LCDC), A: 8x16 sprites on
+        0xF0, 0x44, // line: LDH A, (LY)
+        0xFE, 0x01, // CP 1
+        0x20, 0xFA, // JR NZ, line
+        0xF0, 0x41, // mode_3: LDH A, (STAT)
+        0xE6, 0x03, // AND 3
+        0xFE, 0x03, // CP 3
+        0x20, 0xF8, // JR NZ, mode_3
+        0x06, 0x00, // LD B, 0
+        0x04, // count: INC B
+        0xF0, 0x41, // LDH A, (STAT)
+        0xE6, 0x03, // AND 3
+        0x20, 0xF9, // JR NZ, count
+        0x18, 0xFE, // JR -2
+    ];
+    let mut emulator = Emulator::with_config(Config { unrestricted_vram_oam: true, ..Config::default() });
+    emulator.load_rom_bytes(&rom_with_program(0x0100, &program)).unwrap();
+    for (offset, &byte) in oam.iter().enumerate() {
+        emulator.write_mem(0xFE00 + offset as u16, byte);
+    }
+    while emulator.cpu().pc() != 0x011B {
+        emulator.step().unwrap();
+    }
+    emulator.cpu().reg_b()
+}
+
+#[test]
+fn test_sprites_delay_hblank() {
+    let mut oam = [0; 0xA0];
+    for sprite in 0..10 {
+        oam[sprite * 4..sprite * 4 + 2].copy_from_slice(&[16, 8 + sprite as u8 * 16]);
+    }
+    let without = mode_3_polls(&[0; 0xA0]);
+    let with = mode_3_polls(&oam);
+    // 110 more dots, at 36 dots a turn.
+    assert!(with >= without + 2, "{} turns without sprites, {} with", without, with);
+}